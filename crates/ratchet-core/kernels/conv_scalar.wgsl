@@ -32,12 +32,12 @@ var<uniform> metadata: Meta;
 const MAX_FILTER_ELEM: u32 = 4096u;
 var<workgroup> F: array<f32, MAX_FILTER_ELEM>;
 
-fn inner(input_index: u32, filter_index: u32, output_index: u32, bias_index: u32, start: u32, end: u32) {
+fn inner(batch_offset: u32, input_index: u32, filter_index: u32, output_index: u32, bias_index: u32, start: u32, end: u32) {
     var inp = vec3<f32>(0f);
     var kernel = vec3<f32>(0f);
     var acc = vec3<f32>(0f); 
     for(var i = 0u; i < metadata.Cin; i++) {
-        let input_start = input_index + (i * metadata.Lin) - metadata.padding; //-1 is for padding
+        let input_start = batch_offset + input_index + (i * metadata.Lin) - metadata.padding; //-1 is for padding
         //We only populate the input between the provided indices, used for padding
         for(var j = start; j <= end; j++) {
             inp[j] = X[input_start + j];
@@ -69,7 +69,8 @@ fn load_filters_into_smem(local_id: vec3<u32>, filter_index: u32) {
 @compute @workgroup_size(256,1,1)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>,
         @builtin(local_invocation_id) local_id: vec3<u32>,
-        @builtin(workgroup_id) workgroup_id: vec3<u32>) {
+        @builtin(workgroup_id) workgroup_id: vec3<u32>,
+        @builtin(num_workgroups) num_workgroups: vec3<u32>) {
 
     let input_index = (workgroup_id.x * 256u + local_id.x) * metadata.stride;
     let filter_index = (workgroup_id.y * metadata.F_numel);
@@ -82,15 +83,18 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>,
         return;
     }
 
-    let output_index = (workgroup_id.x * 256u + local_id.x) + (workgroup_id.y * metadata.Lout);
+    //Each z workgroup handles a single batch element, Cout == num_workgroups.y
+    let batch_offset = workgroup_id.z * metadata.Cin * metadata.Lin;
+    let output_batch_offset = workgroup_id.z * num_workgroups.y * metadata.Lout;
+    let output_index = output_batch_offset + (workgroup_id.x * 256u + local_id.x) + (workgroup_id.y * metadata.Lout);
     let bias_index = workgroup_id.y;
 
     if input_index == metadata.Lin - metadata.padding {
-        inner(input_index, filter_index, output_index, bias_index, 0u, 1u);
+        inner(batch_offset, input_index, filter_index, output_index, bias_index, 0u, 1u);
     } else if input_index == 0u {
-        inner(input_index, filter_index, output_index, bias_index, 1u, 2u);
+        inner(batch_offset, input_index, filter_index, output_index, bias_index, 1u, 2u);
     } else {
-        inner(input_index, filter_index, output_index, bias_index, 0u, 2u);
+        inner(batch_offset, input_index, filter_index, output_index, bias_index, 0u, 2u);
     }
 }
 
@@ -80,12 +80,12 @@ impl MetaOperation for Conv {
 
     fn calculate_dispatch(&self, _dst: &Tensor) -> Result<WorkgroupCount, OperationError> {
         let input = &self.input;
-        let [_N, Cin, Lin]: [usize; 3] = input.shape().try_into()?;
+        let [N, Cin, Lin]: [usize; 3] = input.shape().try_into()?;
         let [Cout, _, KS]: [usize; 3] = self.weight.shape().try_into()?;
         let _F_numel = Cin * KS;
         let padded_strided_Lin = (Lin + 2 * self.padding) / self.stride;
         let wgcx = WorkgroupCount::div_ceil(padded_strided_Lin, 256);
-        Ok(wgc![wgcx as _, Cout as _, 1])
+        Ok(wgc![wgcx as _, Cout as _, N as _])
     }
 
     fn storage_bind_group_layout(
@@ -151,12 +151,13 @@ def conv(input, filters, bias, stride, padding):
 
     fn run_conv_trial(device: &Device, problem: ConvProblem) {
         let ConvProblem {
+            N,
             Cin,
             Lin,
             Cout,
             stride,
         } = problem;
-        let input = Tensor::randn::<f32>(shape![1, Cin, Lin], Device::CPU);
+        let input = Tensor::randn::<f32>(shape![N, Cin, Lin], Device::CPU);
         let weight = Tensor::randn::<f32>(shape![Cout, Cin, 3], Device::CPU);
         let bias = Tensor::randn::<f32>(shape![Cout], Device::CPU);
         let ground = ground_truth(&input, &weight, &bias, stride, 1).unwrap();
@@ -178,6 +179,8 @@ def conv(input, filters, bias, stride, padding):
 
     #[derive(Arbitrary, Debug)]
     struct ConvProblem {
+        #[strategy(1..=2usize)]
+        N: usize,
         #[strategy(16..=1024usize)]
         Cin: usize,
         #[strategy(16..=1024usize)]
@@ -193,14 +196,15 @@ def conv(input, filters, bias, stride, padding):
     fn test_conv(prob: ConvProblem) {
         let device = Device::request_device(DeviceRequest::GPU).unwrap();
         let ConvProblem {
+            N,
             Cin,
             Lin,
             Cout,
             stride,
         } = prob;
         println!(
-            "Cin = {}, Lin = {}, Cout = {}, stride = {}",
-            Cin, Lin, Cout, stride
+            "N = {}, Cin = {}, Lin = {}, Cout = {}, stride = {}",
+            N, Cin, Lin, Cout, stride
         );
         run_conv_trial(&device, prob);
     }
@@ -11,7 +11,7 @@ use std::{
 
 use crate::{GgmlDType, LoadError};
 
-trait ReadBytesCustom: ReadBytesExt {
+pub(crate) trait ReadBytesCustom: ReadBytesExt {
     /// Extends to read an exact number of bytes.
     fn read_bytes_with_len(&mut self, len: usize) -> std::io::Result<Vec<u8>> {
         let mut buf: Vec<MaybeUninit<u8>> = Vec::with_capacity(len);
@@ -89,7 +89,7 @@ pub struct TensorHeader {
 }
 
 impl TensorHeader {
-    fn new(name: String, shape: Shape, dtype: GgmlDType, start_offset: u64) -> Self {
+    pub(crate) fn new(name: String, shape: Shape, dtype: GgmlDType, start_offset: u64) -> Self {
         let numel = shape.numel();
         Self {
             name,
@@ -0,0 +1,249 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use half::f16;
+use ratchet::{DType, Device, Quantization, Tensor};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io::{BufRead, Seek},
+};
+
+use crate::ggml::ReadBytesCustom;
+use crate::{k_quants, GgmlDType, LoadError, TensorHeader};
+
+/// "GGUF", little endian.
+pub const MAGIC_GGUF: u32 = 0x46554747;
+pub const GGUF_DEFAULT_ALIGNMENT: u64 = 32;
+
+/// A metadata value of a GGUF file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GgufValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<GgufValue>),
+}
+
+impl GgufValue {
+    fn read<R: BufRead>(reader: &mut R, value_type: u32) -> Result<Self, LoadError> {
+        let value = match value_type {
+            0 => Self::U8(reader.read_u8()?),
+            1 => Self::I8(reader.read_i8()?),
+            2 => Self::U16(reader.read_u16::<LittleEndian>()?),
+            3 => Self::I16(reader.read_i16::<LittleEndian>()?),
+            4 => Self::U32(reader.read_u32::<LittleEndian>()?),
+            5 => Self::I32(reader.read_i32::<LittleEndian>()?),
+            6 => Self::F32(reader.read_f32::<LittleEndian>()?),
+            7 => Self::Bool(reader.read_u8()? != 0),
+            8 => Self::String(read_string(reader)?),
+            9 => {
+                let item_type = reader.read_u32::<LittleEndian>()?;
+                let len = reader.read_u64::<LittleEndian>()?;
+                let items = (0..len)
+                    .map(|_| Self::read(reader, item_type))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Self::Array(items)
+            }
+            10 => Self::U64(reader.read_u64::<LittleEndian>()?),
+            11 => Self::I64(reader.read_i64::<LittleEndian>()?),
+            12 => Self::F64(reader.read_f64::<LittleEndian>()?),
+            _ => {
+                return Err(LoadError::InvariantBroken(format!(
+                    "unknown GGUF value type {}",
+                    value_type
+                )))
+            }
+        };
+        Ok(value)
+    }
+
+    /// Any integer value that fits in a `u64`.
+    pub fn to_u64(&self) -> Option<u64> {
+        match *self {
+            Self::U8(v) => Some(v as u64),
+            Self::U16(v) => Some(v as u64),
+            Self::U32(v) => Some(v as u64),
+            Self::U64(v) => Some(v),
+            Self::I8(v) => v.try_into().ok(),
+            Self::I16(v) => v.try_into().ok(),
+            Self::I32(v) => v.try_into().ok(),
+            Self::I64(v) => v.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn to_f32(&self) -> Option<f32> {
+        match *self {
+            Self::F32(v) => Some(v),
+            Self::F64(v) => Some(v as f32),
+            _ => self.to_u64().map(|v| v as f32),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[GgufValue]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+fn read_string<R: BufRead>(reader: &mut R) -> Result<String, LoadError> {
+    let len = reader.read_u64::<LittleEndian>()?;
+    Ok(String::from_utf8(
+        reader.read_bytes_with_len(len.try_into()?)?,
+    )?)
+}
+
+/// A GGUF file: its metadata & the location of each tensor.
+///
+/// Tensors are read on demand by [GGUFModel::load_tensor]. F16, Q4_0 & Q8_0 tensors are
+/// dequantized to F32 on load, [GGUFModel::quantization] tells which to pack again.
+#[derive(Debug)]
+pub struct GGUFModel {
+    pub version: u32,
+    pub metadata: HashMap<String, GgufValue>,
+    pub tensors: HashMap<String, TensorHeader>,
+    pub total_bytes_loaded: Cell<usize>,
+}
+
+impl GGUFModel {
+    /// Reads the header & tensor infos, supports versions 2 & 3.
+    pub fn read<R: BufRead + Seek>(reader: &mut R) -> Result<Self, LoadError> {
+        reader.seek(std::io::SeekFrom::Start(0))?;
+        let magic = reader.read_u32::<LittleEndian>()?;
+        if magic != MAGIC_GGUF {
+            return Err(LoadError::InvalidFormat(magic));
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if !(2..=3).contains(&version) {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let n_tensors = reader.read_u64::<LittleEndian>()?;
+        let n_metadata = reader.read_u64::<LittleEndian>()?;
+
+        let mut metadata = HashMap::new();
+        for _ in 0..n_metadata {
+            let key = read_string(reader)?;
+            let value_type = reader.read_u32::<LittleEndian>()?;
+            metadata.insert(key, GgufValue::read(reader, value_type)?);
+        }
+
+        let mut infos = Vec::with_capacity(n_tensors.try_into()?);
+        for _ in 0..n_tensors {
+            let name = read_string(reader)?;
+            let n_dims = reader.read_u32::<LittleEndian>()?;
+            let mut dims = (0..n_dims)
+                .map(|_| Ok(reader.read_u64::<LittleEndian>()?.try_into()?))
+                .collect::<Result<Vec<usize>, LoadError>>()?;
+            //GGML dimensions are ordered from the fastest varying
+            dims.reverse();
+            let dtype = reader.read_u32::<LittleEndian>()?;
+            let dtype = GgmlDType::try_from(dtype).map_err(|_| LoadError::UnsupportedDType {
+                name: name.clone(),
+                dtype,
+            })?;
+            let offset = reader.read_u64::<LittleEndian>()?;
+            infos.push((name, dims, dtype, offset));
+        }
+
+        let alignment = metadata
+            .get("general.alignment")
+            .and_then(GgufValue::to_u64)
+            .unwrap_or(GGUF_DEFAULT_ALIGNMENT);
+        let position = reader.stream_position()?;
+        let data_start = position.div_ceil(alignment) * alignment;
+
+        let tensors = infos
+            .into_iter()
+            .map(|(name, dims, dtype, offset)| {
+                let header =
+                    TensorHeader::new(name.clone(), dims.into(), dtype, data_start + offset);
+                (name, header)
+            })
+            .collect::<HashMap<_, _>>();
+        log::info!(
+            "GGUF v{} model with {} tensors & {} metadata entries",
+            version,
+            tensors.len(),
+            metadata.len()
+        );
+        Ok(Self {
+            version,
+            metadata,
+            tensors,
+            total_bytes_loaded: Cell::new(0),
+        })
+    }
+
+    pub fn metadata(&self, key: &str) -> Result<&GgufValue, LoadError> {
+        self.metadata.get(key).ok_or(LoadError::MissingMetadata {
+            key: key.to_string(),
+        })
+    }
+
+    /// `general.architecture`, which prefixes the model specific metadata keys, e.g "llama".
+    pub fn architecture(&self) -> Result<&str, LoadError> {
+        self.metadata("general.architecture")?
+            .as_str()
+            .ok_or_else(|| LoadError::InvariantBroken("general.architecture".to_string()))
+    }
+
+    /// The [Quantization] keeping `key` compressed once loaded, `SInt8` for Q4_0 & Q8_0
+    /// tensors as ratchet has no kernels for the GGML block formats.
+    pub fn quantization(&self, key: &str) -> Quantization {
+        match self.tensors.get(key).map(|header| header.dtype) {
+            Some(GgmlDType::Q4_0 | GgmlDType::Q8_0) => Quantization::SInt8,
+            _ => Quantization::None,
+        }
+    }
+
+    pub fn load_tensor<R: BufRead + Seek>(
+        &self,
+        key: &str,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Tensor, LoadError> {
+        let header = self.tensors.get(key).ok_or(LoadError::MissingTensor {
+            name: key.to_string(),
+        })?;
+        let data = header.read_data(reader)?;
+        log::info!("Loading tensor: {} with size: {} bytes", key, data.len());
+        let data = match header.dtype {
+            GgmlDType::F32 => data,
+            GgmlDType::F16 => {
+                let f32_data = bytemuck::pod_collect_to_vec::<u8, f16>(&data)
+                    .iter()
+                    .map(|f| f.to_f32())
+                    .collect::<Vec<_>>();
+                bytemuck::cast_slice::<f32, u8>(&f32_data).to_vec()
+            }
+            GgmlDType::Q4_0 => bytemuck::cast_slice(&k_quants::dequantize_q4_0(&data)).to_vec(),
+            GgmlDType::Q8_0 => bytemuck::cast_slice(&k_quants::dequantize_q8_0(&data)).to_vec(),
+            dtype => {
+                return Err(LoadError::UnsupportedDType {
+                    name: key.to_string(),
+                    dtype: dtype.to_u32(),
+                })
+            }
+        };
+        self.total_bytes_loaded
+            .set(self.total_bytes_loaded.get() + data.len());
+        Ok(Tensor::from_bytes(&data, DType::F32, header.shape.clone(), device.clone()).unwrap())
+    }
+}
@@ -134,3 +134,122 @@ pub struct BlockWQ8 {
     pub(crate) qs: [i8; 16],
 }
 const _: () = assert!(std::mem::size_of::<BlockWQ8>() == 20);
+
+/// Dequantizes Q4_0 blocks: 32 4-bit weights offset by 8 & a shared f16 scale.
+pub(crate) fn dequantize_q4_0(data: &[u8]) -> Vec<f32> {
+    let mut output = Vec::with_capacity(data.len() / std::mem::size_of::<BlockQ4_0>() * QK4_0);
+    for block in data.chunks_exact(std::mem::size_of::<BlockQ4_0>()) {
+        let d = f16::from_le_bytes([block[0], block[1]]).to_f32();
+        let qs = &block[2..];
+        output.extend(qs.iter().map(|q| ((q & 0xF) as i32 - 8) as f32 * d));
+        output.extend(qs.iter().map(|q| ((q >> 4) as i32 - 8) as f32 * d));
+    }
+    output
+}
+
+/// Dequantizes Q8_0 blocks: 32 signed 8-bit weights & a shared f16 scale.
+pub(crate) fn dequantize_q8_0(data: &[u8]) -> Vec<f32> {
+    let mut output = Vec::with_capacity(data.len() / std::mem::size_of::<BlockQ8_0>() * QK8_0);
+    for block in data.chunks_exact(std::mem::size_of::<BlockQ8_0>()) {
+        let d = f16::from_le_bytes([block[0], block[1]]).to_f32();
+        output.extend(block[2..].iter().map(|q| *q as i8 as f32 * d));
+    }
+    output
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    //https://github.com/ggerganov/llama.cpp/blob/master/ggml-quants.c quantize_row_q4_0_reference
+    fn quantize_q4_0(xs: &[f32]) -> Vec<u8> {
+        let mut out = vec![];
+        for block in xs.chunks_exact(QK4_0) {
+            let max = block
+                .iter()
+                .copied()
+                .fold(0f32, |m, x| if x.abs() > m.abs() { x } else { m });
+            let d = max / -8.;
+            let id = if d != 0. { 1. / d } else { 0. };
+            out.extend(f16::from_f32(d).to_le_bytes());
+            let q = |x: f32| (x * id + 8.5).min(15.) as u8;
+            let (lo, hi) = block.split_at(QK4_0 / 2);
+            out.extend(lo.iter().zip(hi).map(|(l, h)| q(*l) | (q(*h) << 4)));
+        }
+        out
+    }
+
+    //quantize_row_q8_0_reference
+    fn quantize_q8_0(xs: &[f32]) -> Vec<u8> {
+        let mut out = vec![];
+        for block in xs.chunks_exact(QK8_0) {
+            let amax = block.iter().fold(0f32, |m, x| m.max(x.abs()));
+            let d = amax / 127.;
+            let id = if d != 0. { 1. / d } else { 0. };
+            out.extend(f16::from_f32(d).to_le_bytes());
+            out.extend(block.iter().map(|x| (x * id).round() as i8 as u8));
+        }
+        out
+    }
+
+    fn blocks() -> Vec<f32> {
+        let ramp = (0..QK4_0).map(|i| i as f32 * 0.25 - 4.);
+        let wave = (0..QK4_0).map(|i| (i as f32 * 0.7).sin() * 0.03);
+        ramp.chain(wave)
+            .chain(std::iter::repeat_n(0., QK4_0))
+            .collect()
+    }
+
+    #[test]
+    fn q4_0_round_trips() {
+        let xs = blocks();
+        let data = quantize_q4_0(&xs);
+        assert_eq!(data.len(), 3 * std::mem::size_of::<BlockQ4_0>());
+        let ys = dequantize_q4_0(&data);
+        for (block_xs, block_ys) in xs.chunks(QK4_0).zip(ys.chunks(QK4_0)) {
+            let amax = block_xs.iter().fold(0f32, |m, x| m.max(x.abs()));
+            //Levels run from -8 to 7 steps, so the sign opposite the largest magnitude may
+            //clip a whole step short
+            let step = amax / 8.;
+            for (x, y) in block_xs.iter().zip(block_ys) {
+                assert!((x - y).abs() <= step + 1e-3, "{} != {}", x, y);
+            }
+        }
+        //The largest magnitude is exact: -4 with d = 0.5
+        assert_eq!(ys[0], -4.);
+        assert!(ys[2 * QK4_0..].iter().all(|y| *y == 0.));
+    }
+
+    #[test]
+    fn q8_0_round_trips() {
+        let xs = blocks();
+        let data = quantize_q8_0(&xs);
+        assert_eq!(data.len(), 3 * std::mem::size_of::<BlockQ8_0>());
+        let ys = dequantize_q8_0(&data);
+        for (block_xs, block_ys) in xs.chunks(QK8_0).zip(ys.chunks(QK8_0)) {
+            let amax = block_xs.iter().fold(0f32, |m, x| m.max(x.abs()));
+            let step = amax / 127.;
+            for (x, y) in block_xs.iter().zip(block_ys) {
+                assert!((x - y).abs() <= step / 2. + amax * 1e-3, "{} != {}", x, y);
+            }
+        }
+        assert!(ys[2 * QK8_0..].iter().all(|y| *y == 0.));
+    }
+
+    #[test]
+    fn known_blocks() {
+        //d = 0.5, every low nibble 0 & high nibble 15
+        let mut q4 = f16::from_f32(0.5).to_le_bytes().to_vec();
+        q4.extend([0xF0; QK4_0 / 2]);
+        let ys = dequantize_q4_0(&q4);
+        assert!(ys[..QK4_0 / 2].iter().all(|y| *y == -4.));
+        assert!(ys[QK4_0 / 2..].iter().all(|y| *y == 3.5));
+
+        //d = 0.25, weights -16..16
+        let mut q8 = f16::from_f32(0.25).to_le_bytes().to_vec();
+        q8.extend((-16i8..16).map(|q| q as u8));
+        let ys = dequantize_q8_0(&q8);
+        let expected = (-16..16).map(|q| q as f32 * 0.25).collect::<Vec<_>>();
+        assert_eq!(ys, expected);
+    }
+}
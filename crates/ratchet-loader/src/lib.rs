@@ -1,9 +1,11 @@
 mod converter;
 mod ggml;
+mod gguf;
 mod k_quants;
 
 pub use converter::*;
 pub use ggml::*;
+pub use gguf::*;
 
 pub const STORAGE_BUFFER_ALIGN: usize = 256;
 
@@ -25,6 +27,10 @@ pub enum LoadError {
     InvalidDType(u32),
     #[error("Missing tensor {name}")]
     MissingTensor { name: String },
+    #[error("Missing metadata {key}")]
+    MissingMetadata { key: String },
+    #[error("Unsupported GGUF version {0}")]
+    UnsupportedVersion(u32),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
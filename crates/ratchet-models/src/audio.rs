@@ -0,0 +1,246 @@
+use rubato::{
+    Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction,
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use crate::SAMPLE_RATE;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioLoadError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to decode audio: {0}")]
+    Decode(#[from] SymphoniaError),
+    #[error("No decodable audio track found")]
+    NoTrack,
+    #[error("Unknown sample rate")]
+    UnknownSampleRate,
+    #[error("Failed to resample audio: {0}")]
+    Resample(String),
+}
+
+/// Loads an audio file from disk, returning mono PCM at `SAMPLE_RATE`.
+///
+/// Supports WAV (any bit depth), FLAC, MP3 & OGG Vorbis.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_audio<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<f32>, AudioLoadError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    decode_audio(bytes, path.extension().and_then(|e| e.to_str()))
+}
+
+/// Decodes an in-memory audio file, returning mono PCM at `SAMPLE_RATE`.
+///
+/// The container is sniffed from the bytes, `extension` is only used as a hint.
+pub fn decode_audio(bytes: Vec<u8>, extension: Option<&str>) -> Result<Vec<f32>, AudioLoadError> {
+    let (samples, sample_rate) = decode_mono(bytes, extension)?;
+    resample(&samples, sample_rate, SAMPLE_RATE)
+}
+
+fn decode_mono(
+    bytes: Vec<u8>,
+    extension: Option<&str>,
+) -> Result<(Vec<f32>, usize), AudioLoadError> {
+    let mss = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes)), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = extension {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(AudioLoadError::NoTrack)?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples = vec![];
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(e)) => {
+                // corrupt packets are skipped, as ffmpeg does
+                log::warn!("Skipping undecodable packet: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        sample_rate = Some(spec.rate);
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend(downmix(buffer.samples(), spec.channels.count()));
+    }
+    let sample_rate = sample_rate.ok_or(AudioLoadError::UnknownSampleRate)?;
+    Ok((samples, sample_rate as usize))
+}
+
+/// Averages interleaved channels down to mono.
+pub fn downmix(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return interleaved.to_vec();
+    }
+    interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Band-limited (windowed sinc) resampling of mono PCM.
+pub fn resample(
+    samples: &[f32],
+    from_rate: usize,
+    to_rate: usize,
+) -> Result<Vec<f32>, AudioLoadError> {
+    if from_rate == to_rate || samples.is_empty() {
+        return Ok(samples.to_vec());
+    }
+    const CHUNK_SIZE: usize = 1024;
+    let ratio = to_rate as f64 / from_rate as f64;
+    let params = SincInterpolationParameters {
+        sinc_len: 256,
+        f_cutoff: 0.95,
+        oversampling_factor: 256,
+        interpolation: SincInterpolationType::Cubic,
+        window: WindowFunction::BlackmanHarris2,
+    };
+    let mut resampler = SincFixedIn::<f32>::new(ratio, 1.0, params, CHUNK_SIZE, 1)
+        .map_err(|e| AudioLoadError::Resample(e.to_string()))?;
+
+    let expected = (samples.len() as f64 * ratio).ceil() as usize;
+    let delay = resampler.output_delay();
+    let mut output = Vec::with_capacity(expected + delay);
+
+    let mut chunks = samples.chunks_exact(CHUNK_SIZE);
+    for chunk in chunks.by_ref() {
+        let processed = resampler
+            .process(&[chunk], None)
+            .map_err(|e| AudioLoadError::Resample(e.to_string()))?;
+        output.extend_from_slice(&processed[0]);
+    }
+    let remainder = chunks.remainder();
+    if !remainder.is_empty() {
+        let processed = resampler
+            .process_partial(Some(&[remainder]), None)
+            .map_err(|e| AudioLoadError::Resample(e.to_string()))?;
+        output.extend_from_slice(&processed[0]);
+    }
+    // flush the filter delay
+    while output.len() < expected + delay {
+        let processed = resampler
+            .process_partial::<&[f32]>(None, None)
+            .map_err(|e| AudioLoadError::Resample(e.to_string()))?;
+        output.extend_from_slice(&processed[0]);
+    }
+    output.drain(..delay);
+    output.truncate(expected);
+    Ok(output)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sine(freq: f32, rate: usize, seconds: f32) -> impl Iterator<Item = f32> {
+        (0..(rate as f32 * seconds) as usize)
+            .map(move |i| (2. * std::f32::consts::PI * freq * i as f32 / rate as f32).sin() * 0.5)
+    }
+
+    fn write_wav(spec: hound::WavSpec, samples: &[f32]) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+        for s in samples {
+            for _ in 0..spec.channels {
+                match (spec.sample_format, spec.bits_per_sample) {
+                    (hound::SampleFormat::Float, _) => writer.write_sample(*s).unwrap(),
+                    (_, bits) => {
+                        let max = (1i64 << (bits - 1)) as f32;
+                        writer.write_sample((*s * max) as i32).unwrap()
+                    }
+                }
+            }
+        }
+        writer.finalize().unwrap();
+        cursor.into_inner()
+    }
+
+    //Estimates the dominant frequency by counting rising zero crossings
+    fn frequency(samples: &[f32], rate: usize) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|w| w[0] < 0. && w[1] >= 0.)
+            .count();
+        crossings as f32 * rate as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn decode_any_bit_depth() {
+        let source = sine(440., 44100, 1.0).collect::<Vec<_>>();
+        for (bits, format) in [
+            (16, hound::SampleFormat::Int),
+            (24, hound::SampleFormat::Int),
+            (32, hound::SampleFormat::Float),
+        ] {
+            let spec = hound::WavSpec {
+                channels: 2,
+                sample_rate: 44100,
+                bits_per_sample: bits,
+                sample_format: format,
+            };
+            let pcm = decode_audio(write_wav(spec, &source), Some("wav")).unwrap();
+            assert!(pcm.len().abs_diff(SAMPLE_RATE) <= 1, "len: {}", pcm.len());
+            // edges ring, so only look at the interior
+            let f = frequency(&pcm[1000..15000], SAMPLE_RATE);
+            assert!((f - 440.).abs() < 5., "{} bit: {}Hz", bits, f);
+            let peak = pcm[1000..15000].iter().fold(0f32, |a, b| a.max(b.abs()));
+            assert!((peak - 0.5).abs() < 0.02, "{} bit peak: {}", bits, peak);
+        }
+    }
+
+    #[test]
+    fn resample_is_band_limited() {
+        // 10kHz is above the 8kHz Nyquist of the target rate and should be removed
+        let pcm = sine(10_000., 48000, 1.0).collect::<Vec<_>>();
+        let resampled = resample(&pcm, 48000, SAMPLE_RATE).unwrap();
+        assert_eq!(resampled.len(), SAMPLE_RATE);
+        let peak = resampled[1000..15000]
+            .iter()
+            .fold(0f32, |a, b| a.max(b.abs()));
+        assert!(peak < 0.01, "aliased peak: {}", peak);
+    }
+
+    #[test]
+    fn downmix_averages_channels() {
+        let stereo = [1.0, 0.0, 0.5, 0.5, -1.0, 1.0];
+        assert_eq!(downmix(&stereo, 2), vec![0.5, 0.5, 0.0]);
+    }
+}
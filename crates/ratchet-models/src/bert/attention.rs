@@ -0,0 +1,69 @@
+use ratchet::{shape, Tensor};
+use ratchet_nn::{Linear, Module};
+
+/// Bidirectional multi-head self attention.
+#[derive(Debug)]
+pub struct BertAttention {
+    q: Linear,
+    k: Linear,
+    v: Linear,
+    o: Linear,
+    n_heads: usize,
+    scale: Tensor,
+}
+
+impl BertAttention {
+    pub fn new(q: Linear, k: Linear, v: Linear, o: Linear, n_heads: usize) -> Self {
+        let head_dim = q.w.shape()[0] / n_heads;
+        let scale = (head_dim as f32).powf(-0.5);
+        let scale = Tensor::from_data([scale], shape![1], q.w.device().clone());
+        Self {
+            q,
+            k,
+            v,
+            o,
+            n_heads,
+            scale,
+        }
+    }
+}
+
+/// `x` is `[batch_size, seq_len, hidden_size]`, `mask` the additive
+/// `[batch_size, 1, 1, seq_len]` mask hiding the padding.
+#[derive(Debug, derive_new::new)]
+pub struct BertAttentionInput {
+    pub x: Tensor,
+    pub mask: Tensor,
+}
+
+impl Module for BertAttention {
+    type Input = BertAttentionInput;
+
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let BertAttentionInput { x, mask } = input;
+        let [bs, n_ctx, n_state]: [usize; 3] = x.shape().try_into()?;
+        let heads = shape![bs, n_ctx, self.n_heads, n_state / self.n_heads];
+
+        let q = self
+            .q
+            .forward(x)?
+            .view(heads.clone())?
+            .permute(&[0, 2, 1, 3])?
+            .mul(&self.scale)?;
+        let k = self
+            .k
+            .forward(x)?
+            .view(heads.clone())?
+            .permute(&[0, 2, 3, 1])?;
+        let v = self.v.forward(x)?.view(heads)?.permute(&[0, 2, 1, 3])?;
+
+        let wv = q
+            .matmul(&k, false)?
+            .add(mask)?
+            .softmax(3)?
+            .matmul(&v, false)?
+            .permute(&[0, 2, 1, 3])?
+            .view(shape![bs, n_ctx, n_state])?;
+        self.o.forward(&wv)
+    }
+}
@@ -0,0 +1,147 @@
+use ratchet::{shape, Device, Tensor};
+use tokenizers::Tokenizer;
+
+/// A batch of tokenized texts, padded to the longest sequence.
+///
+/// Each sequence is encoded with the tokenizer's special tokens, e.g `[CLS] ... [SEP]`, and
+/// truncated to `max_len` tokens.
+#[derive(Debug, Clone)]
+pub struct BertBatch {
+    /// `[batch_size, seq_len]`, padded with the pad token.
+    pub input_ids: Vec<i32>,
+    /// `[batch_size, seq_len]`, segment of each token.
+    pub token_type_ids: Vec<i32>,
+    /// Number of tokens of each sequence, padding excluded.
+    pub lengths: Vec<usize>,
+    pub seq_len: usize,
+}
+
+impl BertBatch {
+    pub fn encode(
+        tokenizer: &Tokenizer,
+        texts: &[&str],
+        max_len: usize,
+        pad_token_id: u32,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(!texts.is_empty(), "No texts to encode");
+        let encodings = texts
+            .iter()
+            .map(|text| tokenizer.encode(*text, true).map_err(anyhow::Error::msg))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let lengths = encodings
+            .iter()
+            .map(|e| e.get_ids().len().min(max_len))
+            .collect::<Vec<_>>();
+        anyhow::ensure!(
+            lengths.iter().all(|&len| len > 0),
+            "Texts must encode to at least one token"
+        );
+        let seq_len = *lengths.iter().max().unwrap();
+
+        let padded = |tokens: &[u32], pad: u32| {
+            let mut row = tokens[..tokens.len().min(max_len)].to_vec();
+            row.resize(seq_len, pad);
+            row.into_iter().map(|t| t as i32)
+        };
+        let input_ids = encodings
+            .iter()
+            .flat_map(|e| padded(e.get_ids(), pad_token_id))
+            .collect();
+        let token_type_ids = encodings
+            .iter()
+            .flat_map(|e| padded(e.get_type_ids(), 0))
+            .collect();
+        Ok(Self {
+            input_ids,
+            token_type_ids,
+            lengths,
+            seq_len,
+        })
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.lengths.len()
+    }
+
+    /// Additive mask hiding the padding from attention, `[batch_size, 1, 1, seq_len]`.
+    pub fn attention_mask(&self, device: &Device) -> Tensor {
+        let mask = self.rows(|_| 0., |_| f32::NEG_INFINITY);
+        let shape = shape![self.batch_size(), 1, 1, self.seq_len];
+        Tensor::from_data(mask, shape, device.clone())
+    }
+
+    /// Weights averaging the hidden states of each sequence, `[batch_size, 1, seq_len]`.
+    pub fn mean_weights(&self, device: &Device) -> Tensor {
+        let weights = self.rows(|len| 1. / len as f32, |_| 0.);
+        let shape = shape![self.batch_size(), 1, self.seq_len];
+        Tensor::from_data(weights, shape, device.clone())
+    }
+
+    /// One value per position, `token` for positions within the sequence & `pad` after.
+    fn rows(&self, token: impl Fn(usize) -> f32, pad: impl Fn(usize) -> f32) -> Vec<f32> {
+        let (token, pad) = (&token, &pad);
+        self.lengths
+            .iter()
+            .flat_map(|&len| {
+                (0..self.seq_len).map(move |i| if i < len { token(len) } else { pad(len) })
+            })
+            .collect()
+    }
+
+    pub fn input_ids(&self, device: &Device) -> Tensor {
+        let shape = shape![self.batch_size(), self.seq_len];
+        Tensor::from_data(&self.input_ids, shape, device.clone())
+    }
+
+    pub fn token_type_ids(&self, device: &Device) -> Tensor {
+        let shape = shape![self.batch_size(), self.seq_len];
+        Tensor::from_data(&self.token_type_ids, shape, device.clone())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::pre_tokenizers::whitespace::Whitespace;
+
+    fn tokenizer() -> Tokenizer {
+        let vocab = [
+            "[PAD]", "[UNK]", "semantic", "search", "in", "the", "browser",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.to_string(), i as u32))
+        .collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+        tokenizer
+    }
+
+    #[test]
+    fn pads_and_masks_the_batch() {
+        let texts = ["semantic search", "search in the browser", "the browser"];
+        let batch = BertBatch::encode(&tokenizer(), &texts, 3, 0).unwrap();
+        assert_eq!(batch.lengths, [2, 3, 2]);
+        assert_eq!(batch.input_ids, [2, 3, 0, 3, 4, 5, 5, 6, 0]);
+        assert_eq!(batch.token_type_ids, [0; 9]);
+
+        let inf = f32::NEG_INFINITY;
+        let mask = batch.attention_mask(&Device::CPU);
+        assert_eq!(mask.shape(), &shape![3, 1, 1, 3]);
+        assert_eq!(
+            mask.to_vec::<f32>().unwrap(),
+            [0., 0., inf, 0., 0., 0., 0., 0., inf]
+        );
+        let third = 1. / 3.;
+        assert_eq!(
+            batch.mean_weights(&Device::CPU).to_vec::<f32>().unwrap(),
+            [0.5, 0.5, 0., third, third, third, 0.5, 0.5, 0.]
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use ratchet::{shape, Device, Tensor};
+use ratchet_nn::{Embedding, LayerNorm, Linear, Module};
+use tokenizers::Tokenizer;
+
+use crate::checkpoint::SafetensorsShards;
+use crate::{BertAttention, BertAttentionInput, BertBatch, BertConfig, Pooling};
+
+/// Reads the tensors of a layer from a checkpoint, `prefix` is `bert.` for checkpoints of
+/// task models built on a BERT encoder.
+struct Loader<'a> {
+    shards: SafetensorsShards<'a>,
+    prefix: &'static str,
+    device: Device,
+}
+
+impl Loader<'_> {
+    fn tensor(&self, name: &str) -> anyhow::Result<Tensor> {
+        let name = format!("{}{}", self.prefix, name);
+        let (data, shape) = self
+            .shards
+            .get(&name)?
+            .ok_or_else(|| anyhow::anyhow!("Missing tensor {}", name))?;
+        Ok(Tensor::from_data(data, shape, self.device.clone()))
+    }
+
+    fn linear(&self, prefix: &str) -> anyhow::Result<Linear> {
+        let w = self.tensor(&format!("{}.weight", prefix))?;
+        let b = self.tensor(&format!("{}.bias", prefix))?;
+        Ok(Linear::new(w, Some(b)))
+    }
+
+    fn layer_norm(&self, prefix: &str, eps: f32) -> anyhow::Result<LayerNorm> {
+        let weight = self.tensor(&format!("{}.weight", prefix))?;
+        let bias = self.tensor(&format!("{}.bias", prefix))?;
+        Ok(LayerNorm::new(weight, Some(bias), eps))
+    }
+}
+
+/// Sum of the word, position & token type embeddings, normalized.
+#[derive(Debug)]
+pub struct BertEmbeddings {
+    word: Embedding,
+    /// `[max_position_embeddings, hidden_size]`
+    position: Tensor,
+    token_type: Embedding,
+    norm: LayerNorm,
+}
+
+impl BertEmbeddings {
+    fn load(loader: &Loader, config: &BertConfig) -> anyhow::Result<Self> {
+        let eps = config.layer_norm_eps;
+        Ok(Self {
+            word: Embedding::new(loader.tensor("embeddings.word_embeddings.weight")?),
+            position: loader.tensor("embeddings.position_embeddings.weight")?,
+            token_type: Embedding::new(loader.tensor("embeddings.token_type_embeddings.weight")?),
+            norm: loader.layer_norm("embeddings.LayerNorm", eps)?,
+        })
+    }
+
+    /// `input_ids` & `token_type_ids` are `[batch_size, seq_len]`.
+    fn forward(&self, input_ids: &Tensor, token_type_ids: &Tensor) -> anyhow::Result<Tensor> {
+        let n_ctx = input_ids.shape()[1];
+        let n_state = self.position.shape()[1];
+        let position = self.position.slice(&[0..n_ctx, 0..n_state])?;
+        let x = self
+            .word
+            .forward(input_ids)?
+            .add(&self.token_type.forward(token_type_ids)?)?
+            .add(&position)?;
+        self.norm.forward(&x)
+    }
+}
+
+/// A post-norm encoder layer, each sublayer is normalized after the residual connection.
+#[derive(Debug)]
+pub struct BertLayer {
+    attn: BertAttention,
+    attn_norm: LayerNorm,
+    intermediate: Linear,
+    output: Linear,
+    output_norm: LayerNorm,
+}
+
+impl Module for BertLayer {
+    type Input = BertAttentionInput;
+
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let h = input.x.add(&self.attn.forward(input)?)?;
+        let h = self.attn_norm.forward(&h)?;
+        let mlp = self
+            .output
+            .forward(&self.intermediate.forward(&h)?.gelu()?)?;
+        self.output_norm.forward(&h.add(&mlp)?)
+    }
+}
+
+impl BertLayer {
+    fn load(loader: &Loader, layer: usize, config: &BertConfig) -> anyhow::Result<Self> {
+        let name = |n: &str| format!("encoder.layer.{}.{}", layer, n);
+        let eps = config.layer_norm_eps;
+        let attn = BertAttention::new(
+            loader.linear(&name("attention.self.query"))?,
+            loader.linear(&name("attention.self.key"))?,
+            loader.linear(&name("attention.self.value"))?,
+            loader.linear(&name("attention.output.dense"))?,
+            config.num_attention_heads,
+        );
+        Ok(Self {
+            attn,
+            attn_norm: loader.layer_norm(&name("attention.output.LayerNorm"), eps)?,
+            intermediate: loader.linear(&name("intermediate.dense"))?,
+            output: loader.linear(&name("output.dense"))?,
+            output_norm: loader.layer_norm(&name("output.LayerNorm"), eps)?,
+        })
+    }
+}
+
+/// `input_ids` & `token_type_ids` are `[batch_size, seq_len]`, see [BertBatch::attention_mask]
+/// for the mask.
+#[derive(Debug, derive_new::new)]
+pub struct BertInput {
+    pub input_ids: Tensor,
+    pub token_type_ids: Tensor,
+    pub attention_mask: Tensor,
+}
+
+/// An encoder-only transformer of the BERT family, e.g MiniLM & BGE sentence embedding models.
+///
+/// The feed forward uses ratchet's GELU, a close approximation of the exact GELU these models
+/// were trained with.
+#[derive(Debug)]
+pub struct Bert {
+    embeddings: BertEmbeddings,
+    layers: Vec<BertLayer>,
+    ones: Tensor,
+    eps: Tensor,
+    pub config: BertConfig,
+    pub device: Device,
+}
+
+impl Module for Bert {
+    type Input = BertInput;
+
+    /// Hidden states of the final layer, `[batch_size, seq_len, hidden_size]`.
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let BertInput {
+            input_ids,
+            token_type_ids,
+            attention_mask,
+        } = input;
+        let n_ctx = input_ids.shape()[1];
+        anyhow::ensure!(
+            n_ctx <= self.config.max_position_embeddings,
+            "Sequence of {} tokens exceeds the maximum length of {}",
+            n_ctx,
+            self.config.max_position_embeddings
+        );
+        let mut x = self.embeddings.forward(input_ids, token_type_ids)?;
+        for layer in &self.layers {
+            let layer_input = BertAttentionInput::new(x, attention_mask.clone());
+            x = layer.forward(&layer_input)?;
+        }
+        Ok(x)
+    }
+}
+
+impl Bert {
+    /// Loads a HuggingFace checkpoint, which may be split over several `.safetensors` files.
+    pub fn load_safetensors(
+        shards: &[&[u8]],
+        config: BertConfig,
+        device: Device,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            config.hidden_act.starts_with("gelu"),
+            "Unsupported activation {}",
+            config.hidden_act
+        );
+        let shards = SafetensorsShards::deserialize(shards)?;
+        let prefix = match shards.contains("embeddings.word_embeddings.weight") {
+            true => "",
+            false => "bert.",
+        };
+        let loader = Loader {
+            shards,
+            prefix,
+            device: device.clone(),
+        };
+        let embeddings = BertEmbeddings::load(&loader, &config)?;
+        let layers = (0..config.num_hidden_layers)
+            .map(|layer| BertLayer::load(&loader, layer, &config))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let n_state = config.hidden_size;
+        let ones = Tensor::from_data(vec![1f32; n_state], shape![1, n_state], device.clone());
+        let eps = Tensor::from_data([1e-12f32], shape![1], device.clone());
+        log::info!("Sucessfully loaded BERT model");
+        Ok(Self {
+            embeddings,
+            layers,
+            ones,
+            eps,
+            config,
+            device,
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_bytes(safetensors: &[u8], config: &[u8]) -> anyhow::Result<Self> {
+        let device = Device::request_device(ratchet::DeviceRequest::GPU).await?;
+        let config = BertConfig::from_json(config)?;
+        Self::load_safetensors(&[safetensors], config, device)
+    }
+
+    pub fn encode(&self, tokenizer: &Tokenizer, texts: &[&str]) -> anyhow::Result<BertBatch> {
+        let max_len = self.config.max_position_embeddings;
+        BertBatch::encode(tokenizer, texts, max_len, self.config.pad_token_id)
+    }
+
+    /// Pools the hidden states of `batch` into one embedding per sequence,
+    /// `[batch_size, hidden_size]`, optionally scaled to unit length.
+    pub fn pooled(
+        &self,
+        batch: &BertBatch,
+        pooling: Pooling,
+        normalize: bool,
+    ) -> anyhow::Result<Tensor> {
+        let input = BertInput::new(
+            batch.input_ids(&self.device),
+            batch.token_type_ids(&self.device),
+            batch.attention_mask(&self.device),
+        );
+        let hidden = self.forward(&input)?;
+        let (bs, n_state) = (batch.batch_size(), self.config.hidden_size);
+        let pooled = match pooling {
+            Pooling::Mean => batch
+                .mean_weights(&self.device)
+                .matmul(&hidden, false)?
+                .view(shape![bs, n_state])?,
+            Pooling::Cls => hidden
+                .slice(&[0..bs, 0..1, 0..n_state])?
+                .view(shape![bs, n_state])?,
+        };
+        if !normalize {
+            return Ok(pooled);
+        }
+        let norm = pooled
+            .mul(&pooled)?
+            .matmul(&self.ones, true)?
+            .add(&self.eps)?
+            .sqrt()?;
+        pooled.div(&norm)
+    }
+
+    /// One embedding per text, the rows of [Bert::pooled].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn embed(
+        &self,
+        tokenizer: &Tokenizer,
+        texts: &[&str],
+        pooling: Pooling,
+        normalize: bool,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        let batch = self.encode(tokenizer, texts)?;
+        let pooled = self.pooled(&batch, pooling, normalize)?.resolve()?;
+        let pooled = pooled.to(&Device::CPU)?.to_vec::<f32>()?;
+        Ok(Self::rows(pooled, self.config.hidden_size))
+    }
+
+    /// One embedding per text, the rows of [Bert::pooled].
+    #[cfg(target_arch = "wasm32")]
+    pub async fn embed(
+        &self,
+        tokenizer: &Tokenizer,
+        texts: &[&str],
+        pooling: Pooling,
+        normalize: bool,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        let batch = self.encode(tokenizer, texts)?;
+        let pooled = self.pooled(&batch, pooling, normalize)?.resolve()?;
+        let pooled = pooled.to(&Device::CPU).await?.to_vec::<f32>()?;
+        Ok(Self::rows(pooled, self.config.hidden_size))
+    }
+
+    fn rows(data: Vec<f32>, n_state: usize) -> Vec<Vec<f32>> {
+        data.chunks(n_state).map(<[f32]>::to_vec).collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use hf_hub::api::sync::Api;
+    use numpy::PyArrayDyn;
+    use pyo3::{prelude::*, types::PyTuple};
+    use ratchet::DeviceRequest;
+
+    const TEXTS: [&str; 3] = [
+        "Semantic search in the browser",
+        "Embeddings",
+        "A much longer sentence, which pads the others in the batch to its length",
+    ];
+
+    fn log_init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    fn ground_truth(texts: &[&str]) -> anyhow::Result<Vec<f32>> {
+        let prg = r#"
+from sentence_transformers import SentenceTransformer
+def ground(texts):
+    model = SentenceTransformer("sentence-transformers/all-MiniLM-L6-v2")
+    return model.encode(texts, normalize_embeddings=True)
+"#;
+        Python::with_gil(|py| {
+            let prg = PyModule::from_code(py, prg, "x.py", "x")?;
+            let py_args = PyTuple::new(py, [texts.to_vec()]);
+            let py_result: &PyArrayDyn<f32> = prg.getattr("ground")?.call1(py_args)?.extract()?;
+            Tensor::from(py_result).to_vec::<f32>()
+        })
+    }
+
+    fn assert_close(a: &[f32], b: &[f32], atol: f32) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < atol, "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn minilm_end_to_end() {
+        log_init();
+        let api = Api::new().unwrap();
+        let model = api.model("sentence-transformers/all-MiniLM-L6-v2".to_string());
+        let safetensors = std::fs::read(model.get("model.safetensors").unwrap()).unwrap();
+        let config = std::fs::read(model.get("config.json").unwrap()).unwrap();
+        let pooling = std::fs::read(model.get("1_Pooling/config.json").unwrap()).unwrap();
+        let tokenizer = Tokenizer::from_file(model.get("tokenizer.json").unwrap()).unwrap();
+
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let config = BertConfig::from_json(&config).unwrap();
+        let bert = Bert::load_safetensors(&[&safetensors], config, device).unwrap();
+        let pooling = Pooling::from_json(&pooling).unwrap();
+
+        let batch = bert.embed(&tokenizer, &TEXTS, pooling, true).unwrap();
+        assert_eq!(batch.len(), TEXTS.len());
+        for (text, row) in TEXTS.iter().zip(&batch) {
+            let single = bert.embed(&tokenizer, &[text], pooling, true).unwrap();
+            assert_close(row, &single[0], 1e-4);
+        }
+
+        let expected = ground_truth(&TEXTS).unwrap();
+        assert_close(&batch.concat(), &expected, 1e-3);
+    }
+}
@@ -0,0 +1,117 @@
+/// Hyperparameters of a BERT-style encoder, deserialized from the `config.json` of a
+/// HuggingFace checkpoint.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct BertConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    #[serde(default = "default_max_position_embeddings")]
+    pub max_position_embeddings: usize,
+    #[serde(default = "default_type_vocab_size")]
+    pub type_vocab_size: usize,
+    #[serde(default = "default_layer_norm_eps")]
+    pub layer_norm_eps: f32,
+    #[serde(default)]
+    pub pad_token_id: u32,
+    #[serde(default = "default_hidden_act")]
+    pub hidden_act: String,
+}
+
+fn default_max_position_embeddings() -> usize {
+    512
+}
+
+fn default_type_vocab_size() -> usize {
+    2
+}
+
+fn default_layer_norm_eps() -> f32 {
+    1e-12
+}
+
+fn default_hidden_act() -> String {
+    "gelu".to_string()
+}
+
+impl BertConfig {
+    pub fn from_json(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    pub fn head_dim(&self) -> usize {
+        self.hidden_size / self.num_attention_heads
+    }
+}
+
+/// How the hidden states of a sequence are reduced to a single embedding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pooling {
+    /// Average of the hidden states, padding excluded.
+    #[default]
+    Mean,
+    /// Hidden state of the first token, `[CLS]`.
+    Cls,
+}
+
+#[derive(serde::Deserialize)]
+struct PoolingConfig {
+    #[serde(default)]
+    pooling_mode_cls_token: bool,
+    #[serde(default)]
+    pooling_mode_mean_tokens: bool,
+}
+
+impl Pooling {
+    /// Reads the `1_Pooling/config.json` of a sentence-transformers model.
+    pub fn from_json(bytes: &[u8]) -> anyhow::Result<Self> {
+        let config: PoolingConfig = serde_json::from_slice(bytes)?;
+        match (
+            config.pooling_mode_cls_token,
+            config.pooling_mode_mean_tokens,
+        ) {
+            (true, false) => Ok(Pooling::Cls),
+            (false, true) => Ok(Pooling::Mean),
+            _ => anyhow::bail!("Only one of CLS or mean pooling is supported"),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minilm_config() {
+        let json = r#"{
+            "architectures": ["BertModel"],
+            "attention_probs_dropout_prob": 0.1,
+            "hidden_act": "gelu",
+            "hidden_size": 384,
+            "intermediate_size": 1536,
+            "layer_norm_eps": 1e-12,
+            "max_position_embeddings": 512,
+            "model_type": "bert",
+            "num_attention_heads": 12,
+            "num_hidden_layers": 6,
+            "pad_token_id": 0,
+            "type_vocab_size": 2,
+            "vocab_size": 30522
+        }"#;
+        let config = BertConfig::from_json(json.as_bytes()).unwrap();
+        assert_eq!(config.head_dim(), 32);
+        assert_eq!(config.num_hidden_layers, 6);
+
+        let pooling = r#"{
+            "word_embedding_dimension": 384,
+            "pooling_mode_cls_token": false,
+            "pooling_mode_mean_tokens": true,
+            "pooling_mode_max_tokens": false
+        }"#;
+        assert_eq!(
+            Pooling::from_json(pooling.as_bytes()).unwrap(),
+            Pooling::Mean
+        );
+    }
+}
@@ -0,0 +1,10 @@
+mod attention;
+mod batch;
+#[allow(clippy::module_inception)]
+mod bert;
+mod config;
+
+pub use attention::*;
+pub use batch::*;
+pub use bert::*;
+pub use config::*;
@@ -0,0 +1,51 @@
+//! Reading weights from HuggingFace safetensors checkpoints.
+use ratchet::Shape;
+use safetensors::{Dtype, SafeTensorError, SafeTensors};
+
+/// Casts the raw data of a safetensors tensor to F32.
+pub(crate) fn to_f32(name: &str, dtype: Dtype, data: &[u8]) -> anyhow::Result<Vec<f32>> {
+    Ok(match dtype {
+        Dtype::F32 => bytemuck::pod_collect_to_vec::<u8, f32>(data),
+        Dtype::F16 => bytemuck::pod_collect_to_vec::<u8, half::f16>(data)
+            .iter()
+            .map(|h| h.to_f32())
+            .collect(),
+        Dtype::BF16 => bytemuck::pod_collect_to_vec::<u8, half::bf16>(data)
+            .iter()
+            .map(|h| h.to_f32())
+            .collect(),
+        dt => anyhow::bail!("Unsupported dtype {:?} for tensor {}", dt, name),
+    })
+}
+
+/// A checkpoint split over one or more `.safetensors` files.
+pub(crate) struct SafetensorsShards<'a>(Vec<SafeTensors<'a>>);
+
+impl<'a> SafetensorsShards<'a> {
+    pub fn deserialize(shards: &[&'a [u8]]) -> anyhow::Result<Self> {
+        let shards = shards
+            .iter()
+            .map(|bytes| SafeTensors::deserialize(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(shards))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|shard| shard.tensor(name).is_ok())
+    }
+
+    /// The named tensor as F32 data & its shape, from whichever shard holds it.
+    pub fn get(&self, name: &str) -> anyhow::Result<Option<(Vec<f32>, Shape)>> {
+        for shard in &self.0 {
+            match shard.tensor(name) {
+                Ok(view) => {
+                    let data = to_f32(name, view.dtype(), view.data())?;
+                    return Ok(Some((data, view.shape().to_vec().into())));
+                }
+                Err(SafeTensorError::TensorNotFound(_)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::ops::AddAssign;
+
+/// How a reference token was transcribed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum EditOp {
+    Hit,
+    Substitution,
+    /// A hypothesis token with no counterpart in the reference.
+    Insertion,
+    /// A reference token missing from the hypothesis.
+    Deletion,
+}
+
+/// One step of the alignment between a reference & a hypothesis.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Edit {
+    pub op: EditOp,
+    /// `None` for insertions.
+    pub reference: Option<String>,
+    /// `None` for deletions.
+    pub hypothesis: Option<String>,
+}
+
+/// Word or character error rate, along with the minimum edit alignment it was computed from.
+///
+/// Error rates of several utterances are combined with `+=`, weighting each by its
+/// reference length. Alignments are not combined.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ErrorRate {
+    pub hits: usize,
+    pub substitutions: usize,
+    pub deletions: usize,
+    pub insertions: usize,
+    pub alignment: Vec<Edit>,
+}
+
+impl ErrorRate {
+    /// Aligns `hypothesis` against `reference` with the fewest edits.
+    pub fn compute<S: AsRef<str>>(reference: &[S], hypothesis: &[S]) -> Self {
+        let (n, m) = (reference.len(), hypothesis.len());
+        let same = |i: usize, j: usize| reference[i].as_ref() == hypothesis[j].as_ref();
+
+        //distances[i][j], edits between the first i reference & j hypothesis tokens
+        let mut distances = vec![vec![0usize; m + 1]; n + 1];
+        for (i, row) in distances.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        for (j, distance) in distances[0].iter_mut().enumerate() {
+            *distance = j;
+        }
+        for i in 1..=n {
+            for j in 1..=m {
+                let diagonal = distances[i - 1][j - 1] + usize::from(!same(i - 1, j - 1));
+                distances[i][j] = diagonal
+                    .min(distances[i - 1][j] + 1)
+                    .min(distances[i][j - 1] + 1);
+            }
+        }
+
+        let mut rate = ErrorRate::default();
+        let (mut i, mut j) = (n, m);
+        while i > 0 || j > 0 {
+            let token = |tokens: &[S], k: usize| Some(tokens[k].as_ref().to_string());
+            let op = if i > 0
+                && j > 0
+                && distances[i][j] == distances[i - 1][j - 1] + usize::from(!same(i - 1, j - 1))
+            {
+                i -= 1;
+                j -= 1;
+                match same(i, j) {
+                    true => EditOp::Hit,
+                    false => EditOp::Substitution,
+                }
+            } else if i > 0 && distances[i][j] == distances[i - 1][j] + 1 {
+                i -= 1;
+                EditOp::Deletion
+            } else {
+                j -= 1;
+                EditOp::Insertion
+            };
+            let (reference, hypothesis) = match op {
+                EditOp::Hit => {
+                    rate.hits += 1;
+                    (token(reference, i), token(hypothesis, j))
+                }
+                EditOp::Substitution => {
+                    rate.substitutions += 1;
+                    (token(reference, i), token(hypothesis, j))
+                }
+                EditOp::Deletion => {
+                    rate.deletions += 1;
+                    (token(reference, i), None)
+                }
+                EditOp::Insertion => {
+                    rate.insertions += 1;
+                    (None, token(hypothesis, j))
+                }
+            };
+            rate.alignment.push(Edit {
+                op,
+                reference,
+                hypothesis,
+            });
+        }
+        rate.alignment.reverse();
+        rate
+    }
+
+    pub fn errors(&self) -> usize {
+        self.substitutions + self.deletions + self.insertions
+    }
+
+    pub fn reference_len(&self) -> usize {
+        self.hits + self.substitutions + self.deletions
+    }
+
+    /// Errors per reference token. An empty reference has a rate of 0, or infinity if
+    /// anything was inserted.
+    pub fn rate(&self) -> f64 {
+        match (self.errors(), self.reference_len()) {
+            (0, _) => 0.,
+            (errors, n) => errors as f64 / n as f64,
+        }
+    }
+}
+
+impl AddAssign<&ErrorRate> for ErrorRate {
+    fn add_assign(&mut self, other: &ErrorRate) {
+        self.hits += other.hits;
+        self.substitutions += other.substitutions;
+        self.deletions += other.deletions;
+        self.insertions += other.insertions;
+    }
+}
+
+impl fmt::Display for ErrorRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}% (S={}, D={}, I={}, N={})",
+            self.rate() * 100.,
+            self.substitutions,
+            self.deletions,
+            self.insertions,
+            self.reference_len()
+        )
+    }
+}
+
+/// Word error rate between two normalised transcripts, split on whitespace.
+pub fn word_error_rate(reference: &str, hypothesis: &str) -> ErrorRate {
+    let reference = reference.split_whitespace().collect::<Vec<_>>();
+    let hypothesis = hypothesis.split_whitespace().collect::<Vec<_>>();
+    ErrorRate::compute(&reference, &hypothesis)
+}
+
+/// Character error rate between two normalised transcripts, runs of whitespace count
+/// as a single space.
+pub fn char_error_rate(reference: &str, hypothesis: &str) -> ErrorRate {
+    let chars = |text: &str| {
+        text.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    ErrorRate::compute(&chars(reference), &chars(hypothesis))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_alignment() {
+        let wer = word_error_rate("the cat sat on the mat", "the cat sat at the mat mat");
+        assert_eq!(
+            (wer.hits, wer.substitutions, wer.deletions, wer.insertions),
+            (5, 1, 0, 1)
+        );
+        assert_eq!(wer.rate(), 2. / 6.);
+        assert_eq!(wer.alignment.len(), 7);
+        assert_eq!(
+            wer.alignment[3],
+            Edit {
+                op: EditOp::Substitution,
+                reference: Some("on".to_string()),
+                hypothesis: Some("at".to_string()),
+            }
+        );
+
+        let wer = word_error_rate("a b c d", "a d");
+        assert_eq!((wer.deletions, wer.errors()), (2, 2));
+        let ops = wer.alignment.iter().map(|e| e.op).collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            [EditOp::Hit, EditOp::Deletion, EditOp::Deletion, EditOp::Hit]
+        );
+    }
+
+    #[test]
+    fn corpus_rate_is_weighted_by_reference_length() {
+        let mut total = word_error_rate("one two three", "one two three");
+        total += &word_error_rate("four", "five");
+        assert_eq!(total.rate(), 0.25);
+        assert_eq!(total.to_string(), "25.00% (S=1, D=0, I=0, N=4)");
+
+        assert_eq!(word_error_rate("", "").rate(), 0.);
+        assert_eq!(word_error_rate("", "noise").rate(), f64::INFINITY);
+    }
+
+    #[test]
+    fn character_errors() {
+        let cer = char_error_rate("kitten  sat", "sitting sat");
+        assert_eq!(cer.errors(), 3);
+        assert_eq!(cer.reference_len(), 10);
+    }
+}
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+use crate::{
+    char_error_rate, load_audio, transcribe, word_error_rate, DecodingOptions, ErrorRate,
+    TextNormalizer, Whisper,
+};
+
+/// Audio formats picked up by [evaluate_dir].
+const AUDIO_EXTENSIONS: [&str; 5] = ["wav", "flac", "mp3", "ogg", "m4a"];
+
+/// A recording & its reference transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalSample {
+    pub audio: PathBuf,
+    pub reference: String,
+}
+
+/// The transcript of one [EvalSample] & its error rates, after normalisation.
+#[derive(Debug, Clone)]
+pub struct EvalResult {
+    pub audio: PathBuf,
+    pub reference: String,
+    pub hypothesis: String,
+    pub wer: ErrorRate,
+    pub cer: ErrorRate,
+    pub processing_time: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct EvalReport {
+    pub results: Vec<EvalResult>,
+    /// Word error rate over all samples, weighted by their reference lengths.
+    pub wer: ErrorRate,
+    /// Character error rate over all samples, weighted by their reference lengths.
+    pub cer: ErrorRate,
+    pub elapsed: Duration,
+}
+
+impl EvalReport {
+    /// Results ordered from the highest word error rate, to triage regressions.
+    pub fn worst(&self) -> Vec<&EvalResult> {
+        let mut results = self.results.iter().collect::<Vec<_>>();
+        results.sort_by(|a, b| b.wer.rate().total_cmp(&a.wer.rate()));
+        results
+    }
+}
+
+/// Pairs each audio file in `dir` with the transcript of the same name, e.g `001.wav` with
+/// `001.txt`, in file name order. Audio without a transcript is skipped.
+pub fn eval_samples<P: AsRef<Path>>(dir: P) -> anyhow::Result<Vec<EvalSample>> {
+    let dir = dir.as_ref();
+    let mut audio = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    audio.retain(|path| {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+    });
+    audio.sort();
+
+    let mut samples = vec![];
+    for path in audio {
+        let transcript = path.with_extension("txt");
+        if !transcript.exists() {
+            log::warn!("No reference transcript for {}", path.display());
+            continue;
+        }
+        let reference = std::fs::read_to_string(&transcript)
+            .with_context(|| format!("Failed to read {}", transcript.display()))?;
+        samples.push(EvalSample {
+            audio: path,
+            reference,
+        });
+    }
+    Ok(samples)
+}
+
+/// Transcribes `samples`, scoring each against its reference.
+///
+/// Both transcripts are normalised with `normalizer` first, use
+/// [crate::EnglishTextNormalizer] to compare against Whisper's published English results.
+pub fn evaluate(
+    model: &mut Whisper,
+    samples: &[EvalSample],
+    options: DecodingOptions,
+    normalizer: &dyn TextNormalizer,
+) -> anyhow::Result<EvalReport> {
+    let start = Instant::now();
+    let mut results = Vec::with_capacity(samples.len());
+    let mut wer = ErrorRate::default();
+    let mut cer = ErrorRate::default();
+    for sample in samples {
+        let audio = load_audio(&sample.audio)
+            .with_context(|| format!("Failed to load {}", sample.audio.display()))?;
+        let transcript = transcribe(model, audio, options.clone())
+            .with_context(|| format!("Failed to transcribe {}", sample.audio.display()))?;
+
+        let reference = normalizer.normalize(&sample.reference);
+        let hypothesis = normalizer.normalize(&transcript.formatted.unwrap_or_default());
+        let result = EvalResult {
+            audio: sample.audio.clone(),
+            wer: word_error_rate(&reference, &hypothesis),
+            cer: char_error_rate(&reference, &hypothesis),
+            reference,
+            hypothesis,
+            processing_time: transcript.processing_time,
+        };
+        log::info!("{}: WER {}", sample.audio.display(), result.wer);
+        wer += &result.wer;
+        cer += &result.cer;
+        results.push(result);
+    }
+    Ok(EvalReport {
+        results,
+        wer,
+        cer,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// As [evaluate], for the samples found by [eval_samples].
+pub fn evaluate_dir<P: AsRef<Path>>(
+    model: &mut Whisper,
+    dir: P,
+    options: DecodingOptions,
+    normalizer: &dyn TextNormalizer,
+) -> anyhow::Result<EvalReport> {
+    let samples = eval_samples(&dir)?;
+    anyhow::ensure!(
+        !samples.is_empty(),
+        "No audio with a reference transcript in {}",
+        dir.as_ref().display()
+    );
+    evaluate(model, &samples, options, normalizer)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_are_paired_with_transcripts() {
+        let dir = std::env::temp_dir().join(format!("ratchet-eval-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["b.wav", "b.txt", "a.flac", "a.txt", "c.mp3", "notes.txt"] {
+            std::fs::write(dir.join(file), file).unwrap();
+        }
+
+        let samples = eval_samples(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            samples,
+            [
+                EvalSample {
+                    audio: dir.join("a.flac"),
+                    reference: "a.txt".to_string(),
+                },
+                EvalSample {
+                    audio: dir.join("b.wav"),
+                    reference: "b.txt".to_string(),
+                },
+            ]
+        );
+    }
+}
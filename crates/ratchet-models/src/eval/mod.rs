@@ -0,0 +1,11 @@
+mod error_rate;
+#[cfg(not(target_arch = "wasm32"))]
+mod harness;
+mod normalizers;
+mod numbers;
+
+pub use error_rate::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use harness::*;
+pub use normalizers::*;
+pub use numbers::*;
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use tokenizers::NormalizedString;
+
+use crate::EnglishNumberNormalizer;
+
+lazy_static::lazy_static! {
+    static ref BRACKETED: Regex = Regex::new(r"[<\[][^>\]]*[>\]]").unwrap();
+    static ref PARENTHESISED: Regex = Regex::new(r"\(([^)]+?)\)").unwrap();
+    static ref FILLERS: Regex = Regex::new(r"\b(hmm|mm|mhm|mmm|uh|um)\b").unwrap();
+    static ref SPACED_APOSTROPHE: Regex = Regex::new(r"\s+'").unwrap();
+    static ref DIGIT_COMMA: Regex = Regex::new(r"(\d),(\d)").unwrap();
+    static ref PERIOD: Regex = Regex::new(r"\.([^0-9]|$)").unwrap();
+    static ref PREFIX_SYMBOL: Regex = Regex::new(r"[.$¢€£]([^0-9])").unwrap();
+    static ref SUFFIX_SYMBOL: Regex = Regex::new(r"([^0-9])%").unwrap();
+    static ref WHITESPACE: Regex = Regex::new(r"\s+").unwrap();
+    static ref MARKS: Regex = Regex::new(r"\p{Mn}").unwrap();
+    static ref SYMBOLS: Regex = Regex::new(r"[\p{M}\p{S}\p{P}]").unwrap();
+    static ref NON_NUMERIC_SYMBOLS: Regex = Regex::new(r"[[\p{M}\p{S}\p{P}]--[.%$¢€£]]").unwrap();
+    static ref REPLACERS: Vec<(Regex, &'static str)> = [
+        // common contractions
+        (r"\bwon't\b", "will not"),
+        (r"\bcan't\b", "can not"),
+        (r"\blet's\b", "let us"),
+        (r"\bain't\b", "aint"),
+        (r"\by'all\b", "you all"),
+        (r"\bwanna\b", "want to"),
+        (r"\bgotta\b", "got to"),
+        (r"\bgonna\b", "going to"),
+        (r"\bi'ma\b", "i am going to"),
+        (r"\bimma\b", "i am going to"),
+        (r"\bwoulda\b", "would have"),
+        (r"\bcoulda\b", "could have"),
+        (r"\bshoulda\b", "should have"),
+        (r"\bma'am\b", "madam"),
+        // titles & prefixes
+        (r"\bmr\b", "mister "),
+        (r"\bmrs\b", "missus "),
+        (r"\bst\b", "saint "),
+        (r"\bdr\b", "doctor "),
+        (r"\bprof\b", "professor "),
+        (r"\bcapt\b", "captain "),
+        (r"\bgov\b", "governor "),
+        (r"\bald\b", "alderman "),
+        (r"\bgen\b", "general "),
+        (r"\bsen\b", "senator "),
+        (r"\brep\b", "representative "),
+        (r"\bpres\b", "president "),
+        (r"\brev\b", "reverend "),
+        (r"\bhon\b", "honorable "),
+        (r"\basst\b", "assistant "),
+        (r"\bassoc\b", "associate "),
+        (r"\blt\b", "lieutenant "),
+        (r"\bcol\b", "colonel "),
+        (r"\bjr\b", "junior "),
+        (r"\bsr\b", "senior "),
+        (r"\besq\b", "esquire "),
+        // perfect tenses
+        (r"'d been\b", " had been"),
+        (r"'s been\b", " has been"),
+        (r"'d gone\b", " had gone"),
+        (r"'s gone\b", " has gone"),
+        (r"'d done\b", " had done"),
+        (r"'s got\b", " has got"),
+        // general contractions
+        (r"n't\b", " not"),
+        (r"'re\b", " are"),
+        (r"'s\b", " is"),
+        (r"'d\b", " would"),
+        (r"'ll\b", " will"),
+        (r"'t\b", " not"),
+        (r"'ve\b", " have"),
+        (r"'m\b", " am"),
+    ]
+    .into_iter()
+    .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), replacement))
+    .collect();
+}
+
+/// Common British spellings & their American equivalents, a subset of Whisper's `english.json`.
+const SPELLINGS: [(&str, &str); 64] = [
+    ("aeroplane", "airplane"),
+    ("ageing", "aging"),
+    ("aluminium", "aluminum"),
+    ("analyse", "analyze"),
+    ("analysed", "analyzed"),
+    ("apologise", "apologize"),
+    ("behaviour", "behavior"),
+    ("behaviours", "behaviors"),
+    ("cancelled", "canceled"),
+    ("cancelling", "canceling"),
+    ("catalogue", "catalog"),
+    ("centre", "center"),
+    ("centres", "centers"),
+    ("cheque", "check"),
+    ("colour", "color"),
+    ("coloured", "colored"),
+    ("colours", "colors"),
+    ("defence", "defense"),
+    ("dialogue", "dialog"),
+    ("emphasise", "emphasize"),
+    ("favour", "favor"),
+    ("favourite", "favorite"),
+    ("fibre", "fiber"),
+    ("flavour", "flavor"),
+    ("grey", "gray"),
+    ("harbour", "harbor"),
+    ("honour", "honor"),
+    ("honoured", "honored"),
+    ("humour", "humor"),
+    ("jewellery", "jewelry"),
+    ("labour", "labor"),
+    ("licence", "license"),
+    ("litre", "liter"),
+    ("litres", "liters"),
+    ("metre", "meter"),
+    ("metres", "meters"),
+    ("modelling", "modeling"),
+    ("neighbour", "neighbor"),
+    ("neighbours", "neighbors"),
+    ("offence", "offense"),
+    ("organisation", "organization"),
+    ("organisations", "organizations"),
+    ("organise", "organize"),
+    ("organised", "organized"),
+    ("practise", "practice"),
+    ("programme", "program"),
+    ("programmes", "programs"),
+    ("realise", "realize"),
+    ("realised", "realized"),
+    ("recognise", "recognize"),
+    ("recognised", "recognized"),
+    ("rumour", "rumor"),
+    ("savour", "savor"),
+    ("theatre", "theater"),
+    ("theatres", "theaters"),
+    ("travelled", "traveled"),
+    ("traveller", "traveler"),
+    ("travelling", "traveling"),
+    ("tyre", "tire"),
+    ("tyres", "tires"),
+    ("valour", "valor"),
+    ("vapour", "vapor"),
+    ("whilst", "while"),
+    ("yoghurt", "yogurt"),
+];
+
+/// Letters that don't decompose into a base letter & a combining mark.
+const ADDITIONAL_DIACRITICS: [(char, &str); 16] = [
+    ('œ', "oe"),
+    ('Œ', "OE"),
+    ('ø', "o"),
+    ('Ø', "O"),
+    ('æ', "ae"),
+    ('Æ', "AE"),
+    ('ß', "ss"),
+    ('ẞ', "SS"),
+    ('đ', "d"),
+    ('Đ', "D"),
+    ('ð', "d"),
+    ('Ð', "D"),
+    ('þ', "th"),
+    ('Þ', "th"),
+    ('ł', "l"),
+    ('Ł', "L"),
+];
+
+/// Brings reference & hypothesis transcripts into a common form before they are compared,
+/// so that error rates aren't dominated by casing, punctuation & spelling conventions.
+pub trait TextNormalizer {
+    fn normalize(&self, text: &str) -> String;
+}
+
+/// Replaces marks, symbols & punctuation with spaces, as Whisper's `remove_symbols`.
+pub fn remove_symbols(text: &str) -> String {
+    let mut text = NormalizedString::from(text);
+    text.nfkc();
+    SYMBOLS.replace_all(text.get(), " ").into_owned()
+}
+
+/// As [remove_symbols], also stripping diacritics, "café" -> "cafe".
+/// Symbols used in numbers, ".%$¢€£", are kept if `keep_numeric`.
+pub fn remove_symbols_and_diacritics(text: &str, keep_numeric: bool) -> String {
+    let mut decomposed = NormalizedString::from(text);
+    decomposed.nfkd();
+    let mut text = String::with_capacity(decomposed.get().len());
+    for c in decomposed.get().chars() {
+        match ADDITIONAL_DIACRITICS.iter().find(|(d, _)| *d == c) {
+            Some((_, replacement)) => text.push_str(replacement),
+            None => text.push(c),
+        }
+    }
+    let text = MARKS.replace_all(&text, "");
+    match keep_numeric {
+        true => NON_NUMERIC_SYMBOLS.replace_all(&text, " ").into_owned(),
+        false => SYMBOLS.replace_all(&text, " ").into_owned(),
+    }
+}
+
+/// Language agnostic normalisation, as Whisper's `BasicTextNormalizer`: lowercases,
+/// drops bracketed & parenthesised text, and replaces symbols & punctuation with spaces.
+#[derive(Debug, Default, Clone)]
+pub struct BasicTextNormalizer {
+    pub remove_diacritics: bool,
+}
+
+impl BasicTextNormalizer {
+    pub fn new(remove_diacritics: bool) -> Self {
+        Self { remove_diacritics }
+    }
+}
+
+impl TextNormalizer for BasicTextNormalizer {
+    fn normalize(&self, text: &str) -> String {
+        let text = text.to_lowercase();
+        let text = BRACKETED.replace_all(&text, "");
+        let text = PARENTHESISED.replace_all(&text, "");
+        let text = match self.remove_diacritics {
+            true => remove_symbols_and_diacritics(&text, false),
+            false => remove_symbols(&text),
+        };
+        WHITESPACE
+            .replace_all(&text.to_lowercase(), " ")
+            .trim()
+            .to_string()
+    }
+}
+
+/// Maps British spellings to American ones, word by word.
+#[derive(Debug, Clone)]
+pub struct EnglishSpellingNormalizer {
+    mapping: HashMap<String, String>,
+}
+
+impl Default for EnglishSpellingNormalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnglishSpellingNormalizer {
+    /// The built in mapping of common spellings.
+    pub fn new() -> Self {
+        Self::from_mapping(
+            SPELLINGS
+                .iter()
+                .map(|(british, american)| (british.to_string(), american.to_string()))
+                .collect(),
+        )
+    }
+
+    pub fn from_mapping(mapping: HashMap<String, String>) -> Self {
+        Self { mapping }
+    }
+
+    /// Loads a `{"british": "american"}` mapping, e.g Whisper's full `english.json`.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json).map(Self::from_mapping)
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        text.split_whitespace()
+            .map(|word| self.mapping.get(word).map_or(word, String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Whisper's `EnglishTextNormalizer`, used to compute its published English error rates.
+///
+/// On top of [BasicTextNormalizer] it drops fillers ("um", "uh"), expands contractions
+/// & titles, writes numbers as digits and standardises spellings.
+#[derive(Debug, Default)]
+pub struct EnglishTextNormalizer {
+    numbers: EnglishNumberNormalizer,
+    spellings: EnglishSpellingNormalizer,
+}
+
+impl EnglishTextNormalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_spellings(mut self, spellings: EnglishSpellingNormalizer) -> Self {
+        self.spellings = spellings;
+        self
+    }
+}
+
+impl TextNormalizer for EnglishTextNormalizer {
+    fn normalize(&self, text: &str) -> String {
+        let text = text.to_lowercase();
+        let text = BRACKETED.replace_all(&text, "");
+        let text = PARENTHESISED.replace_all(&text, "");
+        let text = FILLERS.replace_all(&text, "");
+        let mut text = SPACED_APOSTROPHE.replace_all(&text, "'").into_owned();
+        for (pattern, replacement) in REPLACERS.iter() {
+            text = pattern.replace_all(&text, *replacement).into_owned();
+        }
+        let text = DIGIT_COMMA.replace_all(&text, "${1}${2}");
+        let text = PERIOD.replace_all(&text, " ${1}");
+        let text = remove_symbols_and_diacritics(&text, true);
+        let text = self.numbers.normalize(&text);
+        let text = self.spellings.normalize(&text);
+        //symbols not attached to a number
+        let text = PREFIX_SYMBOL.replace_all(&text, " ${1}");
+        let text = SUFFIX_SYMBOL.replace_all(&text, "${1} ");
+        WHITESPACE.replace_all(&text, " ").trim().to_string()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english_normalisation() {
+        let normalizer = EnglishTextNormalizer::new();
+        let cases = [
+            (
+                "And so, my fellow Americans: ask not what your country can do for you.",
+                "and so my fellow americans ask not what your country can do for you",
+            ),
+            (
+                "Um, I can't believe it's [MUSIC] over!",
+                "i can not believe it is over",
+            ),
+            (
+                "Mr. Smith paid $1,500 for the colour TV.",
+                "mister smith paid $1500 for the color tv",
+            ),
+            ("It's twenty five percent off (approx.)", "it is 25% off"),
+            ("Café crème à la façon", "cafe creme a la facon"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(normalizer.normalize(text), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn basic_normalisation_keeps_diacritics() {
+        let text = "¿Qué tal? <laughs> Très-bien!";
+        assert_eq!(
+            BasicTextNormalizer::new(false).normalize(text),
+            "qué tal très bien"
+        );
+        assert_eq!(
+            BasicTextNormalizer::new(true).normalize(text),
+            "que tal tres bien"
+        );
+    }
+
+    #[test]
+    fn spellings_load_from_json() {
+        let spellings = EnglishSpellingNormalizer::from_json(r#"{"colour": "color"}"#).unwrap();
+        assert_eq!(
+            spellings.normalize("colour of the centre"),
+            "color of the centre"
+        );
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use regex::{Captures, Regex};
+
+lazy_static::lazy_static! {
+    static ref AND_A_HALF: Regex = Regex::new(r"\band\s+a\s+half\b").unwrap();
+    static ref LETTER_DIGIT: Regex = Regex::new(r"([a-z])([0-9])").unwrap();
+    static ref DIGIT_LETTER: Regex = Regex::new(r"([0-9])([a-z])").unwrap();
+    static ref DIGIT_SUFFIX: Regex = Regex::new(r"([0-9])\s+(st|nd|rd|th|s)\b").unwrap();
+    static ref NUMERIC: Regex = Regex::new(r"^\d+(\.\d+)?$").unwrap();
+    static ref CENTS: Regex = Regex::new(r"([€£$])([0-9]+) (?:and )?¢([0-9]{1,2})\b").unwrap();
+    static ref ONLY_CENTS: Regex = Regex::new(r"[€£$]0\.([0-9]{1,2})\b").unwrap();
+    static ref ONE: Regex = Regex::new(r"\b1(s?)\b").unwrap();
+}
+
+const ONES: [&str; 19] = [
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 8] = [
+    "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const MULTIPLIERS: [&str; 12] = [
+    "hundred",
+    "thousand",
+    "million",
+    "billion",
+    "trillion",
+    "quadrillion",
+    "quintillion",
+    "sextillion",
+    "septillion",
+    "octillion",
+    "nonillion",
+    "decillion",
+];
+
+/// A number being spelled out, either a plain integer or digits that can't be summed,
+/// e.g decimals or digit sequences.
+#[derive(Debug, Clone)]
+enum Value {
+    Int(u128),
+    Digits(String),
+}
+
+impl Value {
+    /// The digits to append to, a leading zero is dropped.
+    fn digits(value: Option<&Value>) -> String {
+        match value {
+            None | Some(Value::Int(0)) => String::new(),
+            Some(value) => value.to_string(),
+        }
+    }
+
+    /// `self * multiplier`, if it is an integer.
+    fn scale(&self, multiplier: u128) -> Option<u128> {
+        let digits = self.to_string();
+        let (whole, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+        let n = format!("{whole}{fraction}").parse::<u128>().ok()?;
+        let denominator = 10u128.checked_pow(fraction.len() as u32)?;
+        let product = n.checked_mul(multiplier)?;
+        (product % denominator == 0).then_some(product / denominator)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Digits(digits) => write!(f, "{digits}"),
+        }
+    }
+}
+
+/// Converts spelled out numbers to arabic numerals, as Whisper's `EnglishNumberNormalizer`:
+///
+/// - "twenty five" -> "25", "one thousand and two" -> "1002"
+/// - "one point five million" -> "1500000", "double oh seven" -> "007"
+/// - "twenty first" -> "21st", "the nineties" -> "the 90s"
+/// - "five dollars and twenty cents" -> "$5.20", "fifty percent" -> "50%"
+///
+/// A lone "one" is left spelled out, as it is more often a pronoun than a number.
+#[derive(Debug)]
+pub struct EnglishNumberNormalizer {
+    zeros: HashSet<&'static str>,
+    ones: HashMap<String, u128>,
+    ones_suffixed: HashMap<String, (u128, &'static str)>,
+    tens: HashMap<String, u128>,
+    tens_suffixed: HashMap<String, (u128, &'static str)>,
+    multipliers: HashMap<String, u128>,
+    multipliers_suffixed: HashMap<String, (u128, &'static str)>,
+    preceding_prefixers: HashMap<&'static str, char>,
+    following_prefixers: HashMap<&'static str, char>,
+    prefixes: HashSet<char>,
+    specials: HashSet<&'static str>,
+    words: HashSet<String>,
+}
+
+impl Default for EnglishNumberNormalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnglishNumberNormalizer {
+    pub fn new() -> Self {
+        let zeros = HashSet::from(["o", "oh", "zero"]);
+        let ones = (1..)
+            .zip(ONES)
+            .map(|(n, name)| (name.to_string(), n))
+            .collect::<HashMap<_, _>>();
+
+        let mut ones_suffixed = HashMap::new();
+        for (name, n) in &ones {
+            let plural = match name.as_str() {
+                "six" => "sixes".to_string(),
+                _ => format!("{name}s"),
+            };
+            ones_suffixed.insert(plural, (*n, "s"));
+            let ordinal = match name.as_str() {
+                "one" | "two" | "three" | "five" | "twelve" => continue,
+                "nine" => "ninth".to_string(),
+                name if name.ends_with('t') => format!("{name}h"),
+                name => format!("{name}th"),
+            };
+            ones_suffixed.insert(ordinal, (*n, "th"));
+        }
+        for (ordinal, n, suffix) in [
+            ("zeroth", 0, "th"),
+            ("first", 1, "st"),
+            ("second", 2, "nd"),
+            ("third", 3, "rd"),
+            ("fifth", 5, "th"),
+            ("twelfth", 12, "th"),
+        ] {
+            ones_suffixed.insert(ordinal.to_string(), (n, suffix));
+        }
+
+        let tens = (2..)
+            .zip(TENS)
+            .map(|(n, name)| (name.to_string(), n * 10))
+            .collect::<HashMap<_, _>>();
+        let tens_suffixed = tens
+            .iter()
+            .flat_map(|(name, n)| {
+                [
+                    (name.replace('y', "ies"), (*n, "s")),
+                    (name.replace('y', "ieth"), (*n, "th")),
+                ]
+            })
+            .collect::<HashMap<_, _>>();
+
+        let multipliers = std::iter::once(100)
+            .chain((1..).map(|k| 1000u128.pow(k)))
+            .zip(MULTIPLIERS)
+            .map(|(n, name)| (name.to_string(), n))
+            .collect::<HashMap<_, _>>();
+        let multipliers_suffixed = multipliers
+            .iter()
+            .flat_map(|(name, n)| {
+                [
+                    (format!("{name}s"), (*n, "s")),
+                    (format!("{name}th"), (*n, "th")),
+                ]
+            })
+            .collect::<HashMap<_, _>>();
+
+        let preceding_prefixers = HashMap::from([
+            ("minus", '-'),
+            ("negative", '-'),
+            ("plus", '+'),
+            ("positive", '+'),
+        ]);
+        let following_prefixers = HashMap::from([
+            ("pound", '£'),
+            ("pounds", '£'),
+            ("euro", '€'),
+            ("euros", '€'),
+            ("dollar", '$'),
+            ("dollars", '$'),
+            ("cent", '¢'),
+            ("cents", '¢'),
+        ]);
+        let prefixes = preceding_prefixers
+            .values()
+            .chain(following_prefixers.values())
+            .copied()
+            .collect();
+        let specials = HashSet::from(["and", "double", "triple", "point"]);
+
+        let mut words = HashSet::new();
+        words.extend(zeros.iter().map(|w| w.to_string()));
+        words.extend(ones.keys().cloned());
+        words.extend(ones_suffixed.keys().cloned());
+        words.extend(tens.keys().cloned());
+        words.extend(tens_suffixed.keys().cloned());
+        words.extend(multipliers.keys().cloned());
+        words.extend(multipliers_suffixed.keys().cloned());
+        words.extend(preceding_prefixers.keys().map(|w| w.to_string()));
+        words.extend(following_prefixers.keys().map(|w| w.to_string()));
+        words.extend(["per", "percent"].map(String::from));
+        words.extend(specials.iter().map(|w| w.to_string()));
+
+        Self {
+            zeros,
+            ones,
+            ones_suffixed,
+            tens,
+            tens_suffixed,
+            multipliers,
+            multipliers_suffixed,
+            preceding_prefixers,
+            following_prefixers,
+            prefixes,
+            specials,
+            words,
+        }
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        let text = self.preprocess(text);
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        self.postprocess(&self.process_words(&words).join(" "))
+    }
+
+    fn is_decimal(&self, word: &str) -> bool {
+        self.zeros.contains(word) || self.ones.contains_key(word) || self.tens.contains_key(word)
+    }
+
+    /// Replaces "<number> and a half" with "<number> point five" & splits numbers from
+    /// the letters around them, keeping ordinal & plural suffixes attached.
+    fn preprocess(&self, text: &str) -> String {
+        let segments = AND_A_HALF.split(text).collect::<Vec<_>>();
+        let mut results = vec![];
+        for (i, segment) in segments.iter().enumerate() {
+            if segment.trim().is_empty() {
+                continue;
+            }
+            results.push(*segment);
+            if i == segments.len() - 1 {
+                continue;
+            }
+            let last_word = segment.split_whitespace().last().unwrap_or_default();
+            match self.is_decimal(last_word) || self.multipliers.contains_key(last_word) {
+                true => results.push("point five"),
+                false => results.push("and a half"),
+            }
+        }
+        let text = results.join(" ");
+        let text = LETTER_DIGIT.replace_all(&text, "$1 $2");
+        let text = DIGIT_LETTER.replace_all(&text, "$1 $2");
+        DIGIT_SUFFIX.replace_all(&text, "$1$2").into_owned()
+    }
+
+    /// Merges currencies & cents, "$2 and ¢7" -> "$2.07", and spells out lone ones.
+    fn postprocess(&self, text: &str) -> String {
+        let text = CENTS.replace_all(text, |c: &Captures| {
+            let cents = c[3].parse::<u32>().unwrap();
+            format!("{}{}.{:02}", &c[1], &c[2], cents)
+        });
+        let text = ONLY_CENTS.replace_all(&text, |c: &Captures| {
+            format!("¢{}", c[1].parse::<u32>().unwrap())
+        });
+        ONE.replace_all(&text, "one$1").into_owned()
+    }
+
+    fn process_words(&self, words: &[&str]) -> Vec<String> {
+        let mut state = Numbers::default();
+        let mut skip = false;
+        for (i, &current) in words.iter().enumerate() {
+            if skip {
+                skip = false;
+                continue;
+            }
+            let prev = i.checked_sub(1).map(|p| words[p]);
+            let next = words.get(i + 1).copied();
+            let next_is_word = next.is_some_and(|n| self.words.contains(n));
+            let next_is_numeric = next.is_some_and(|n| NUMERIC.is_match(n));
+            let prev_in =
+                |table: &HashMap<String, u128>| prev.is_some_and(|p| table.contains_key(p));
+
+            let has_prefix = current
+                .chars()
+                .next()
+                .is_some_and(|c| self.prefixes.contains(&c));
+            let without_prefix = match has_prefix {
+                true => &current[current.chars().next().unwrap().len_utf8()..],
+                false => current,
+            };
+
+            if NUMERIC.is_match(without_prefix) {
+                //arabic numbers, potentially with a sign or currency
+                if let Some(value) = &state.value {
+                    match value {
+                        Value::Digits(digits) if digits.ends_with('.') => {
+                            state.value = Some(Value::Digits(format!("{digits}{current}")));
+                            continue;
+                        }
+                        _ => state.output_value(),
+                    }
+                }
+                if has_prefix {
+                    state.prefix = current.chars().next();
+                }
+                state.value = Some(match without_prefix.parse::<u128>() {
+                    Ok(n) => Value::Int(n),
+                    Err(_) => Value::Digits(without_prefix.to_string()),
+                });
+            } else if !self.words.contains(current) {
+                state.output_value();
+                state.output(current);
+            } else if self.zeros.contains(current) {
+                let digits = Value::digits(state.value.as_ref());
+                state.value = Some(Value::Digits(format!("{digits}0")));
+            } else if let Some(&ones) = self.ones.get(current) {
+                state.value = Some(match state.value.take() {
+                    None => Value::Int(ones),
+                    Some(value) if matches!(value, Value::Digits(_)) || prev_in(&self.ones) => {
+                        let digits = value.to_string();
+                        match prev_in(&self.tens) && ones < 10 {
+                            true => Value::Digits(format!("{}{ones}", &digits[..digits.len() - 1])),
+                            false => Value::Digits(format!("{digits}{ones}")),
+                        }
+                    }
+                    Some(Value::Int(n)) if (ones < 10 && n % 10 == 0) || n % 100 == 0 => {
+                        Value::Int(n + ones)
+                    }
+                    Some(value) => Value::Digits(format!("{value}{ones}")),
+                });
+            } else if let Some(&(ones, suffix)) = self.ones_suffixed.get(current) {
+                let number = match state.value.take() {
+                    None => ones.to_string(),
+                    Some(value) if matches!(value, Value::Digits(_)) || prev_in(&self.ones) => {
+                        let digits = value.to_string();
+                        match prev_in(&self.tens) && ones < 10 {
+                            true => format!("{}{ones}", &digits[..digits.len() - 1]),
+                            false => format!("{digits}{ones}"),
+                        }
+                    }
+                    Some(Value::Int(n)) if (ones < 10 && n % 10 == 0) || n % 100 == 0 => {
+                        (n + ones).to_string()
+                    }
+                    Some(value) => format!("{value}{ones}"),
+                };
+                state.output(&format!("{number}{suffix}"));
+            } else if let Some(&tens) = self.tens.get(current) {
+                state.value = Some(match state.value.take() {
+                    None => Value::Int(tens),
+                    Some(Value::Int(n)) if n % 100 == 0 => Value::Int(n + tens),
+                    Some(value) => Value::Digits(format!("{value}{tens}")),
+                });
+            } else if let Some(&(tens, suffix)) = self.tens_suffixed.get(current) {
+                let number = match state.value.take() {
+                    None => tens.to_string(),
+                    Some(Value::Int(n)) if n % 100 == 0 => (n + tens).to_string(),
+                    Some(value) => format!("{value}{tens}"),
+                };
+                state.output(&format!("{number}{suffix}"));
+            } else if let Some(&multiplier) = self.multipliers.get(current) {
+                match state.value.take() {
+                    None => state.value = Some(Value::Int(multiplier)),
+                    Some(value) => match Self::multiply(&value, multiplier) {
+                        Some(n) => state.value = Some(Value::Int(n)),
+                        None => {
+                            state.value = Some(value);
+                            state.output_value();
+                            state.value = Some(Value::Int(multiplier));
+                        }
+                    },
+                }
+            } else if let Some(&(multiplier, suffix)) = self.multipliers_suffixed.get(current) {
+                match state.value.take() {
+                    None => state.output(&format!("{multiplier}{suffix}")),
+                    Some(value) => match Self::multiply(&value, multiplier) {
+                        Some(n) => state.output(&format!("{n}{suffix}")),
+                        None => {
+                            state.value = Some(value);
+                            state.output_value();
+                            state.output(&format!("{multiplier}{suffix}"));
+                        }
+                    },
+                }
+            } else if let Some(&prefix) = self.preceding_prefixers.get(current) {
+                //a sign only applies if it precedes a number
+                state.output_value();
+                match next_is_word || next_is_numeric {
+                    true => state.prefix = Some(prefix),
+                    false => state.output(current),
+                }
+            } else if let Some(&prefix) = self.following_prefixers.get(current) {
+                //a currency only applies if it follows a number
+                match state.value {
+                    Some(_) => {
+                        state.prefix = Some(prefix);
+                        state.output_value();
+                    }
+                    None => state.output(current),
+                }
+            } else if current == "percent" || current == "per" {
+                match state.value.take() {
+                    Some(value) if current == "percent" => state.output(&format!("{value}%")),
+                    Some(value) if next == Some("cent") => {
+                        state.output(&format!("{value}%"));
+                        skip = true;
+                    }
+                    Some(value) => {
+                        state.output(&value.to_string());
+                        state.output(current);
+                    }
+                    None => state.output(current),
+                }
+            } else if self.specials.contains(current) {
+                if !next_is_word && !next_is_numeric {
+                    //only special if the next word can be numeric
+                    state.output_value();
+                    state.output(current);
+                } else if current == "and" {
+                    //"and" is dropped after hundreds, thousands, etc.
+                    if !prev_in(&self.multipliers) {
+                        state.output_value();
+                        state.output(current);
+                    }
+                } else if current == "double" || current == "triple" {
+                    let next = next.unwrap();
+                    if self.ones.contains_key(next) || self.zeros.contains(next) {
+                        let repeats = if current == "double" { 2 } else { 3 };
+                        let ones = self.ones.get(next).copied().unwrap_or(0);
+                        let digits = Value::digits(state.value.as_ref());
+                        state.value = Some(Value::Digits(format!(
+                            "{digits}{}",
+                            ones.to_string().repeat(repeats)
+                        )));
+                        skip = true;
+                    } else {
+                        state.output_value();
+                        state.output(current);
+                    }
+                } else if self.is_decimal(next.unwrap()) || next_is_numeric {
+                    //point
+                    let digits = Value::digits(state.value.as_ref());
+                    state.value = Some(Value::Digits(format!("{digits}.")));
+                }
+            }
+        }
+        state.output_value();
+        state.out
+    }
+
+    /// `value` times a multiplier, applied to its last three digits so that
+    /// "two thousand three hundred" is 2300.
+    fn multiply(value: &Value, multiplier: u128) -> Option<u128> {
+        match value {
+            Value::Int(n) if *n != 0 => {
+                let residual = (n % 1000).checked_mul(multiplier)?;
+                (n / 1000 * 1000).checked_add(residual)
+            }
+            value => value.scale(multiplier),
+        }
+    }
+}
+
+/// Words output so far, along with the number & prefix being built up.
+#[derive(Debug, Default)]
+struct Numbers {
+    out: Vec<String>,
+    prefix: Option<char>,
+    value: Option<Value>,
+}
+
+impl Numbers {
+    fn output(&mut self, word: &str) {
+        let mut result = self.prefix.take().map(String::from).unwrap_or_default();
+        result.push_str(word);
+        self.out.push(result);
+        self.value = None;
+    }
+
+    fn output_value(&mut self) {
+        if let Some(value) = self.value.take() {
+            self.output(&value.to_string());
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spelled_out_numbers() {
+        let normalizer = EnglishNumberNormalizer::new();
+        let cases = [
+            ("twenty five", "25"),
+            ("one thousand and two", "1002"),
+            ("two thousand three hundred and forty five", "2345"),
+            ("one point five million", "1500000"),
+            ("double oh seven", "007"),
+            ("nineteen ninety nine", "1999"),
+            ("the twenty first century", "the 21st century"),
+            ("the nineties", "the 90s"),
+            ("five dollars and twenty cents", "$5.20"),
+            ("fifty percent", "50%"),
+            ("minus three", "-3"),
+            ("two and a half", "2.5"),
+            ("one of them", "one of them"),
+            ("room 101 and room 4", "room 101 and room 4"),
+        ];
+        for (spoken, expected) in cases {
+            assert_eq!(normalizer.normalize(spoken), expected, "{spoken}");
+        }
+    }
+}
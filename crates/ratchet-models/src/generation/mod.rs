@@ -0,0 +1,35 @@
+//! Model agnostic text generation: logit processing, sampling & streaming.
+mod options;
+mod processors;
+mod sampler;
+mod stop;
+mod stream;
+
+pub use options::*;
+pub use processors::*;
+pub use sampler::*;
+pub use stop::*;
+pub use stream::*;
+
+use ratchet::Tensor;
+
+/// A decoder-only model predicting the next token, which caches the keys & values of the
+/// tokens it has seen.
+pub trait CausalLM {
+    /// Logits of the final position of `tokens`, [1, 1, n_vocab], before they are resolved.
+    ///
+    /// `tokens` follow the `cache_len()` tokens already in the cache.
+    fn logits(&self, tokens: &[u32]) -> anyhow::Result<Tensor>;
+
+    /// Adds the `n` tokens passed to the last [CausalLM::logits] call to the cache, once
+    /// their logits are resolved.
+    fn advance(&mut self, n: usize);
+
+    fn reset(&mut self);
+
+    fn cache_len(&self) -> usize;
+
+    fn max_seq_len(&self) -> usize;
+
+    fn eos_tokens(&self) -> Vec<u32>;
+}
@@ -0,0 +1,218 @@
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    FrequencyPenalty, LogitMutator, MinP, RepeatPenalty, Sampler, StopStrings, Temperature, TopK,
+    TopP,
+};
+
+#[cfg_attr(
+    target_arch = "wasm32",
+    wasm_bindgen,
+    derive(serde::Serialize, serde::Deserialize)
+)]
+#[derive(Debug, Clone)]
+pub struct GenerationOptions {
+    pub(crate) max_tokens: u32,                 // default: 256
+    pub(crate) temperature: f32,                // default: 0.0
+    pub(crate) top_k: Option<u32>,              // default: None
+    pub(crate) top_p: Option<f32>,              // default: None
+    pub(crate) min_p: Option<f32>,              // default: None
+    pub(crate) repetition_penalty: Option<f32>, // default: None
+    pub(crate) repetition_last_n: u32,          // default: 64
+    pub(crate) frequency_penalty: Option<f32>,  // default: None
+    pub(crate) presence_penalty: Option<f32>,   // default: None
+    pub(crate) stop: Vec<String>,               // default: []
+    pub(crate) seed: Option<u64>,               // default: None
+}
+
+impl GenerationOptions {
+    /// The chain of mutators applied to the logits before sampling: penalties, temperature,
+    /// then top-k, top-p & min-p truncation.
+    ///
+    /// Tokens from `sample_begin` onwards were generated, those before are the prompt.
+    pub fn logit_mutators(&self, sample_begin: usize) -> Vec<Box<dyn LogitMutator>> {
+        let mut mutators: Vec<Box<dyn LogitMutator>> = vec![];
+        if let Some(penalty) = self.repetition_penalty.filter(|p| *p != 1.) {
+            let last_n = self.repetition_last_n as usize;
+            mutators.push(Box::new(RepeatPenalty::new(penalty, last_n)));
+        }
+        let frequency = self.frequency_penalty.unwrap_or(0.);
+        let presence = self.presence_penalty.unwrap_or(0.);
+        if frequency != 0. || presence != 0. {
+            mutators.push(Box::new(FrequencyPenalty::new(
+                frequency,
+                presence,
+                sample_begin,
+            )));
+        }
+        if self.temperature > 0. {
+            mutators.push(Box::new(Temperature::new(self.temperature)));
+            if let Some(k) = self.top_k {
+                mutators.push(Box::new(TopK::new(k as usize)));
+            }
+            if let Some(p) = self.top_p {
+                mutators.push(Box::new(TopP::new(p)));
+            }
+            if let Some(min_p) = self.min_p {
+                mutators.push(Box::new(MinP::new(min_p)));
+            }
+        }
+        mutators
+    }
+
+    pub fn sampler(&self) -> Sampler {
+        Sampler::new(self.temperature, self.seed)
+    }
+
+    pub fn stop_strings(&self) -> StopStrings {
+        StopStrings::new(self.stop.clone())
+    }
+
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens as usize
+    }
+}
+
+impl Default for GenerationOptions {
+    fn default() -> Self {
+        GenerationOptionsBuilder::new().options()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct GenerationOptionsBuilder {
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    top_k: Option<u32>,
+    top_p: Option<f32>,
+    min_p: Option<f32>,
+    repetition_penalty: Option<f32>,
+    repetition_last_n: Option<u32>,
+    frequency_penalty: Option<f32>,
+    presence_penalty: Option<f32>,
+    stop: Vec<String>,
+    seed: Option<u64>,
+}
+
+impl Default for GenerationOptionsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+impl GenerationOptionsBuilder {
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(constructor))]
+    pub fn new() -> GenerationOptionsBuilder {
+        GenerationOptionsBuilder {
+            max_tokens: Some(256),
+            temperature: Some(0.0),
+            top_k: None,
+            top_p: None,
+            min_p: None,
+            repetition_penalty: None,
+            repetition_last_n: Some(64),
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: vec![],
+            seed: None,
+        }
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setMaxTokens"))]
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// 0 for greedy decoding, top-k, top-p & min-p only apply when sampling.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setTemperature"))]
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setTopK"))]
+    pub fn top_k(mut self, top_k: u32) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setTopP"))]
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setMinP"))]
+    pub fn min_p(mut self, min_p: f32) -> Self {
+        self.min_p = Some(min_p);
+        self
+    }
+
+    /// Applied to the last `repetition_last_n` tokens, see [crate::RepeatPenalty].
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setRepetitionPenalty"))]
+    pub fn repetition_penalty(mut self, repetition_penalty: f32) -> Self {
+        self.repetition_penalty = Some(repetition_penalty);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setRepetitionLastN"))]
+    pub fn repetition_last_n(mut self, repetition_last_n: u32) -> Self {
+        self.repetition_last_n = Some(repetition_last_n);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setFrequencyPenalty"))]
+    pub fn frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setPresencePenalty"))]
+    pub fn presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "addStop"))]
+    pub fn stop(mut self, stop: String) -> Self {
+        self.stop.push(stop);
+        self
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setSeed"))]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn build(&self) -> GenerationOptions {
+        self.options()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn build(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.options()).unwrap()
+    }
+}
+
+impl GenerationOptionsBuilder {
+    fn options(&self) -> GenerationOptions {
+        GenerationOptions {
+            max_tokens: self.max_tokens.unwrap_or(256),
+            temperature: self.temperature.unwrap_or(0.0),
+            top_k: self.top_k,
+            top_p: self.top_p,
+            min_p: self.min_p,
+            repetition_penalty: self.repetition_penalty,
+            repetition_last_n: self.repetition_last_n.unwrap_or(64),
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            stop: self.stop.clone(),
+            seed: self.seed,
+        }
+    }
+}
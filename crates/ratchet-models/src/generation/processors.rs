@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use ndarray::{ArrayViewMut1, Axis, Ix2};
+use ratchet::Tensor;
+
+use crate::LogitMutator;
+
+/// Applies `f` to each row of the [batch_size, n_vocab] `logits`.
+fn map_rows(
+    logits: Tensor,
+    mut f: impl FnMut(usize, ArrayViewMut1<f32>),
+) -> anyhow::Result<Tensor> {
+    let mut nd_logits = logits.into_ndarray::<f32>().into_dimensionality::<Ix2>()?;
+    for (k, row) in nd_logits.axis_iter_mut(Axis(0)).enumerate() {
+        f(k, row);
+    }
+    Ok(Tensor::from(nd_logits.into_dyn()))
+}
+
+/// Probabilities of a row of logits.
+fn softmax(row: &ArrayViewMut1<f32>) -> Vec<f32> {
+    let max = row.fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+    let exp = row.iter().map(|&x| (x - max).exp()).collect::<Vec<_>>();
+    let sum = exp.iter().sum::<f32>();
+    exp.into_iter().map(|x| x / sum).collect()
+}
+
+/// Divides the logits by `temperature`, flattening the distribution above 1 & sharpening it
+/// below.
+#[derive(Debug, Clone, derive_new::new)]
+pub struct Temperature {
+    pub temperature: f32,
+}
+
+impl LogitMutator for Temperature {
+    fn apply(&self, logits: Tensor, _: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        anyhow::ensure!(self.temperature > 0., "Temperature must be positive");
+        map_rows(logits, |_, mut row| {
+            row.mapv_inplace(|x| x / self.temperature)
+        })
+    }
+}
+
+/// Keeps the `k` most likely tokens, ties with the k-th included.
+#[derive(Debug, Clone, derive_new::new)]
+pub struct TopK {
+    pub k: usize,
+}
+
+impl LogitMutator for TopK {
+    fn apply(&self, logits: Tensor, _: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        map_rows(logits, |_, mut row| {
+            if self.k == 0 || self.k >= row.len() {
+                return;
+            }
+            let mut sorted = row.to_vec();
+            let (_, kth, _) = sorted.select_nth_unstable_by(self.k - 1, |a, b| b.total_cmp(a));
+            let kth = *kth;
+            row.mapv_inplace(|x| if x < kth { f32::NEG_INFINITY } else { x });
+        })
+    }
+}
+
+/// Nucleus sampling, keeps the most likely tokens until their cumulative probability
+/// reaches `p`.
+#[derive(Debug, Clone, derive_new::new)]
+pub struct TopP {
+    pub p: f32,
+}
+
+impl LogitMutator for TopP {
+    fn apply(&self, logits: Tensor, _: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        map_rows(logits, |_, mut row| {
+            if self.p >= 1. {
+                return;
+            }
+            let probs = softmax(&row);
+            let mut order = (0..probs.len()).collect::<Vec<_>>();
+            order.sort_unstable_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+            let mut cumulative = 0.;
+            for (rank, &token) in order.iter().enumerate() {
+                //The most likely token is always kept
+                if rank > 0 && cumulative >= self.p {
+                    row[token] = f32::NEG_INFINITY;
+                }
+                cumulative += probs[token];
+            }
+        })
+    }
+}
+
+/// Removes tokens less likely than `min_p` times the probability of the most likely token.
+#[derive(Debug, Clone, derive_new::new)]
+pub struct MinP {
+    pub min_p: f32,
+}
+
+impl LogitMutator for MinP {
+    fn apply(&self, logits: Tensor, _: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        map_rows(logits, |_, mut row| {
+            let probs = softmax(&row);
+            let threshold = self.min_p * probs.iter().fold(0f32, |a, &b| a.max(b));
+            for (logit, p) in row.iter_mut().zip(probs) {
+                if p < threshold {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        })
+    }
+}
+
+/// Penalises each token among the last `last_n` tokens of the sequence, prompt included, as in
+/// CTRL: positive logits are divided by `penalty` & negative logits multiplied by it.
+///
+/// Unlike [crate::RepetitionPenalty], no tokens are exempt, so it suits any model.
+#[derive(Debug, Clone, derive_new::new)]
+pub struct RepeatPenalty {
+    pub penalty: f32,
+    pub last_n: usize,
+}
+
+impl LogitMutator for RepeatPenalty {
+    fn apply(&self, logits: Tensor, tokens: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        let nd_tokens = tokens.unwrap().clone().into_ndarray::<i32>();
+        map_rows(logits, |k, mut row| {
+            let sampled = nd_tokens.index_axis(Axis(0), k);
+            let mut seen = sampled
+                .iter()
+                .skip(sampled.len().saturating_sub(self.last_n))
+                .map(|&t| t as usize)
+                .filter(|&t| t < row.len())
+                .collect::<Vec<_>>();
+            seen.sort_unstable();
+            seen.dedup();
+            for token in seen {
+                let logit = &mut row[token];
+                *logit = match *logit > 0. {
+                    true => *logit / self.penalty,
+                    false => *logit * self.penalty,
+                };
+            }
+        })
+    }
+}
+
+/// OpenAI style penalties on the tokens sampled after `sample_begin`: `frequency` is
+/// subtracted once per occurrence, `presence` once if the token occurs at all.
+#[derive(Debug, Clone, derive_new::new)]
+pub struct FrequencyPenalty {
+    pub frequency: f32,
+    pub presence: f32,
+    pub sample_begin: usize,
+}
+
+impl LogitMutator for FrequencyPenalty {
+    fn apply(&self, logits: Tensor, tokens: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        let nd_tokens = tokens.unwrap().clone().into_ndarray::<i32>();
+        map_rows(logits, |k, mut row| {
+            let mut counts = HashMap::<usize, usize>::new();
+            for &token in nd_tokens
+                .index_axis(Axis(0), k)
+                .iter()
+                .skip(self.sample_begin)
+            {
+                *counts.entry(token as usize).or_default() += 1;
+            }
+            let n_vocab = row.len();
+            for (token, count) in counts.into_iter().filter(|(t, _)| *t < n_vocab) {
+                row[token] -= count as f32 * self.frequency + self.presence;
+            }
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use ratchet::{shape, Device};
+
+    fn logits(data: &[f32]) -> Tensor {
+        Tensor::from_data(data, shape![1, data.len()], Device::CPU)
+    }
+
+    fn apply(mutator: impl LogitMutator, data: &[f32], tokens: &[i32]) -> Vec<f32> {
+        let tokens = (!tokens.is_empty())
+            .then(|| Tensor::from_data(tokens, shape![1, tokens.len()], Device::CPU));
+        let result = mutator.apply(logits(data), tokens.as_ref()).unwrap();
+        result.into_ndarray::<f32>().iter().copied().collect()
+    }
+
+    #[test]
+    fn truncation() {
+        let inf = f32::NEG_INFINITY;
+        let data = [1., 3., 2., 3., 0.];
+        assert_eq!(apply(TopK::new(2), &data, &[]), [inf, 3., inf, 3., inf]);
+        assert_eq!(apply(TopK::new(3), &data, &[]), [inf, 3., 2., 3., inf]);
+
+        //Probabilities of roughly [0.64, 0.24, 0.09, 0.03]
+        let data = [3., 2., 1., 0.];
+        assert_eq!(apply(TopP::new(0.5), &data, &[]), [3., inf, inf, inf]);
+        assert_eq!(apply(TopP::new(0.8), &data, &[]), [3., 2., inf, inf]);
+        assert_eq!(apply(MinP::new(0.1), &data, &[]), [3., 2., 1., inf]);
+        assert_eq!(apply(Temperature::new(2.), &data, &[]), [1.5, 1., 0.5, 0.]);
+    }
+
+    #[test]
+    fn penalties() {
+        let data = [2., -2., 2., 2.];
+        let tokens = [0, 1, 2, 2];
+        assert_eq!(
+            apply(RepeatPenalty::new(2., 3), &data, &tokens),
+            [2., -4., 1., 2.]
+        );
+        assert_eq!(
+            apply(FrequencyPenalty::new(0.5, 1., 1), &data, &tokens),
+            [2., -3.5, 0., 2.]
+        );
+    }
+}
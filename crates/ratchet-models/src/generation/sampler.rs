@@ -0,0 +1,76 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+/// Picks the next token from a row of processed logits.
+///
+/// Greedy when `temperature` is 0, otherwise samples from the softmax of the logits, which
+/// the [crate::Temperature] mutator is expected to have already scaled. The same seed yields
+/// the same tokens for the same logits.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    rng: StdRng,
+    temperature: f32,
+}
+
+impl Sampler {
+    pub fn new(temperature: f32, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self { rng, temperature }
+    }
+
+    pub fn sample(&mut self, logits: &[f32]) -> anyhow::Result<u32> {
+        if self.temperature <= 0. {
+            return Self::argmax(logits);
+        }
+        let max = logits.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        anyhow::ensure!(max.is_finite(), "No token can be sampled");
+        let weights = logits.iter().map(|&x| (x - max).exp());
+        let distribution = WeightedIndex::new(weights)?;
+        Ok(distribution.sample(&mut self.rng) as u32)
+    }
+
+    /// The most likely token, the first one on ties.
+    pub fn argmax(logits: &[f32]) -> anyhow::Result<u32> {
+        logits
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| !x.is_nan())
+            .rev()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| i as u32)
+            .ok_or_else(|| anyhow::anyhow!("No token can be sampled"))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_sampling_is_reproducible() {
+        let logits = [0., 1., f32::NEG_INFINITY, 1., 0.5];
+        let draw = |seed| {
+            let mut sampler = Sampler::new(1., Some(seed));
+            (0..32)
+                .map(|_| sampler.sample(&logits).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let tokens = draw(42);
+        assert_eq!(tokens, draw(42));
+        assert!(!tokens.contains(&2));
+        assert!(
+            tokens
+                .iter()
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+                > 1
+        );
+
+        let mut greedy = Sampler::new(0., None);
+        assert_eq!(greedy.sample(&logits).unwrap(), 1);
+    }
+}
@@ -0,0 +1,44 @@
+/// Strings that end generation once they appear in the generated text.
+///
+/// The stop string itself is not part of the output. While streaming, text that could be the
+/// start of a stop string is held back until it is ruled out.
+#[derive(Debug, Clone, Default)]
+pub struct StopStrings(Vec<String>);
+
+impl StopStrings {
+    pub fn new(stop: Vec<String>) -> Self {
+        Self(stop.into_iter().filter(|s| !s.is_empty()).collect())
+    }
+
+    /// Byte offset of the earliest stop string in `text`.
+    pub fn find(&self, text: &str) -> Option<usize> {
+        self.0.iter().filter_map(|s| text.find(s.as_str())).min()
+    }
+
+    /// Length of the longest suffix of `text` that a stop string starts with.
+    pub fn partial_len(&self, text: &str) -> usize {
+        text.char_indices()
+            .map(|(i, _)| &text[i..])
+            .find(|suffix| self.0.iter().any(|s| s.starts_with(suffix)))
+            .map_or(0, str::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_complete_and_partial_stops() {
+        let stop = StopStrings::new(vec!["\nUser:".to_string(), "###".to_string()]);
+        assert_eq!(stop.find("Sure.\nUser: hi ###"), Some(5));
+        assert_eq!(stop.find("Sure."), None);
+        assert_eq!(stop.partial_len("Sure.\nUs"), 3);
+        assert_eq!(stop.partial_len("Sure. #"), 1);
+        assert_eq!(stop.partial_len("Sure."), 0);
+    }
+}
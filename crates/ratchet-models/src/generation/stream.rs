@@ -0,0 +1,342 @@
+use ratchet::{shape, Device, Tensor};
+use tokenizers::Tokenizer;
+
+use crate::{CausalLM, GenerationOptions, LogitMutator, Sampler, StopStrings};
+
+/// Why a [TokenStream] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    /// An end of sequence token was sampled.
+    EndOfSequence,
+    /// A stop string was generated.
+    Stop,
+    /// `max_tokens` were generated.
+    Length,
+    /// The model's cache is full.
+    ContextFull,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedToken {
+    pub token: u32,
+    /// Text completed by this token, may be empty, e.g midway through a multi-byte character
+    /// or a possible stop string.
+    pub text: String,
+}
+
+/// Streams the tokens generated by a [CausalLM] following a prompt.
+///
+/// Each step samples from the logits of the previous step after applying the
+/// [GenerationOptions::logit_mutators]. The end of sequence token, if sampled, is yielded
+/// last with any text that was held back.
+pub struct TokenStream<'a, M: CausalLM> {
+    model: &'a mut M,
+    tokenizer: &'a Tokenizer,
+    mutators: Vec<Box<dyn LogitMutator>>,
+    sampler: Sampler,
+    stop: StopStrings,
+    eos: Vec<u32>,
+    max_tokens: usize,
+    /// Prompt & generated tokens.
+    tokens: Vec<u32>,
+    prompt_len: usize,
+    /// Tokens to feed to the model on the next step.
+    pending: Vec<u32>,
+    /// Decoded generated text & how much of it has been yielded.
+    text: String,
+    emitted: usize,
+    finish_reason: Option<FinishReason>,
+}
+
+impl<'a, M: CausalLM> TokenStream<'a, M> {
+    /// Resets `model` & encodes `prompt`, including the tokenizer's special tokens.
+    pub fn new(
+        model: &'a mut M,
+        tokenizer: &'a Tokenizer,
+        prompt: &str,
+        options: &GenerationOptions,
+    ) -> anyhow::Result<Self> {
+        let encoding = tokenizer.encode(prompt, true).map_err(anyhow::Error::msg)?;
+        let prompt_tokens = encoding.get_ids().to_vec();
+        anyhow::ensure!(!prompt_tokens.is_empty(), "Prompt is empty");
+        Self::from_tokens(model, tokenizer, prompt_tokens, options)
+    }
+
+    pub fn from_tokens(
+        model: &'a mut M,
+        tokenizer: &'a Tokenizer,
+        prompt: Vec<u32>,
+        options: &GenerationOptions,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            prompt.len() <= model.max_seq_len(),
+            "Prompt of {} tokens exceeds the maximum sequence length of {}",
+            prompt.len(),
+            model.max_seq_len()
+        );
+        model.reset();
+        Ok(Self {
+            eos: model.eos_tokens(),
+            model,
+            tokenizer,
+            mutators: options.logit_mutators(prompt.len()),
+            sampler: options.sampler(),
+            stop: options.stop_strings(),
+            max_tokens: options.max_tokens(),
+            prompt_len: prompt.len(),
+            pending: prompt.clone(),
+            tokens: prompt,
+            text: String::new(),
+            emitted: 0,
+            finish_reason: None,
+        })
+    }
+
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason
+    }
+
+    /// The text yielded so far.
+    pub fn text(&self) -> &str {
+        &self.text[..self.emitted]
+    }
+
+    pub fn generated_tokens(&self) -> &[u32] {
+        &self.tokens[self.prompt_len..]
+    }
+
+    /// Checks the stopping conditions that apply before the model is run.
+    fn should_stop(&mut self) -> bool {
+        if self.finish_reason.is_some() {
+            return true;
+        }
+        if self.generated_tokens().len() >= self.max_tokens {
+            self.finish_reason = Some(FinishReason::Length);
+        } else if self.model.cache_len() + self.pending.len() > self.model.max_seq_len() {
+            self.finish_reason = Some(FinishReason::ContextFull);
+        }
+        self.finish_reason.is_some()
+    }
+
+    /// Applies the mutators to the logits of the last position, samples & decodes the next
+    /// token.
+    fn accept(&mut self, logits: Vec<f32>) -> anyhow::Result<GeneratedToken> {
+        let n_vocab = logits.len();
+        let mut logits = Tensor::from_data(logits, shape![1, n_vocab], Device::CPU);
+        let tokens = self.tokens.iter().map(|&t| t as i32).collect::<Vec<_>>();
+        let tokens = Tensor::from_data(tokens, shape![1, self.tokens.len()], Device::CPU);
+        for m in &self.mutators {
+            logits = m.apply(logits, Some(&tokens))?;
+        }
+        let logits = logits.into_ndarray::<f32>();
+        let token = self.sampler.sample(logits.as_slice().unwrap())?;
+
+        if self.eos.contains(&token) {
+            self.finish_reason = Some(FinishReason::EndOfSequence);
+            return Ok(self.flush(token));
+        }
+        self.tokens.push(token);
+        self.pending = vec![token];
+
+        let decoded = self
+            .tokenizer
+            .decode(self.generated_tokens(), true)
+            .map_err(anyhow::Error::msg)?;
+        if let Some(stop) = self.stop.find(&decoded) {
+            self.finish_reason = Some(FinishReason::Stop);
+            self.text = decoded[..stop.max(self.emitted)].to_string();
+            return Ok(self.flush(token));
+        }
+        self.text = decoded;
+        if self.generated_tokens().len() >= self.max_tokens {
+            self.finish_reason = Some(FinishReason::Length);
+            return Ok(self.flush(token));
+        }
+
+        //Hold back incomplete characters & possible stop strings
+        let mut end = self.text.len() - self.stop.partial_len(&self.text);
+        if self.text[..end].ends_with(char::REPLACEMENT_CHARACTER) {
+            end = self.emitted;
+        }
+        let text = match self.text.get(self.emitted..end) {
+            Some(text) => text.to_string(),
+            None => String::new(),
+        };
+        self.emitted = self.emitted.max(end);
+        Ok(GeneratedToken { token, text })
+    }
+
+    /// Yields all remaining text.
+    fn flush(&mut self, token: u32) -> GeneratedToken {
+        let text = self
+            .text
+            .get(self.emitted..)
+            .unwrap_or_default()
+            .to_string();
+        self.emitted = self.text.len();
+        GeneratedToken { token, text }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn next_token(&mut self) -> anyhow::Result<Option<GeneratedToken>> {
+        if self.should_stop() {
+            return Ok(None);
+        }
+        let logits = self.model.logits(&self.pending)?.resolve()?;
+        let logits = logits.to(&Device::CPU)?;
+        self.model.advance(self.pending.len());
+        self.accept(logits.to_vec::<f32>()?).map(Some)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn next_token(&mut self) -> anyhow::Result<Option<GeneratedToken>> {
+        if self.should_stop() {
+            return Ok(None);
+        }
+        let logits = self.model.logits(&self.pending)?.resolve()?;
+        let logits = logits.to(&Device::CPU).await?;
+        self.model.advance(self.pending.len());
+        self.accept(logits.to_vec::<f32>()?).map(Some)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<M: CausalLM> Iterator for TokenStream<'_, M> {
+    type Item = anyhow::Result<GeneratedToken>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().transpose()
+    }
+}
+
+/// Continues `prompt` until a stopping condition of `options` is met, calling `callback` with
+/// the text as it is generated.
+///
+/// Returns the generated text, without the prompt.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate_with<M: CausalLM>(
+    model: &mut M,
+    tokenizer: &Tokenizer,
+    prompt: &str,
+    options: &GenerationOptions,
+    mut callback: impl FnMut(&str),
+) -> anyhow::Result<String> {
+    let mut stream = TokenStream::new(model, tokenizer, prompt, options)?;
+    while let Some(generated) = stream.next_token()? {
+        if !generated.text.is_empty() {
+            callback(&generated.text);
+        }
+    }
+    log::info!("Generation finished: {:?}", stream.finish_reason());
+    Ok(stream.text().to_string())
+}
+
+/// Continues `prompt` until a stopping condition of `options` is met, calling `callback` with
+/// the text as it is generated.
+///
+/// Returns the generated text, without the prompt.
+#[cfg(target_arch = "wasm32")]
+pub async fn generate_with<M: CausalLM>(
+    model: &mut M,
+    tokenizer: &Tokenizer,
+    prompt: &str,
+    options: &GenerationOptions,
+    mut callback: impl FnMut(&str),
+) -> anyhow::Result<String> {
+    let mut stream = TokenStream::new(model, tokenizer, prompt, options)?;
+    while let Some(generated) = stream.next_token().await? {
+        if !generated.text.is_empty() {
+            callback(&generated.text);
+        }
+    }
+    log::info!("Generation finished: {:?}", stream.finish_reason());
+    Ok(stream.text().to_string())
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::GenerationOptionsBuilder;
+    use tokenizers::models::wordlevel::WordLevel;
+
+    const VOCAB: [&str; 6] = ["<eos>", "Hi", "there", "ST", "OP", "friend"];
+
+    struct Scripted;
+
+    impl CausalLM for Scripted {
+        fn logits(&self, _: &[u32]) -> anyhow::Result<Tensor> {
+            unreachable!("the stream is driven through accept")
+        }
+        fn advance(&mut self, _: usize) {}
+        fn reset(&mut self) {}
+        fn cache_len(&self) -> usize {
+            0
+        }
+        fn max_seq_len(&self) -> usize {
+            64
+        }
+        fn eos_tokens(&self) -> Vec<u32> {
+            vec![0]
+        }
+    }
+
+    fn tokenizer() -> Tokenizer {
+        let vocab = VOCAB
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.to_string(), i as u32))
+            .collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("<eos>".to_string())
+            .build()
+            .unwrap();
+        Tokenizer::new(model)
+    }
+
+    fn one_hot(token: usize) -> Vec<f32> {
+        let mut logits = vec![0.; VOCAB.len()];
+        logits[token] = 10.;
+        logits
+    }
+
+    fn run(options: &GenerationOptions, script: &[usize]) -> (Vec<String>, String, FinishReason) {
+        let (mut model, tokenizer) = (Scripted, tokenizer());
+        let mut stream =
+            TokenStream::from_tokens(&mut model, &tokenizer, vec![1], options).unwrap();
+        let mut pieces = vec![];
+        for &token in script {
+            pieces.push(stream.accept(one_hot(token)).unwrap().text);
+            if stream.should_stop() {
+                break;
+            }
+        }
+        let reason = stream.finish_reason().unwrap();
+        (pieces, stream.text().to_string(), reason)
+    }
+
+    #[test]
+    fn stop_strings_are_held_back_and_removed() {
+        let options = GenerationOptionsBuilder::new()
+            .stop("ST OP".to_string())
+            .build();
+        let (pieces, text, reason) = run(&options, &[2, 3, 5, 3, 4, 5]);
+        assert_eq!(pieces, ["there", " ", "ST friend", " ", ""]);
+        assert_eq!(text, "there ST friend ");
+        assert_eq!(reason, FinishReason::Stop);
+
+        let (pieces, text, reason) = run(&options, &[2, 3, 0]);
+        assert_eq!(pieces, ["there", " ", "ST"]);
+        assert_eq!(text, "there ST");
+        assert_eq!(reason, FinishReason::EndOfSequence);
+    }
+
+    #[test]
+    fn stops_at_max_tokens() {
+        let options = GenerationOptionsBuilder::new().max_tokens(2).build();
+        let (pieces, text, reason) = run(&options, &[5, 2, 5]);
+        assert_eq!(pieces, ["friend", " there"]);
+        assert_eq!(text, "friend there");
+        assert_eq!(reason, FinishReason::Length);
+    }
+}
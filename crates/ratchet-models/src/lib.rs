@@ -1,3 +1,14 @@
+mod audio;
+mod bert;
+mod checkpoint;
+mod eval;
+mod generation;
+mod llama;
 mod whisper;
 
+pub use audio::*;
+pub use bert::*;
+pub use eval::*;
+pub use generation::*;
+pub use llama::*;
 pub use whisper::*;
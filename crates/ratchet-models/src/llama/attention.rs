@@ -0,0 +1,140 @@
+use ratchet::{rvec, shape, Device, Tensor};
+use ratchet_nn::{KVEntry, Linear, Module, RotaryEmbedding, RotaryInput};
+
+/// Grouped-query self attention with rotary position embeddings.
+///
+/// Each key/value head is shared by `n_heads / n_kv_heads` query heads. Rather than repeating
+/// the keys & values, the query heads of a group are stacked along the sequence dimension.
+#[derive(Debug)]
+pub struct LlamaAttention {
+    q: Linear,
+    k: Linear,
+    v: Linear,
+    o: Linear,
+    rope: RotaryEmbedding,
+    n_heads: usize,
+    n_kv_heads: usize,
+    head_dim: usize,
+    scale: Tensor,
+}
+
+impl LlamaAttention {
+    pub fn new(
+        q: Linear,
+        k: Linear,
+        v: Linear,
+        o: Linear,
+        rope: RotaryEmbedding,
+        n_heads: usize,
+        n_kv_heads: usize,
+    ) -> Self {
+        let head_dim = q.w.shape()[0] / n_heads;
+        let scale = (head_dim as f32).powf(-0.5);
+        let scale = Tensor::from_data([scale], shape![1], q.w.device().clone());
+        Self {
+            q,
+            k,
+            v,
+            o,
+            rope,
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            scale,
+        }
+    }
+
+    /// Additive mask of the new positions over all cached positions, `[n_ctx, n_cached]`.
+    pub(crate) fn causal_mask(n_ctx: usize, n_cached: usize, device: &Device) -> Tensor {
+        let offset = n_cached - n_ctx;
+        let mask: Vec<_> = (0..n_ctx)
+            .flat_map(|i| {
+                (0..n_cached).map(move |j| match j > offset + i {
+                    true => f32::NEG_INFINITY,
+                    false => 0f32,
+                })
+            })
+            .collect();
+        Tensor::from_data(mask, shape![n_ctx, n_cached], device.clone())
+    }
+}
+
+/// `x` is `[1, n_ctx, hidden_size]`, the cache is `[max_seq, 1, kv_dim]`.
+#[derive(Debug, derive_new::new)]
+pub struct LlamaAttentionInput {
+    pub x: Tensor,
+    pub cache: KVEntry,
+}
+
+impl Module for LlamaAttention {
+    type Input = LlamaAttentionInput;
+
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let LlamaAttentionInput { x, cache } = input;
+        let [bs, n_ctx, _]: [usize; 3] = x.shape().try_into()?;
+        anyhow::ensure!(bs == 1, "Llama attention supports a batch of 1, got {}", bs);
+        let (hd, n_heads, n_kv_heads) = (self.head_dim, self.n_heads, self.n_kv_heads);
+        let kv_dim = n_kv_heads * hd;
+        let offset = cache.entries;
+
+        let q = self.q.forward(x)?.view(shape![1, n_ctx, n_heads, hd])?;
+        let k = self.k.forward(x)?.view(shape![1, n_ctx, n_kv_heads, hd])?;
+        let q = self.rope.forward(&RotaryInput::new(q, offset))?;
+        let k = self.rope.forward(&RotaryInput::new(k, offset))?;
+        let v = self.v.forward(x)?;
+
+        //With a batch of 1, [1, n_ctx, kv_dim] is already time major
+        let n_cached = offset + n_ctx;
+        let cached = shape![n_cached, 1, n_kv_heads, hd];
+        let k = cache
+            .k_cache
+            .index_write(&k.view(shape![n_ctx, 1, kv_dim])?, rvec![offset, 0, 0])?
+            .view(cached.clone())?
+            .permute(&[1, 2, 3, 0])?;
+        let v = cache
+            .v_cache
+            .index_write(&v.view(shape![n_ctx, 1, kv_dim])?, rvec![offset, 0, 0])?
+            .view(cached)?
+            .permute(&[1, 2, 0, 3])?;
+
+        //[1, n_heads, n_ctx, hd] -> [1, n_kv_heads, group * n_ctx, hd]
+        let group = n_heads / n_kv_heads;
+        let q = q.permute(&[0, 2, 1, 3])?.mul(&self.scale)?.view(shape![
+            1,
+            n_kv_heads,
+            group * n_ctx,
+            hd
+        ])?;
+
+        let mut qk = q.matmul(&k, false)?;
+        if n_ctx > 1 {
+            let mask = Self::causal_mask(n_ctx, n_cached, x.device());
+            qk = qk
+                .view(shape![1, n_heads, n_ctx, n_cached])?
+                .add(&mask)?
+                .view(shape![1, n_kv_heads, group * n_ctx, n_cached])?;
+        }
+        let wv = qk
+            .softmax(3)?
+            .matmul(&v, false)?
+            .view(shape![1, n_heads, n_ctx, hd])?
+            .permute(&[0, 2, 1, 3])?
+            .view(shape![1, n_ctx, n_heads * hd])?;
+        self.o.forward(&wv)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_covers_the_cached_prefix() {
+        let mask = LlamaAttention::causal_mask(2, 4, &Device::CPU);
+        let inf = f32::NEG_INFINITY;
+        assert_eq!(
+            mask.to_vec::<f32>().unwrap(),
+            [0., 0., 0., inf, 0., 0., 0., 0.]
+        );
+    }
+}
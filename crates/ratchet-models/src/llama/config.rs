@@ -0,0 +1,155 @@
+use ratchet_loader::{GGUFModel, GgufValue};
+
+/// Hyperparameters of a Llama-style decoder.
+///
+/// Deserializes from the `config.json` of a HuggingFace checkpoint, or is read from the
+/// metadata of a GGUF file with [LlamaConfig::from_gguf].
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct LlamaConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    /// Fewer key/value heads than query heads for grouped-query attention, defaults to
+    /// `num_attention_heads`.
+    #[serde(default)]
+    pub num_key_value_heads: Option<usize>,
+    #[serde(default = "default_rms_norm_eps")]
+    pub rms_norm_eps: f32,
+    #[serde(default = "default_rope_theta")]
+    pub rope_theta: f32,
+    #[serde(default = "default_max_position_embeddings")]
+    pub max_position_embeddings: usize,
+    /// Rotate adjacent features rather than halves of each head, see
+    /// [ratchet_nn::RotaryEmbedding].
+    #[serde(default)]
+    pub rope_interleaved: bool,
+    #[serde(default)]
+    pub tie_word_embeddings: bool,
+    #[serde(default)]
+    pub bos_token_id: Option<u32>,
+    #[serde(default)]
+    pub eos_token_id: Option<EosTokens>,
+}
+
+fn default_rms_norm_eps() -> f32 {
+    1e-6
+}
+
+fn default_rope_theta() -> f32 {
+    10000.
+}
+
+fn default_max_position_embeddings() -> usize {
+    2048
+}
+
+/// `eos_token_id` is a single token or a list of them in HuggingFace configs.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum EosTokens {
+    Single(u32),
+    Multiple(Vec<u32>),
+}
+
+impl LlamaConfig {
+    pub fn from_json(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Reads the `{architecture}.*` keys written by llama.cpp.
+    ///
+    /// Only the `llama` architecture rotates adjacent features, llama.cpp permutes the
+    /// query & key projections of those checkpoints on conversion.
+    pub fn from_gguf(model: &GGUFModel) -> anyhow::Result<Self> {
+        let arch = model.architecture()?;
+        let key = |name: &str| format!("{}.{}", arch, name);
+        let usize_of = |key: &str| -> anyhow::Result<usize> {
+            model
+                .metadata(key)?
+                .to_u64()
+                .map(|v| v as usize)
+                .ok_or_else(|| anyhow::anyhow!("Metadata {} is not an integer", key))
+        };
+        let optional = |key: &str| model.metadata.get(key);
+
+        let vocab_size = match optional(&key("vocab_size")) {
+            Some(v) => v.to_u64().map(|v| v as usize),
+            None => optional("tokenizer.ggml.tokens")
+                .and_then(GgufValue::as_array)
+                .map(<[_]>::len),
+        }
+        .ok_or_else(|| anyhow::anyhow!("GGUF file does not specify a vocabulary size"))?;
+        let num_attention_heads = usize_of(&key("attention.head_count"))?;
+        let token_id = |key: &str| optional(key).and_then(GgufValue::to_u64).map(|v| v as u32);
+
+        Ok(Self {
+            vocab_size,
+            hidden_size: usize_of(&key("embedding_length"))?,
+            intermediate_size: usize_of(&key("feed_forward_length"))?,
+            num_hidden_layers: usize_of(&key("block_count"))?,
+            num_attention_heads,
+            num_key_value_heads: optional(&key("attention.head_count_kv"))
+                .and_then(GgufValue::to_u64)
+                .map(|v| v as usize),
+            rms_norm_eps: optional(&key("attention.layer_norm_rms_epsilon"))
+                .and_then(GgufValue::to_f32)
+                .unwrap_or_else(default_rms_norm_eps),
+            rope_theta: optional(&key("rope.freq_base"))
+                .and_then(GgufValue::to_f32)
+                .unwrap_or_else(default_rope_theta),
+            max_position_embeddings: optional(&key("context_length"))
+                .and_then(GgufValue::to_u64)
+                .map_or_else(default_max_position_embeddings, |v| v as usize),
+            rope_interleaved: arch == "llama",
+            tie_word_embeddings: !model.tensors.contains_key("output.weight"),
+            bos_token_id: token_id("tokenizer.ggml.bos_token_id"),
+            eos_token_id: token_id("tokenizer.ggml.eos_token_id").map(EosTokens::Single),
+        })
+    }
+
+    pub fn num_key_value_heads(&self) -> usize {
+        self.num_key_value_heads.unwrap_or(self.num_attention_heads)
+    }
+
+    pub fn head_dim(&self) -> usize {
+        self.hidden_size / self.num_attention_heads
+    }
+
+    pub fn eos_tokens(&self) -> Vec<u32> {
+        match &self.eos_token_id {
+            Some(EosTokens::Single(token)) => vec![*token],
+            Some(EosTokens::Multiple(tokens)) => tokens.clone(),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hf_config() {
+        let json = r#"{
+            "architectures": ["LlamaForCausalLM"],
+            "hidden_size": 2048,
+            "intermediate_size": 5632,
+            "max_position_embeddings": 2048,
+            "num_attention_heads": 32,
+            "num_hidden_layers": 22,
+            "num_key_value_heads": 4,
+            "rms_norm_eps": 1e-05,
+            "vocab_size": 32000,
+            "bos_token_id": 1,
+            "eos_token_id": [2, 32000]
+        }"#;
+        let config = LlamaConfig::from_json(json.as_bytes()).unwrap();
+        assert_eq!(config.head_dim(), 64);
+        assert_eq!(config.num_key_value_heads(), 4);
+        assert_eq!(config.rope_theta, 10000.);
+        assert!(!config.rope_interleaved && !config.tie_word_embeddings);
+        assert_eq!(config.eos_tokens(), [2, 32000]);
+    }
+}
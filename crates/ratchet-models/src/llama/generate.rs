@@ -0,0 +1,55 @@
+use tokenizers::Tokenizer;
+
+use crate::{GenerationOptions, Llama, TokenStream};
+
+/// Greedy decoding of up to `max_tokens` tokens.
+fn greedy(max_tokens: usize) -> GenerationOptions {
+    GenerationOptions {
+        max_tokens: u32::try_from(max_tokens).unwrap_or(u32::MAX),
+        ..Default::default()
+    }
+}
+
+/// Greedily continues `prompt` for up to `max_tokens` tokens, or until an end of sequence
+/// token or the end of the cache. `callback` receives the text as it is decoded.
+///
+/// Returns the generated text, without the prompt.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate(
+    model: &mut Llama,
+    tokenizer: &Tokenizer,
+    prompt: &str,
+    max_tokens: usize,
+    mut callback: impl FnMut(&str),
+) -> anyhow::Result<String> {
+    let mut stream = TokenStream::new(model, tokenizer, prompt, &greedy(max_tokens))?;
+    while let Some(generated) = stream.next_token()? {
+        if !generated.text.is_empty() {
+            callback(&generated.text);
+        }
+    }
+    log::info!("Generation finished: {:?}", stream.finish_reason());
+    Ok(stream.text().to_string())
+}
+
+/// Greedily continues `prompt` for up to `max_tokens` tokens, or until an end of sequence
+/// token or the end of the cache. `callback` receives the text as it is decoded.
+///
+/// Returns the generated text, without the prompt.
+#[cfg(target_arch = "wasm32")]
+pub async fn generate(
+    model: &mut Llama,
+    tokenizer: &Tokenizer,
+    prompt: &str,
+    max_tokens: usize,
+    mut callback: impl FnMut(&str),
+) -> anyhow::Result<String> {
+    let mut stream = TokenStream::new(model, tokenizer, prompt, &greedy(max_tokens))?;
+    while let Some(generated) = stream.next_token().await? {
+        if !generated.text.is_empty() {
+            callback(&generated.text);
+        }
+    }
+    log::info!("Generation finished: {:?}", stream.finish_reason());
+    Ok(stream.text().to_string())
+}
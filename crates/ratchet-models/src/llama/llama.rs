@@ -0,0 +1,589 @@
+use std::io::{BufRead, Seek};
+
+use ratchet::{shape, DType, Device, Quantization, Quantizer, Shape, Tensor};
+use ratchet_loader::GGUFModel;
+use ratchet_nn::{Embedding, KVCache, KVEntry, Linear, Module, RMSNorm, RotaryEmbedding};
+
+use crate::checkpoint::SafetensorsShards;
+use crate::{CausalLM, LlamaAttention, LlamaAttentionInput, LlamaConfig, LlamaMLP};
+
+/// Maps a HuggingFace tensor name to its name in GGUF files written by llama.cpp.
+///
+/// Fused Phi-3 projections map to the same tensors, `gate_up_proj` to `ffn_up`.
+pub fn gguf_tensor_name(hf_name: &str) -> Option<String> {
+    let layer_name = match hf_name {
+        "model.embed_tokens.weight" => return Some("token_embd.weight".to_string()),
+        "model.norm.weight" => return Some("output_norm.weight".to_string()),
+        "lm_head.weight" => return Some("output.weight".to_string()),
+        _ => hf_name.strip_prefix("model.layers.")?,
+    };
+    let (layer, name) = layer_name.split_once('.')?;
+    let layer: usize = layer.parse().ok()?;
+    let (module, param) = name.rsplit_once('.')?;
+    let mapped = match module {
+        "input_layernorm" => "attn_norm",
+        "self_attn.q_proj" => "attn_q",
+        "self_attn.k_proj" => "attn_k",
+        "self_attn.v_proj" => "attn_v",
+        "self_attn.qkv_proj" => "attn_qkv",
+        "self_attn.o_proj" => "attn_output",
+        "post_attention_layernorm" => "ffn_norm",
+        "mlp.gate_proj" => "ffn_gate",
+        "mlp.up_proj" | "mlp.gate_up_proj" => "ffn_up",
+        "mlp.down_proj" => "ffn_down",
+        _ => return None,
+    };
+    Some(format!("blk.{}.{}.{}", layer, mapped, param))
+}
+
+/// Tensors of a checkpoint looked up by their HuggingFace names, as F32 data & shape.
+trait LlamaWeights {
+    fn get(&mut self, name: &str) -> anyhow::Result<Option<(Vec<f32>, Shape)>>;
+
+    /// How to pack `name` on upload if it is a matrix, to keep quantized checkpoints small.
+    fn quantization(&self, _name: &str) -> Quantization {
+        Quantization::None
+    }
+}
+
+struct GGUFWeights<'a, R> {
+    model: &'a GGUFModel,
+    reader: &'a mut R,
+}
+
+impl<R: BufRead + Seek> LlamaWeights for GGUFWeights<'_, R> {
+    fn get(&mut self, name: &str) -> anyhow::Result<Option<(Vec<f32>, Shape)>> {
+        let Some(key) = gguf_tensor_name(name).filter(|k| self.model.tensors.contains_key(k))
+        else {
+            return Ok(None);
+        };
+        let tensor = self.model.load_tensor(&key, self.reader, &Device::CPU)?;
+        Ok(Some((tensor.to_vec::<f32>()?, tensor.shape().clone())))
+    }
+
+    fn quantization(&self, name: &str) -> Quantization {
+        gguf_tensor_name(name).map_or(Quantization::None, |k| self.model.quantization(&k))
+    }
+}
+
+impl LlamaWeights for SafetensorsShards<'_> {
+    fn get(&mut self, name: &str) -> anyhow::Result<Option<(Vec<f32>, Shape)>> {
+        SafetensorsShards::get(self, name)
+    }
+}
+
+struct Loader<'a> {
+    weights: &'a mut dyn LlamaWeights,
+    device: Device,
+}
+
+impl Loader<'_> {
+    fn raw(&mut self, name: &str) -> anyhow::Result<(Vec<f32>, Shape)> {
+        self.weights
+            .get(name)?
+            .ok_or_else(|| anyhow::anyhow!("Missing tensor {}", name))
+    }
+
+    fn upload(&self, (data, shape): (Vec<f32>, Shape)) -> Tensor {
+        Tensor::from_data(data, shape, self.device.clone())
+    }
+
+    /// Uploads a matrix, packed as WQ8 when `quantization` asks for it.
+    fn upload_weight(
+        &self,
+        (data, shape): (Vec<f32>, Shape),
+        quantization: Quantization,
+    ) -> anyhow::Result<Tensor> {
+        if matches!(quantization, Quantization::None) {
+            return Ok(self.upload((data, shape)));
+        }
+        let tensor = Tensor::from_data(data, shape.clone(), Device::CPU);
+        let bytes = unsafe { Quantizer::new(quantization).quantize(tensor).into_bytes()? };
+        Tensor::from_bytes(&bytes, DType::WQ8, shape, self.device.clone())
+    }
+
+    fn tensor(&mut self, name: &str) -> anyhow::Result<Tensor> {
+        let raw = self.raw(name)?;
+        Ok(self.upload(raw))
+    }
+
+    /// A Linear or embedding matrix, kept quantized if the checkpoint is.
+    fn weight(&mut self, name: &str) -> anyhow::Result<Tensor> {
+        let raw = self.raw(name)?;
+        self.upload_weight(raw, self.weights.quantization(name))
+    }
+
+    /// `{prefix}.weight` & the optional `{prefix}.bias`, or `None` if the weight is missing.
+    fn linear(&mut self, prefix: &str) -> anyhow::Result<Option<Linear>> {
+        let name = format!("{}.weight", prefix);
+        let Some(w) = self.weights.get(&name)? else {
+            return Ok(None);
+        };
+        let w = self.upload_weight(w, self.weights.quantization(&name))?;
+        let b = self.weights.get(&format!("{}.bias", prefix))?;
+        Ok(Some(Linear::new(w, b.map(|b| self.upload(b)))))
+    }
+
+    /// Splits a fused `[sum(rows), in]` projection into one [Linear] per entry of `rows`.
+    fn split_linear(&mut self, name: &str, rows: &[usize]) -> anyhow::Result<Vec<Linear>> {
+        let (data, shape) = self.raw(name)?;
+        let [total, cols]: [usize; 2] = (&shape).try_into()?;
+        anyhow::ensure!(
+            rows.iter().sum::<usize>() == total,
+            "Cannot split {} with {} rows into {:?}",
+            name,
+            total,
+            rows
+        );
+        let quantization = self.weights.quantization(name);
+        let mut start = 0;
+        rows.iter()
+            .map(|&n| {
+                let chunk = data[start * cols..(start + n) * cols].to_vec();
+                start += n;
+                let w = self.upload_weight((chunk, shape![n, cols]), quantization)?;
+                Ok(Linear::new(w, None))
+            })
+            .collect()
+    }
+}
+
+/// A pre-norm decoder block.
+#[derive(Debug)]
+pub struct LlamaBlock {
+    attn_norm: RMSNorm,
+    attn: LlamaAttention,
+    mlp_norm: RMSNorm,
+    mlp: LlamaMLP,
+}
+
+#[derive(Debug, derive_new::new)]
+pub struct LlamaBlockInput {
+    pub x: Tensor,
+    pub cache: KVEntry,
+}
+
+impl Module for LlamaBlock {
+    type Input = LlamaBlockInput;
+
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let LlamaBlockInput { x, cache } = input;
+        let attn_input = LlamaAttentionInput::new(self.attn_norm.forward(x)?, cache.clone());
+        let h = x.add(&self.attn.forward(&attn_input)?)?;
+        h.add(&self.mlp.forward(&self.mlp_norm.forward(&h)?)?)
+    }
+}
+
+impl LlamaBlock {
+    fn load(
+        loader: &mut Loader,
+        layer: usize,
+        config: &LlamaConfig,
+        rope: &RotaryEmbedding,
+    ) -> anyhow::Result<Self> {
+        let prefix = format!("model.layers.{}", layer);
+        let name = |n: &str| format!("{}.{}", prefix, n);
+        let (n_heads, n_kv_heads) = (config.num_attention_heads, config.num_key_value_heads());
+        let q_dim = n_heads * config.head_dim();
+        let kv_dim = n_kv_heads * config.head_dim();
+
+        let [q, k, v]: [Linear; 3] = match loader.linear(&name("self_attn.q_proj"))? {
+            Some(q) => {
+                let mut required = |n: &str| {
+                    loader
+                        .linear(&name(n))?
+                        .ok_or_else(|| anyhow::anyhow!("Missing tensor {}.weight", name(n)))
+                };
+                [
+                    q,
+                    required("self_attn.k_proj")?,
+                    required("self_attn.v_proj")?,
+                ]
+            }
+            None => {
+                let fused = name("self_attn.qkv_proj.weight");
+                let qkv = loader.split_linear(&fused, &[q_dim, kv_dim, kv_dim])?;
+                qkv.try_into().unwrap()
+            }
+        };
+        let o = loader
+            .linear(&name("self_attn.o_proj"))?
+            .ok_or_else(|| anyhow::anyhow!("Missing tensor {}.weight", name("self_attn.o_proj")))?;
+
+        let ff = config.intermediate_size;
+        let [gate, up]: [Linear; 2] = match loader.linear(&name("mlp.gate_proj"))? {
+            Some(gate) => {
+                let up = loader.weight(&name("mlp.up_proj.weight"))?;
+                [gate, Linear::new(up, None)]
+            }
+            None => {
+                let fused = name("mlp.gate_up_proj.weight");
+                loader.split_linear(&fused, &[ff, ff])?.try_into().unwrap()
+            }
+        };
+        let down = Linear::new(loader.weight(&name("mlp.down_proj.weight"))?, None);
+
+        let eps = config.rms_norm_eps;
+        Ok(Self {
+            attn_norm: RMSNorm::new(loader.tensor(&name("input_layernorm.weight"))?, eps),
+            attn: LlamaAttention::new(q, k, v, o, rope.clone(), n_heads, n_kv_heads),
+            mlp_norm: RMSNorm::new(
+                loader.tensor(&name("post_attention_layernorm.weight"))?,
+                eps,
+            ),
+            mlp: LlamaMLP::new(gate, up, down),
+        })
+    }
+}
+
+/// A Llama-style decoder-only language model: RMSNorm, rotary embeddings, grouped-query
+/// attention & a SwiGLU feed forward.
+///
+/// Covers Llama, TinyLlama, Mistral, Qwen2 & Phi-3 checkpoints. Loaded from GGUF files, with
+/// F16 weights dequantized to F32 & Q8_0 or Q4_0 matrices packed as WQ8, or from safetensors
+/// checkpoints.
+#[derive(Debug)]
+pub struct Llama {
+    embedding: Embedding,
+    blocks: Vec<LlamaBlock>,
+    norm: RMSNorm,
+    /// `[vocab_size, hidden_size]`, the token embedding when the weights are tied.
+    lm_head: Tensor,
+    cache: KVCache,
+    max_seq_len: usize,
+    pub config: LlamaConfig,
+    pub device: Device,
+}
+
+impl Module for Llama {
+    type Input = Tensor;
+
+    /// Logits of the final position of `tokens`, [1, 1, vocab_size].
+    ///
+    /// `tokens` is [1, num_tokens] and follows the tokens already in the cache, the caller
+    /// updates the cache with `num_tokens` once the logits are resolved.
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let num_tokens = input.shape()[1];
+        anyhow::ensure!(
+            self.cache.entries(0) + num_tokens <= self.max_seq_len,
+            "Sequence exceeds the maximum length of {}",
+            self.max_seq_len
+        );
+        let mut x = self.embedding.forward(input)?;
+        for (block_idx, block) in self.blocks.iter().enumerate() {
+            let block_input = LlamaBlockInput::new(x, self.cache[block_idx].clone());
+            x = block.forward(&block_input)?;
+        }
+        if num_tokens > 1 {
+            let hidden = self.config.hidden_size;
+            x = x.slice(&[0..1, num_tokens - 1..num_tokens, 0..hidden])?;
+        }
+        self.norm.forward(&x)?.matmul(&self.lm_head, true)
+    }
+}
+
+impl Llama {
+    pub const MAX_CACHE: usize = 2048;
+
+    pub fn load_gguf<R: BufRead + Seek>(
+        disk_model: &GGUFModel,
+        reader: &mut R,
+        device: Device,
+    ) -> anyhow::Result<Self> {
+        let config = LlamaConfig::from_gguf(disk_model)?;
+        let mut weights = GGUFWeights {
+            model: disk_model,
+            reader,
+        };
+        Self::load(&mut weights, config, device)
+    }
+
+    /// Loads a HuggingFace checkpoint, which may be split over several `.safetensors` files.
+    pub fn load_safetensors(
+        shards: &[&[u8]],
+        config: LlamaConfig,
+        device: Device,
+    ) -> anyhow::Result<Self> {
+        let mut weights = SafetensorsShards::deserialize(shards)?;
+        Self::load(&mut weights, config, device)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let device = Device::request_device(ratchet::DeviceRequest::GPU).await?;
+        let mut reader = std::io::BufReader::new(std::io::Cursor::new(bytes));
+        let disk_model = GGUFModel::read(&mut reader)?;
+        Self::load_gguf(&disk_model, &mut reader, device)
+    }
+
+    fn load(
+        weights: &mut dyn LlamaWeights,
+        config: LlamaConfig,
+        device: Device,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            config
+                .num_attention_heads
+                .is_multiple_of(config.num_key_value_heads()),
+            "{} attention heads cannot be grouped over {} key/value heads",
+            config.num_attention_heads,
+            config.num_key_value_heads()
+        );
+        let mut loader = Loader {
+            weights,
+            device: device.clone(),
+        };
+        let max_seq_len = config.max_position_embeddings.min(Self::MAX_CACHE);
+        let rope = RotaryEmbedding::new(
+            config.head_dim(),
+            max_seq_len,
+            config.rope_theta,
+            config.rope_interleaved,
+            &device,
+        );
+
+        let embedding = loader.weight("model.embed_tokens.weight")?;
+        let blocks = (0..config.num_hidden_layers)
+            .map(|layer| LlamaBlock::load(&mut loader, layer, &config, &rope))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let norm = RMSNorm::new(loader.tensor("model.norm.weight")?, config.rms_norm_eps);
+        let lm_head = match loader.weights.get("lm_head.weight")? {
+            Some(raw) => {
+                loader.upload_weight(raw, loader.weights.quantization("lm_head.weight"))?
+            }
+            None => embedding.clone(),
+        };
+
+        let kv_dim = config.num_key_value_heads() * config.head_dim();
+        let cache_shape = shape![max_seq_len, 1, kv_dim];
+        let cache = KVCache::new(config.num_hidden_layers as _, &cache_shape, &device);
+        log::info!("Sucessfully loaded Llama model");
+        Ok(Self {
+            embedding: Embedding::new(embedding),
+            blocks,
+            norm,
+            lm_head,
+            cache,
+            max_seq_len,
+            config,
+            device,
+        })
+    }
+
+    pub fn cache_mut(&mut self) -> &mut KVCache {
+        &mut self.cache
+    }
+
+    /// Number of tokens in the cache.
+    pub fn cache_len(&self) -> usize {
+        self.cache.entries(0)
+    }
+
+    /// Longest sequence the cache can hold, `max_position_embeddings` capped at `MAX_CACHE`.
+    pub fn max_seq_len(&self) -> usize {
+        self.max_seq_len
+    }
+
+    pub fn reset(&mut self) {
+        self.cache.reset();
+    }
+}
+
+impl CausalLM for Llama {
+    fn logits(&self, tokens: &[u32]) -> anyhow::Result<Tensor> {
+        let input = tokens.iter().map(|&t| t as i32).collect::<Vec<_>>();
+        let input = Tensor::from_data(input, shape![1, tokens.len()], self.device.clone());
+        self.forward(&input)
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.cache.update(n);
+    }
+
+    fn reset(&mut self) {
+        Llama::reset(self);
+    }
+
+    fn cache_len(&self) -> usize {
+        Llama::cache_len(self)
+    }
+
+    fn max_seq_len(&self) -> usize {
+        Llama::max_seq_len(self)
+    }
+
+    fn eos_tokens(&self) -> Vec<u32> {
+        self.config.eos_tokens()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use hf_hub::api::sync::Api;
+    use numpy::PyArrayDyn;
+    use pyo3::{prelude::*, types::PyTuple};
+    use ratchet::DeviceRequest;
+    use tokenizers::Tokenizer;
+
+    use crate::CausalLM;
+
+    const MODEL_ID: &str = "TinyLlama/TinyLlama-1.1B-Chat-v1.0";
+    const GGUF_ID: &str = "TheBloke/TinyLlama-1.1B-Chat-v1.0-GGUF";
+    const PROMPT: &str = "The capital of France is";
+
+    fn log_init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    /// HF transformers logits of the final position of `PROMPT`.
+    fn ground_truth() -> anyhow::Result<Vec<f32>> {
+        let prg = r#"
+import torch
+from transformers import AutoModelForCausalLM, AutoTokenizer
+def ground(model_id, prompt):
+    tokenizer = AutoTokenizer.from_pretrained(model_id)
+    model = AutoModelForCausalLM.from_pretrained(model_id, torch_dtype=torch.float32)
+    ids = tokenizer(prompt, return_tensors="pt").input_ids
+    with torch.no_grad():
+        return model(ids).logits[0, -1].numpy()
+"#;
+        Python::with_gil(|py| {
+            let prg = PyModule::from_code(py, prg, "x.py", "x")?;
+            let py_args = PyTuple::new(py, [MODEL_ID, PROMPT]);
+            let py_result: &PyArrayDyn<f32> = prg.getattr("ground")?.call1(py_args)?.extract()?;
+            Tensor::from(py_result).to_vec::<f32>()
+        })
+    }
+
+    fn final_logits(model: &Llama, tokenizer: &Tokenizer) -> Vec<f32> {
+        let encoding = tokenizer.encode(PROMPT, true).unwrap();
+        let logits = model.logits(encoding.get_ids()).unwrap().resolve().unwrap();
+        logits.to(&Device::CPU).unwrap().to_vec::<f32>().unwrap()
+    }
+
+    fn argmax(logits: &[f32]) -> usize {
+        logits
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0
+    }
+
+    fn max_abs_diff(a: &[f32], b: &[f32]) -> f32 {
+        assert_eq!(a.len(), b.len());
+        a.iter()
+            .zip(b)
+            .map(|(x, y)| (x - y).abs())
+            .fold(0., f32::max)
+    }
+
+    #[test]
+    fn llama_end_to_end() {
+        log_init();
+        let api = Api::new().unwrap();
+        let model = api.model(MODEL_ID.to_string());
+        let safetensors = std::fs::read(model.get("model.safetensors").unwrap()).unwrap();
+        let config = std::fs::read(model.get("config.json").unwrap()).unwrap();
+        let tokenizer = Tokenizer::from_file(model.get("tokenizer.json").unwrap()).unwrap();
+        let expected = ground_truth().unwrap();
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+
+        //Half-split rotary embeddings, F32 weights
+        let config = LlamaConfig::from_json(&config).unwrap();
+        assert!(!config.rope_interleaved);
+        let llama = Llama::load_safetensors(&[&safetensors], config, device.clone()).unwrap();
+        let ours = final_logits(&llama, &tokenizer);
+        assert_eq!(argmax(&ours), argmax(&expected));
+        let diff = max_abs_diff(&ours, &expected);
+        assert!(diff < 5e-2, "safetensors logits differ by {}", diff);
+
+        //Interleaved rotary embeddings, GGML blocks packed as WQ8
+        let gguf = api.model(GGUF_ID.to_string());
+        for (file, atol) in [
+            ("tinyllama-1.1b-chat-v1.0.Q8_0.gguf", 1.),
+            ("tinyllama-1.1b-chat-v1.0.Q4_0.gguf", 3.),
+        ] {
+            let path = gguf.get(file).unwrap();
+            let mut reader = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+            let disk_model = GGUFModel::read(&mut reader).unwrap();
+            let llama = Llama::load_gguf(&disk_model, &mut reader, device.clone()).unwrap();
+            assert!(llama.config.rope_interleaved);
+            assert_eq!(llama.embedding.weight.dt(), DType::WQ8);
+
+            let ours = final_logits(&llama, &tokenizer);
+            assert_eq!(argmax(&ours), argmax(&expected), "{}", file);
+            let diff = max_abs_diff(&ours, &expected);
+            assert!(diff < atol, "{} logits differ by {}", file, diff);
+        }
+    }
+
+    struct QuantizedWeights(std::collections::HashMap<String, (Vec<f32>, Shape)>);
+
+    impl LlamaWeights for QuantizedWeights {
+        fn get(&mut self, name: &str) -> anyhow::Result<Option<(Vec<f32>, Shape)>> {
+            Ok(self.0.get(name).cloned())
+        }
+
+        fn quantization(&self, name: &str) -> Quantization {
+            if name.ends_with(".weight") {
+                Quantization::SInt8
+            } else {
+                Quantization::None
+            }
+        }
+    }
+
+    #[test]
+    fn quantized_weights_are_packed() {
+        let raw = |rows: usize, cols: usize| {
+            let data = (0..rows * cols).map(|i| i as f32 / 64.).collect();
+            (data, shape![rows, cols])
+        };
+        let mut weights = QuantizedWeights(
+            [
+                ("q.weight".to_string(), raw(32, 64)),
+                ("q.bias".to_string(), raw(1, 32)),
+                ("qkv.weight".to_string(), raw(96, 64)),
+            ]
+            .into(),
+        );
+        let mut loader = Loader {
+            weights: &mut weights,
+            device: Device::CPU,
+        };
+
+        let q = loader.linear("q").unwrap().unwrap();
+        assert_eq!(q.w.dt(), DType::WQ8);
+        assert_eq!(q.w.shape(), &shape![32, 64]);
+        assert_eq!(loader.tensor("q.bias").unwrap().dt(), DType::F32);
+
+        let qkv = loader.split_linear("qkv.weight", &[32, 32, 32]).unwrap();
+        assert!(qkv
+            .iter()
+            .all(|l| l.w.dt() == DType::WQ8 && l.w.shape() == &shape![32, 64]));
+    }
+
+    #[test]
+    fn gguf_names() {
+        let name = |n: &str| gguf_tensor_name(n);
+        assert_eq!(
+            name("model.embed_tokens.weight").as_deref(),
+            Some("token_embd.weight")
+        );
+        assert_eq!(
+            name("model.layers.3.self_attn.k_proj.bias").as_deref(),
+            Some("blk.3.attn_k.bias")
+        );
+        assert_eq!(
+            name("model.layers.12.mlp.gate_up_proj.weight").as_deref(),
+            Some("blk.12.ffn_up.weight")
+        );
+        assert_eq!(
+            name("model.layers.0.post_attention_layernorm.weight").as_deref(),
+            Some("blk.0.ffn_norm.weight")
+        );
+        assert_eq!(name("model.layers.0.self_attn.rotary_emb.inv_freq"), None);
+    }
+}
@@ -0,0 +1,39 @@
+use ratchet::{shape, Tensor};
+use ratchet_nn::{Linear, Module};
+
+/// SwiGLU feed forward, `down(silu(gate(x)) * up(x))`.
+#[derive(Debug)]
+pub struct LlamaMLP {
+    gate: Linear,
+    up: Linear,
+    down: Linear,
+    one: Tensor,
+    neg_one: Tensor,
+}
+
+impl LlamaMLP {
+    pub fn new(gate: Linear, up: Linear, down: Linear) -> Self {
+        let device = gate.w.device().clone();
+        Self {
+            gate,
+            up,
+            down,
+            one: Tensor::from_data([1f32], shape![1], device.clone()),
+            neg_one: Tensor::from_data([-1f32], shape![1], device),
+        }
+    }
+
+    /// `x * sigmoid(x)`, as `x / (1 + exp(-x))`.
+    fn silu(&self, x: &Tensor) -> anyhow::Result<Tensor> {
+        x.div(&x.mul(&self.neg_one)?.exp()?.add(&self.one)?)
+    }
+}
+
+impl Module for LlamaMLP {
+    type Input = Tensor;
+
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let gate = self.silu(&self.gate.forward(input)?)?;
+        self.down.forward(&gate.mul(&self.up.forward(input)?)?)
+    }
+}
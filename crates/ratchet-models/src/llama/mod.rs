@@ -0,0 +1,12 @@
+mod attention;
+mod config;
+mod generate;
+#[allow(clippy::module_inception)]
+mod llama;
+mod mlp;
+
+pub use attention::*;
+pub use config::*;
+pub use generate::*;
+pub use llama::*;
+pub use mlp::*;
@@ -0,0 +1,247 @@
+//! Conversion of HuggingFace `transformers` Whisper checkpoints (including Distil-Whisper)
+//! into the GGML layout used by `Whisper::load`.
+use std::collections::HashSet;
+
+use ratchet::{Device, Quantization, Quantizer, Tensor};
+use ratchet_loader::{GGMLCompatible, GGMLFormat, MAGIC_GGML};
+use safetensors::SafeTensors;
+
+use crate::checkpoint::to_f32;
+use crate::{HyperParameters, MelFilters, Whisper, WhisperGGMLHeader};
+
+/// The subset of a HuggingFace `config.json` required to build `HyperParameters`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct HFWhisperConfig {
+    pub vocab_size: i32,
+    pub num_mel_bins: i32,
+    pub d_model: i32,
+    pub encoder_layers: i32,
+    pub encoder_attention_heads: i32,
+    pub decoder_layers: i32,
+    pub decoder_attention_heads: i32,
+    pub max_source_positions: i32,
+    pub max_target_positions: i32,
+}
+
+impl From<&HFWhisperConfig> for HyperParameters {
+    fn from(config: &HFWhisperConfig) -> Self {
+        Self {
+            n_vocab: config.vocab_size,
+            n_audio_ctx: config.max_source_positions,
+            n_audio_state: config.d_model,
+            n_audio_head: config.encoder_attention_heads,
+            n_audio_layer: config.encoder_layers,
+            n_text_ctx: config.max_target_positions,
+            n_text_state: config.d_model,
+            n_text_head: config.decoder_attention_heads,
+            n_text_layer: config.decoder_layers,
+            n_mels: config.num_mel_bins,
+            ftype: 0,
+        }
+    }
+}
+
+/// Maps a HuggingFace tensor name onto the OpenAI name expected by the loaders.
+///
+/// Returns `None` for tensors with no OpenAI equivalent, e.g `proj_out.weight`,
+/// which is tied to the token embedding.
+pub fn openai_tensor_name(hf_name: &str) -> Option<String> {
+    let name = hf_name.strip_prefix("model.").unwrap_or(hf_name);
+    let (module, rest) = name.split_once('.')?;
+    if module != "encoder" && module != "decoder" {
+        return None;
+    }
+
+    if let Some(layer) = rest.strip_prefix("layers.") {
+        let (index, param) = layer.split_once('.')?;
+        let param = [
+            ("self_attn_layer_norm.", "attn_ln."),
+            ("self_attn.q_proj.", "attn.query."),
+            ("self_attn.k_proj.", "attn.key."),
+            ("self_attn.v_proj.", "attn.value."),
+            ("self_attn.out_proj.", "attn.out."),
+            ("encoder_attn_layer_norm.", "cross_attn_ln."),
+            ("encoder_attn.q_proj.", "cross_attn.query."),
+            ("encoder_attn.k_proj.", "cross_attn.key."),
+            ("encoder_attn.v_proj.", "cross_attn.value."),
+            ("encoder_attn.out_proj.", "cross_attn.out."),
+            ("final_layer_norm.", "mlp_ln."),
+            ("fc1.", "mlp.0."),
+            ("fc2.", "mlp.2."),
+        ]
+        .iter()
+        .find_map(|(hf, oai)| param.strip_prefix(hf).map(|p| format!("{}{}", oai, p)))?;
+        return Some(format!("{}.blocks.{}.{}", module, index, param));
+    }
+
+    let mapped = match (module, rest) {
+        (_, "embed_positions.weight") => "positional_embedding".to_string(),
+        ("decoder", "embed_tokens.weight") => "token_embedding.weight".to_string(),
+        ("encoder", r) if r.starts_with("conv1.") || r.starts_with("conv2.") => r.to_string(),
+        ("encoder", r) => format!("ln_post.{}", r.strip_prefix("layer_norm.")?),
+        ("decoder", r) => format!("ln.{}", r.strip_prefix("layer_norm.")?),
+        _ => return None,
+    };
+    Some(format!("{}.{}", module, mapped))
+}
+
+/// Converts a HuggingFace Whisper checkpoint into a GGML file loadable by `Whisper::load`.
+///
+/// HF checkpoints do not ship the mel filterbank, so `filters` must be provided,
+/// e.g from an existing model with the same number of mel bins, or built with [crate::mel_filters].
+pub fn convert_hf_checkpoint<P: AsRef<std::path::Path>>(
+    safetensors_path: P,
+    config_path: P,
+    filters: MelFilters,
+    dst_path: P,
+    dst_quant: Quantization,
+    to_quant: HashSet<&str>,
+) -> anyhow::Result<()> {
+    let config: HFWhisperConfig = serde_json::from_slice(&std::fs::read(config_path.as_ref())?)?;
+    let hparams = HyperParameters::from(&config);
+    anyhow::ensure!(
+        hparams.n_mels == filters.n_mel,
+        "Checkpoint expects {} mel bins, but the filterbank has {}",
+        hparams.n_mels,
+        filters.n_mel
+    );
+
+    let bytes = std::fs::read(safetensors_path.as_ref())?;
+    let st = SafeTensors::deserialize(&bytes)?;
+
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(dst_path)?);
+    let header = WhisperGGMLHeader {
+        format: GGMLFormat::GGML(MAGIC_GGML),
+        hparams,
+        filters,
+        tokens: vec![],
+    };
+    Whisper::write_header(&header, &mut writer)?;
+
+    let quantizer = Quantizer::new(dst_quant);
+    let mut total_write = 0;
+    for (hf_name, view) in st.tensors() {
+        let Some(name) = openai_tensor_name(&hf_name) else {
+            log::info!("Skipping {}", hf_name);
+            continue;
+        };
+        let data = to_f32(&hf_name, view.dtype(), view.data())?;
+        let mut dims = view.shape().to_vec();
+        if name.starts_with("encoder.conv") && name.ends_with(".bias") {
+            //Conv biases are stored as [n_state, 1], matching whisper.cpp
+            dims.push(1);
+        }
+        let tensor = Tensor::from_data(data, dims.into(), Device::CPU);
+
+        let to_write = if to_quant.iter().any(|suffix| name.ends_with(suffix)) {
+            log::info!("Quantizing {}", name);
+            quantizer.quantize(tensor)
+        } else {
+            tensor
+        };
+        total_write += Whisper::write_tensor(&name, to_write, &mut writer)?;
+    }
+    log::info!("Total tensor data written: {} bytes", total_write);
+    Ok(())
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_hf_names() {
+        let cases = [
+            ("model.encoder.conv1.weight", "encoder.conv1.weight"),
+            ("model.encoder.conv2.bias", "encoder.conv2.bias"),
+            (
+                "model.encoder.embed_positions.weight",
+                "encoder.positional_embedding",
+            ),
+            ("model.encoder.layer_norm.bias", "encoder.ln_post.bias"),
+            (
+                "model.encoder.layers.31.self_attn.k_proj.weight",
+                "encoder.blocks.31.attn.key.weight",
+            ),
+            (
+                "model.decoder.embed_tokens.weight",
+                "decoder.token_embedding.weight",
+            ),
+            (
+                "model.decoder.embed_positions.weight",
+                "decoder.positional_embedding",
+            ),
+            (
+                "model.decoder.layers.1.encoder_attn.out_proj.bias",
+                "decoder.blocks.1.cross_attn.out.bias",
+            ),
+            (
+                "model.decoder.layers.1.encoder_attn_layer_norm.weight",
+                "decoder.blocks.1.cross_attn_ln.weight",
+            ),
+            (
+                "model.decoder.layers.0.self_attn_layer_norm.bias",
+                "decoder.blocks.0.attn_ln.bias",
+            ),
+            (
+                "model.decoder.layers.0.fc2.weight",
+                "decoder.blocks.0.mlp.2.weight",
+            ),
+            (
+                "model.decoder.layers.0.final_layer_norm.weight",
+                "decoder.blocks.0.mlp_ln.weight",
+            ),
+            ("model.decoder.layer_norm.weight", "decoder.ln.weight"),
+        ];
+        for (hf, oai) in cases {
+            assert_eq!(openai_tensor_name(hf).as_deref(), Some(oai), "{}", hf);
+        }
+        assert_eq!(openai_tensor_name("proj_out.weight"), None);
+    }
+
+    #[test]
+    fn distil_config_to_hparams() {
+        let config: HFWhisperConfig = serde_json::from_str(
+            r#"{
+                "vocab_size": 51866,
+                "num_mel_bins": 128,
+                "d_model": 1280,
+                "encoder_layers": 32,
+                "encoder_attention_heads": 20,
+                "decoder_layers": 2,
+                "decoder_attention_heads": 20,
+                "max_source_positions": 1500,
+                "max_target_positions": 448,
+                "model_type": "whisper"
+            }"#,
+        )
+        .unwrap();
+        let hparams = HyperParameters::from(&config);
+        assert_eq!(hparams.n_text_layer, 2);
+        assert_eq!(hparams.n_audio_layer, 32);
+        assert_eq!(hparams.n_mels, 128);
+        assert_eq!(hparams.n_text_ctx, 448);
+    }
+
+    #[test]
+    fn fine_tuned_vocabulary_to_hparams() {
+        let config: HFWhisperConfig = serde_json::from_str(
+            r#"{
+                "vocab_size": 51868,
+                "num_mel_bins": 80,
+                "d_model": 384,
+                "encoder_layers": 4,
+                "encoder_attention_heads": 6,
+                "decoder_layers": 4,
+                "decoder_attention_heads": 6,
+                "max_source_positions": 1500,
+                "max_target_positions": 448
+            }"#,
+        )
+        .unwrap();
+        let hparams = HyperParameters::from(&config);
+        let specials = crate::SpecialTokens::new(hparams.n_vocab as usize).unwrap();
+        assert_eq!(specials.n_languages, 102);
+        assert_eq!(specials.ts_end, 51867);
+    }
+}
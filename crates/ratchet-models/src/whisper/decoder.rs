@@ -1,8 +1,9 @@
+use std::cell::RefCell;
 use std::io::{BufRead, Seek};
 
-use ratchet::prelude::*;
+use ratchet::{prelude::*, TensorId};
 use ratchet_loader::GGMLModel;
-use ratchet_nn::{Embedding, KVCache, LayerNorm, Module};
+use ratchet_nn::{Embedding, KVCache, KVEntry, LayerNorm, Module};
 
 use crate::{ResidualAttentionBlock, ResidualAttentionBlockInputs, Whisper};
 
@@ -50,6 +51,22 @@ impl Module for DecoderStem {
     }
 }
 
+/// Cross attention keys & values for each block.
+/// Projected from the encoder output on the first decoding step of a window.
+#[derive(Debug)]
+pub struct CrossAttentionCache {
+    audio_ctx: TensorId,
+    entries: Vec<Option<KVEntry>>,
+}
+
+impl std::ops::Index<usize> for CrossAttentionCache {
+    type Output = Option<KVEntry>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
 #[derive(Debug)]
 pub struct WhisperDecoder {
     stem: DecoderStem,
@@ -57,35 +74,61 @@ pub struct WhisperDecoder {
     mask: Tensor,
     ln_post: LayerNorm,
     cache: KVCache,
+    x_attn_cache: RefCell<Option<CrossAttentionCache>>,
+    batch_size: usize,
+    n_state: usize,
     device: Device,
 }
 
 impl Module for WhisperDecoder {
     type Input = [Tensor; 2];
 
+    /// Logits of the final position of each sequence, [batch_size, 1, n_vocab].
     fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let num_tokens = input[1].shape()[1];
+        self.forward_from(input, num_tokens - 1)
+    }
+}
+
+impl WhisperDecoder {
+    pub const MAX_CACHE: usize = 512;
+
+    /// Logits of the input positions `from..`, [batch_size, num_tokens - from, n_vocab].
+    ///
+    /// The hidden states are sliced on the device before the final layer norm & the projection
+    /// onto the vocabulary, so the positions before `from`, e.g the prompt, are never projected.
+    pub fn forward_from(&self, input: &[Tensor; 2], from: usize) -> anyhow::Result<Tensor> {
         let [audio_ctx, tokens] = input;
+        anyhow::ensure!(
+            tokens.shape()[0] == self.batch_size,
+            "Decoder cache is allocated for a batch of {}, got {} sequences",
+            self.batch_size,
+            tokens.shape()[0]
+        );
         let mut x = self.stem.forward(&StemInput {
             tokens: tokens.clone(),
             offset: self.cache.entries(0),
         })?;
+        let x_attn_cache = self.cross_attention_cache(audio_ctx)?;
         for (block_idx, block) in self.blocks.iter().enumerate() {
             let block_input = ResidualAttentionBlockInputs {
                 x,
                 xa: Some(audio_ctx.clone()),
                 mask: Some(self.mask.clone()),
                 cache: Some(self.cache[block_idx].clone()),
+                x_attn_cache: x_attn_cache[block_idx].clone(),
             };
             x = block.forward(&block_input)?;
         }
+        let [batch_size, num_tokens, n_state]: [usize; 3] = x.shape().try_into()?;
+        let from = from.min(num_tokens - 1);
+        if from > 0 {
+            x = x.slice(&[0..batch_size, from..num_tokens, 0..n_state])?;
+        }
         x = self.ln_post.forward(&x)?;
         let logits = x.matmul(&self.stem.token_embed.weight, true)?;
         Ok(logits)
     }
-}
-
-impl WhisperDecoder {
-    pub const MAX_CACHE: usize = 512;
 
     pub fn cache_mut(&mut self) -> &mut KVCache {
         &mut self.cache
@@ -93,6 +136,68 @@ impl WhisperDecoder {
 
     pub fn reset(&mut self) {
         self.cache.reset();
+        self.x_attn_cache.replace(None);
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Resets the decoder, reallocating the self attention cache if `batch_size` has changed.
+    ///
+    /// The cache holds `MAX_CACHE` entries for every sequence, so its size grows linearly
+    /// with the batch.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.reset();
+        if batch_size != self.batch_size {
+            let shape = shape![Self::MAX_CACHE, batch_size, self.n_state];
+            self.cache = KVCache::new(self.blocks.len() as _, &shape, &self.device);
+            self.batch_size = batch_size;
+        }
+    }
+
+    /// Returns the cross attention cache, projecting `audio_ctx` if it isn't cached yet.
+    fn cross_attention_cache(
+        &self,
+        audio_ctx: &Tensor,
+    ) -> anyhow::Result<std::cell::Ref<'_, CrossAttentionCache>> {
+        let stale = !matches!(
+            self.x_attn_cache.borrow().as_ref(),
+            Some(cache) if cache.audio_ctx == audio_ctx.id()
+        );
+        if stale {
+            let mut entries = Vec::with_capacity(self.blocks.len());
+            let mut writes = Vec::with_capacity(2 * self.blocks.len());
+            for block in &self.blocks {
+                let projected = block.project_cross_attention(audio_ctx)?;
+                entries.push(projected.map(|(entry, kv_writes)| {
+                    writes.extend(kv_writes);
+                    entry
+                }));
+            }
+            Self::resolve_writes(writes)?;
+            self.x_attn_cache.replace(Some(CrossAttentionCache {
+                audio_ctx: audio_ctx.id(),
+                entries,
+            }));
+        }
+        Ok(std::cell::Ref::map(self.x_attn_cache.borrow(), |c| {
+            c.as_ref().unwrap()
+        }))
+    }
+
+    /// Runs `writes` in a single submission, by resolving an output which reads from each.
+    ///
+    /// The writes are consumed, as a write is only performed in place while its output has
+    /// a single consumer.
+    fn resolve_writes(writes: Vec<Tensor>) -> anyhow::Result<()> {
+        let mut reads = writes.into_iter().map(|w| w.slice(&[0..1, 0..1, 0..1]));
+        if let Some(first) = reads.next() {
+            reads
+                .try_fold(first?, |acc, read| acc.add(&read?))?
+                .resolve()?;
+        }
+        Ok(())
     }
 
     fn load_mask(n_ctx: usize, device: &Device) -> Tensor {
@@ -138,7 +243,10 @@ impl WhisperDecoder {
             blocks,
             mask: Self::load_mask(hparams.n_text_ctx as _, device),
             ln_post: LayerNorm::new(lt("weight")?, Some(lt("bias")?), 1e-5),
-            cache: KVCache::new(n_layers, &shape![1, Self::MAX_CACHE, n_state], device),
+            cache: KVCache::new(n_layers, &shape![Self::MAX_CACHE, 1, n_state], device),
+            x_attn_cache: RefCell::new(None),
+            batch_size: 1,
+            n_state,
             device: device.clone(),
         })
     }
@@ -146,7 +254,11 @@ impl WhisperDecoder {
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    use crate::{DecodingOptions, DecodingOptionsBuilder, Whisper, WhisperDecoder};
+    use super::StemInput;
+    use crate::{
+        DecodingOptions, DecodingOptionsBuilder, ResidualAttentionBlockInputs, Whisper,
+        WhisperDecoder,
+    };
     use hf_hub::api::sync::Api;
     use ndarray::{s, Axis};
     use ndarray_stats::QuantileExt;
@@ -251,13 +363,109 @@ def ground(options):
 
         let ground_logits = ground_truth(&audio_path.to_string_lossy(), options)?;
 
+        // the decoder only projects the final position
         let all_equal = all_logits
             .iter()
             .zip(ground_logits.iter())
-            .all(|(our, their)| their.all_close(our, 1e-4, 1e-4).is_ok());
+            .all(|(our, their)| {
+                let their = their.to_ndarray_view::<f32>();
+                let their_last = their.slice(s![.., -1.., ..]).to_owned().into_dyn();
+                Tensor::from(their_last).all_close(our, 1e-4, 1e-4).is_ok()
+            });
 
         assert!(all_equal);
 
         Ok(())
     }
+
+    #[test]
+    fn forward_from_matches_full_projection() -> anyhow::Result<()> {
+        log_init();
+        let api = Api::new().unwrap();
+        let model = api.model("ggerganov/whisper.cpp".to_string());
+        let path = model.get("ggml-tiny.bin").unwrap();
+        let dataset = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
+        let hs_npy = load_npy(dataset.get("jfk_tiny_encoder_hs.npy").unwrap());
+
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        let gg_disk = Whisper::load_ggml(&mut reader).unwrap();
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let audio_ctx = Tensor::from_data(hs_npy, shape![1, 1500, 384], device.clone());
+        let mut decoder = WhisperDecoder::load(&gg_disk, &mut reader, &device)?;
+
+        // a short prompt ahead of the SOT sequence, which starts at 3
+        let tokens = vec![50361, 2221, 13, 50258, 50259, 50359];
+        let input = || {
+            let tokens_t = Tensor::from_data(&tokens, shape![1, tokens.len()], device.clone());
+            [audio_ctx.clone(), tokens_t]
+        };
+        let mut logits_from = |pass: u64, from: usize| -> anyhow::Result<Tensor> {
+            decoder.reset();
+            decoder.device.try_gpu()?.begin_pass(pass);
+            let logits = decoder.forward_from(&input(), from)?.resolve()?;
+            Ok(logits.to(&Device::CPU)?)
+        };
+
+        let full = logits_from(0, 0)?;
+        let from_sot = logits_from(1, 3)?;
+        let last = logits_from(2, tokens.len() - 1)?;
+        assert_eq!(full.shape()[1], 6);
+        assert_eq!(from_sot.shape()[1], 3);
+        assert_eq!(last.shape()[1], 1);
+
+        let full = full.to_ndarray_view::<f32>();
+        let rows = |range: std::ops::RangeFrom<isize>| {
+            Tensor::from(full.slice(s![.., range, ..]).to_owned().into_dyn())
+        };
+        rows(3..).all_close(&from_sot, 1e-4, 1e-4)?;
+        rows(-1..).all_close(&last, 1e-4, 1e-4)?;
+        Ok(())
+    }
+
+    #[test]
+    fn cached_cross_attention_matches_uncached() -> anyhow::Result<()> {
+        log_init();
+        let api = Api::new().unwrap();
+        let model = api.model("ggerganov/whisper.cpp".to_string());
+        let path = model.get("ggml-tiny.bin").unwrap();
+        let dataset = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
+        let hs_npy = load_npy(dataset.get("jfk_tiny_encoder_hs.npy").unwrap());
+
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        let gg_disk = Whisper::load_ggml(&mut reader).unwrap();
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let audio_ctx = Tensor::from_data(hs_npy, shape![1, 1500, 384], device.clone());
+        let mut decoder = WhisperDecoder::load(&gg_disk, &mut reader, &device)?;
+        let tokens = Tensor::from_data([50258, 50259, 50359], shape![1, 3], device.clone());
+
+        decoder.device.try_gpu()?.begin_pass(0);
+        let cached = decoder
+            .forward(&[audio_ctx.clone(), tokens.clone()])?
+            .resolve()?
+            .to(&Device::CPU)?;
+
+        // the same forward, projecting the audio context within every block
+        decoder.reset();
+        decoder.device.try_gpu()?.begin_pass(1);
+        let mut x = decoder.stem.forward(&StemInput { tokens, offset: 0 })?;
+        for (block_idx, block) in decoder.blocks.iter().enumerate() {
+            x = block.forward(&ResidualAttentionBlockInputs {
+                x,
+                xa: Some(audio_ctx.clone()),
+                mask: Some(decoder.mask.clone()),
+                cache: Some(decoder.cache[block_idx].clone()),
+                x_attn_cache: None,
+            })?;
+        }
+        let x = x.slice(&[0..1, 2..3, 0..384])?;
+        let uncached = decoder
+            .ln_post
+            .forward(&x)?
+            .matmul(&decoder.stem.token_embed.weight, true)?
+            .resolve()?
+            .to(&Device::CPU)?;
+
+        cached.all_close(&uncached, 1e-4, 1e-4)?;
+        Ok(())
+    }
 }
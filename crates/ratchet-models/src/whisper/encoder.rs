@@ -77,6 +77,7 @@ impl Module for WhisperEncoder {
                 xa: None,
                 mask: None,
                 cache: None,
+                x_attn_cache: None,
             };
             x = block.forward(&input)?;
         }
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use ndarray::s;
+use ratchet::Tensor;
+
+use crate::{LogitMutator, WhisperTokenizer};
+
+/// Adds fixed biases to the logits of chosen tokens, e.g to boost product names & jargon
+/// Whisper keeps misspelling. Negative biases discourage tokens instead.
+///
+/// A phrase spanning several tokens is boosted one token at a time: its first token always,
+/// and each following token only once the tokens before it have been sampled after
+/// `sample_begin`, so a prompt ending midway through a phrase doesn't count.
+#[derive(Debug, Clone, Default)]
+pub struct LogitBias {
+    tokens: HashMap<i32, f32>,
+    phrases: Vec<(Vec<i32>, f32)>,
+    sample_begin: usize,
+}
+
+impl LogitBias {
+    pub fn new(sample_begin: usize) -> Self {
+        Self {
+            sample_begin,
+            ..Default::default()
+        }
+    }
+
+    /// Biases `token` wherever it appears.
+    pub fn token(mut self, token: i32, bias: f32) -> Self {
+        *self.tokens.entry(token).or_default() += bias;
+        self
+    }
+
+    pub fn phrase_tokens(mut self, tokens: Vec<i32>, bias: f32) -> Self {
+        if !tokens.is_empty() {
+            self.phrases.push((tokens, bias));
+        }
+        self
+    }
+
+    /// Biases `phrase` as it appears mid sentence, i.e with a leading space.
+    pub fn phrase(
+        self,
+        tokenizer: &WhisperTokenizer,
+        phrase: &str,
+        bias: f32,
+    ) -> Result<Self, tokenizers::Error> {
+        let tokens = tokenizer.encode(format!(" {}", phrase.trim()).as_str(), false)?;
+        Ok(self.phrase_tokens(tokens, bias))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty() && self.phrases.is_empty()
+    }
+
+    /// Index of the next token of `phrase`, the length of the longest prefix of `phrase`
+    /// that `sampled` ends with.
+    fn next_in_phrase(phrase: &[i32], sampled: &[i32]) -> usize {
+        (1..phrase.len())
+            .rev()
+            .find(|&k| sampled.ends_with(&phrase[..k]))
+            .unwrap_or(0)
+    }
+}
+
+impl LogitMutator for LogitBias {
+    fn apply(&self, logits: Tensor, tokens: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        let mut nd_logits = logits.into_ndarray::<f32>();
+        let n_vocab = nd_logits.shape()[1];
+        for (token, bias) in self.tokens.iter().filter(|(t, _)| (**t as usize) < n_vocab) {
+            nd_logits
+                .slice_mut(s![.., *token as usize])
+                .map_inplace(|el| *el += bias);
+        }
+
+        if !self.phrases.is_empty() {
+            let nd_tokens = tokens.unwrap().clone().into_ndarray::<i32>();
+            for (k, sampled) in nd_tokens.outer_iter().enumerate() {
+                let sampled = sampled
+                    .iter()
+                    .skip(self.sample_begin)
+                    .copied()
+                    .collect::<Vec<_>>();
+                for (phrase, bias) in &self.phrases {
+                    let next = phrase[Self::next_in_phrase(phrase, &sampled)];
+                    nd_logits[[k, next as usize].as_slice()] += bias;
+                }
+            }
+        }
+        Ok(Tensor::from(nd_logits))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use ratchet::{shape, Device};
+
+    fn apply(bias: &LogitBias, sampled: &[Vec<i32>]) -> Vec<Vec<f32>> {
+        let batch = sampled.len();
+        let logits = Tensor::from_data(vec![0f32; batch * 8], shape![batch, 8], Device::CPU);
+        let flat = sampled.iter().flatten().copied().collect::<Vec<_>>();
+        let tokens = Tensor::from_data(flat, shape![batch, sampled[0].len()], Device::CPU);
+        let biased = bias.apply(logits, Some(&tokens)).unwrap();
+        biased
+            .into_ndarray::<f32>()
+            .outer_iter()
+            .map(|row| row.iter().copied().collect())
+            .collect()
+    }
+
+    #[test]
+    fn token_biases_accumulate() {
+        let bias = LogitBias::new(0)
+            .token(2, 1.5)
+            .token(2, 0.5)
+            .token(5, -3.)
+            .token(100, 1.);
+        let biased = apply(&bias, &[vec![0]]);
+        assert_eq!(biased[0], [0., 0., 2., 0., 0., -3., 0., 0.]);
+    }
+
+    #[test]
+    fn phrases_are_boosted_token_by_token() {
+        let bias = LogitBias::new(0).phrase_tokens(vec![3, 4, 6], 2.);
+        let biased = apply(
+            &bias,
+            &[vec![0, 1, 1], vec![0, 1, 3], vec![0, 3, 4], vec![3, 4, 6]],
+        );
+        let boosted = biased
+            .iter()
+            .map(|row| row.iter().position(|l| *l > 0.).unwrap())
+            .collect::<Vec<_>>();
+        // once complete, the phrase may start over
+        assert_eq!(boosted, [3, 4, 6, 3]);
+        assert!(biased.iter().all(|row| row.iter().sum::<f32>() == 2.));
+    }
+
+    #[test]
+    fn phrases_ignore_the_prompt() {
+        let bias = LogitBias::new(2).phrase_tokens(vec![3, 4, 6], 2.);
+        let biased = apply(&bias, &[vec![1, 3, 5], vec![1, 3, 3]]);
+        assert_eq!(biased[0][3], 2.);
+        assert_eq!(biased[1][4], 2.);
+    }
+}
@@ -1,6 +1,10 @@
+mod logit_bias;
+mod repetition_penalty;
 mod select_language;
 mod timestamp_rules;
 
+pub use logit_bias::*;
+pub use repetition_penalty::*;
 pub use select_language::*;
 pub use timestamp_rules::*;
 
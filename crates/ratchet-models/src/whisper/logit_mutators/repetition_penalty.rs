@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+
+use ratchet::Tensor;
+
+use crate::{LogitMutator, SpecialTokens};
+
+/// Penalises text tokens that have already been sampled, as in CTRL:
+/// positive logits are divided by `penalty` & negative logits multiplied by it.
+///
+/// Only tokens sampled after `sample_begin` are penalised, so the prompt is free to be repeated.
+#[derive(Debug, derive_new::new)]
+pub struct RepetitionPenalty {
+    pub penalty: f32,
+    pub sample_begin: usize,
+    pub specials: SpecialTokens,
+}
+
+impl LogitMutator for RepetitionPenalty {
+    fn apply(&self, logits: Tensor, tokens: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        let nd_tokens = tokens.unwrap().clone().into_ndarray::<i32>();
+        let mut nd_logits = logits.into_ndarray::<f32>();
+        for (k, sampled) in nd_tokens.outer_iter().enumerate() {
+            let seen = sampled
+                .iter()
+                .skip(self.sample_begin)
+                .filter(|t| **t < self.specials.eot)
+                .collect::<HashSet<_>>();
+            for token in seen {
+                let logit = &mut nd_logits[[k, *token as usize].as_slice()];
+                *logit = match *logit > 0. {
+                    true => *logit / self.penalty,
+                    false => *logit * self.penalty,
+                };
+            }
+        }
+        Ok(Tensor::from(nd_logits))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use ratchet::{shape, Device};
+
+    #[test]
+    fn penalises_sampled_text_tokens() {
+        let specials = SpecialTokens::default();
+        let mut data = vec![0f32; specials.n_vocab];
+        data[1] = 4.;
+        data[2] = -4.;
+        data[3] = 4.;
+        data[specials.ts_begin as usize] = 4.;
+        let logits = Tensor::from_data(data, shape![1, specials.n_vocab], Device::CPU);
+        // token 3 is only part of the prompt
+        let tokens = [3, specials.sot, 1, 2, 2, specials.ts_begin];
+        let tokens = Tensor::from_data(tokens, shape![1, 6], Device::CPU);
+
+        let penalty = RepetitionPenalty::new(2., 2, specials);
+        let penalised = penalty.apply(logits, Some(&tokens)).unwrap();
+        let penalised = penalised.into_ndarray::<f32>();
+        let at = |t: i32| penalised[[0, t as usize].as_slice()];
+        assert_eq!(at(1), 2.);
+        assert_eq!(at(2), -8.);
+        assert_eq!(at(3), 4.);
+        assert_eq!(at(specials.ts_begin), 4.);
+    }
+}
@@ -1,26 +1,158 @@
-use ndarray::{s, Dimension};
-use ndarray_stats::QuantileExt;
+use ndarray::{s, Axis};
 use ratchet::{prelude::shape, NDArrayExt, Tensor};
 
-use crate::{LogitMutator, WhisperTokenizer};
-pub struct SelectLanguage;
+use crate::{LogitMutator, SpecialTokens, LANGUAGES};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LanguageProbability {
+    /// ISO 639-1 code, as in [LANGUAGES].
+    pub language: String,
+    pub token: i32,
+    pub probability: f32,
+}
+
+/// Probabilities of every language supported by the model, sorted most likely first.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LanguageDetection {
+    pub probabilities: Vec<LanguageProbability>,
+}
+
+impl LanguageDetection {
+    pub fn best(&self) -> &LanguageProbability {
+        &self.probabilities[0]
+    }
+
+    pub fn top_k(&self, k: usize) -> &[LanguageProbability] {
+        &self.probabilities[..k.min(self.probabilities.len())]
+    }
+
+    pub fn probability(&self, language: &str) -> Option<f32> {
+        self.probabilities
+            .iter()
+            .find(|p| p.language == language)
+            .map(|p| p.probability)
+    }
+}
+
+#[derive(Debug, derive_new::new)]
+pub struct SelectLanguage {
+    pub specials: SpecialTokens,
+    /// Language tokens detection is restricted to, all languages if `None`.
+    #[new(default)]
+    pub allowed: Option<Vec<i32>>,
+}
+
+impl SelectLanguage {
+    /// Restricts detection to the given language codes.
+    pub fn with_allowed(mut self, codes: &[String]) -> anyhow::Result<Self> {
+        let languages = self.specials.languages();
+        let allowed = codes
+            .iter()
+            .map(|code| {
+                self.specials
+                    .language_codes()
+                    .iter()
+                    .position(|l| l == code)
+                    .map(|p| *languages.start() + p as i32)
+                    .ok_or_else(|| anyhow::anyhow!("Language {} not supported by this model", code))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(!allowed.is_empty(), "At least one language must be allowed");
+        self.allowed = Some(allowed);
+        Ok(self)
+    }
+
+    /// Softmax over the (allowed) language tokens, using the logits of the final position.
+    pub fn detect(&self, logits: Tensor) -> LanguageDetection {
+        let languages = self.specials.languages();
+        let nd_logits = logits.into_ndarray::<f32>();
+        let last = nd_logits.index_axis(Axis(0), nd_logits.len_of(Axis(0)) - 1);
+        let mut language_logits = last
+            .slice(s![*languages.start() as usize..=*languages.end() as usize])
+            .to_owned();
+        if let Some(allowed) = &self.allowed {
+            for (token, logit) in languages.clone().zip(language_logits.iter_mut()) {
+                if !allowed.contains(&token) {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        }
+        let probs = language_logits.softmax(0);
+
+        let mut probabilities = languages
+            .zip(probs.iter())
+            .enumerate()
+            .filter(|(_, (token, _))| match &self.allowed {
+                Some(allowed) => allowed.contains(token),
+                None => true,
+            })
+            .map(|(i, (token, probability))| LanguageProbability {
+                language: LANGUAGES
+                    .get(i)
+                    .map_or_else(|| token.to_string(), |l| l.to_string()),
+                token,
+                probability: *probability,
+            })
+            .collect::<Vec<_>>();
+        probabilities.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+        LanguageDetection { probabilities }
+    }
+}
 
 impl LogitMutator for SelectLanguage {
     fn apply(&self, logits: Tensor, _: Option<&Tensor>) -> Result<Tensor, anyhow::Error> {
         let device = logits.device().clone();
-        let mut nd_logits = logits.into_ndarray::<f32>();
-        nd_logits
-            .slice_mut(s![.., ..WhisperTokenizer::LANGUAGES_BEGIN])
-            .map_inplace(move |el| *el = f32::NEG_INFINITY);
+        let best = self.detect(logits).best().token as u32;
+        Ok(Tensor::from_data([best], shape![1], device))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use ratchet::Device;
+
+    fn logits(specials: SpecialTokens, favour: &[(i32, f32)]) -> Tensor {
+        let mut data = vec![0f32; specials.n_vocab];
+        for (token, logit) in favour {
+            data[*token as usize] = *logit;
+        }
+        // non-language tokens are never selected
+        data[specials.eot as usize] = 100.;
+        Tensor::from_data(data, shape![1, specials.n_vocab], Device::CPU)
+    }
 
-        nd_logits
-            .slice_mut(s![.., WhisperTokenizer::LANGUAGES_END..])
-            .map_inplace(move |el| *el = f32::NEG_INFINITY);
+    #[test]
+    fn language_probabilities() {
+        let specials = SpecialTokens::default();
+        let en = *specials.languages().start();
+        let (de, fr) = (en + 2, en + 6);
+        let logits = logits(specials, &[(en, 3.), (de, 2.), (fr, 1.)]);
 
-        let language_tokens_probs = nd_logits.softmax(nd_logits.ndim() - 1);
+        let detection = SelectLanguage::new(specials).detect(logits.clone());
+        assert_eq!(detection.probabilities.len(), specials.n_languages);
+        let total = detection
+            .probabilities
+            .iter()
+            .map(|p| p.probability)
+            .sum::<f32>();
+        assert!((total - 1.).abs() < 1e-5);
+        let top = detection.top_k(3);
+        assert_eq!(
+            top.iter().map(|p| p.language.as_str()).collect::<Vec<_>>(),
+            ["en", "de", "fr"]
+        );
 
-        let argmax_dims = language_tokens_probs.argmax_skipnan().unwrap();
-        let argmax: u32 = argmax_dims[argmax_dims.ndim() - 1] as _;
-        Ok(Tensor::from_data([argmax], shape![1], device))
+        let allowed = SelectLanguage::new(specials)
+            .with_allowed(&["fr".to_string(), "de".to_string()])
+            .unwrap();
+        let detection = allowed.detect(logits);
+        assert_eq!(detection.probabilities.len(), 2);
+        assert_eq!(detection.best().token, de);
+        let expected = 1. / (1. + (-1f32).exp());
+        assert!((detection.probability("de").unwrap() - expected).abs() < 1e-5);
+        assert!(SelectLanguage::new(specials)
+            .with_allowed(&["xx".to_string()])
+            .is_err());
     }
 }
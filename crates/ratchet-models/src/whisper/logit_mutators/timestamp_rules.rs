@@ -2,47 +2,54 @@ use ndarray::s;
 use ndarray_stats::QuantileExt;
 use ratchet::{NDArrayExt, Tensor};
 
-use crate::{LogitMutator, WhisperTokenizer};
+use crate::{LogitMutator, SpecialTokens};
 
 #[derive(Debug, derive_new::new)]
 pub struct ApplyTimestampRules {
     pub sample_begin: usize,
     pub max_initial_timestamp_index: Option<usize>,
+    pub specials: SpecialTokens,
 }
 
 impl LogitMutator for ApplyTimestampRules {
     fn apply(&self, logits: Tensor, tokens: Option<&Tensor>) -> anyhow::Result<Tensor> {
+        let SpecialTokens {
+            eot,
+            no_timestamps,
+            ts_begin,
+            ..
+        } = self.specials;
         let nd_tokens = tokens.unwrap().clone().into_ndarray::<i32>();
         let mut nd_logits = logits.into_ndarray::<f32>();
 
         nd_logits
-            .slice_mut(s![.., WhisperTokenizer::NO_TIMESTAMPS as usize])
+            .slice_mut(s![.., no_timestamps as usize])
             .map_inplace(move |el| *el = f32::NEG_INFINITY);
 
         for k in 0..nd_tokens.shape()[0] {
             let sampled_tokens = nd_tokens.slice(s![k, self.sample_begin..]);
             let sample_len = sampled_tokens.len();
 
-            let last_was_timestamp = !sampled_tokens.is_empty()
-                && sampled_tokens[sample_len - 1] >= WhisperTokenizer::TS_BEGIN;
-            let penultimate_was_timestamp = sampled_tokens.len() < 2
-                || sampled_tokens[sample_len - 2] >= WhisperTokenizer::TS_BEGIN;
+            let last_was_timestamp =
+                !sampled_tokens.is_empty() && sampled_tokens[sample_len - 1] >= ts_begin;
+            let penultimate_was_timestamp =
+                sampled_tokens.len() < 2 || sampled_tokens[sample_len - 2] >= ts_begin;
 
             if last_was_timestamp {
                 if penultimate_was_timestamp {
                     nd_logits
-                        .slice_mut(s![k, WhisperTokenizer::TS_BEGIN..])
+                        .slice_mut(s![k, ts_begin..])
                         .map_inplace(move |el| *el = f32::NEG_INFINITY);
                 } else {
                     nd_logits
-                        .slice_mut(s![k, ..WhisperTokenizer::EOT])
+                        .slice_mut(s![k, ..eot])
                         .map_inplace(move |el| *el = f32::NEG_INFINITY);
                 }
             }
 
             let timestamps = sampled_tokens
                 .iter()
-                .filter(|x| **x >= WhisperTokenizer::TS_BEGIN)
+                .filter(|x| **x >= ts_begin)
                 .collect::<Vec<_>>();
 
             if !timestamps.is_empty() {
@@ -54,38 +61,64 @@ impl LogitMutator for ApplyTimestampRules {
                     timestamps[timestamps.len() - 1] + 1
                 };
                 nd_logits
-                    .slice_mut(s![k, WhisperTokenizer::TS_BEGIN..timestamp_last])
+                    .slice_mut(s![k, ts_begin..timestamp_last])
                     .map_inplace(move |el| *el = f32::NEG_INFINITY);
             }
         }
         if nd_tokens.shape()[1] == self.sample_begin {
             // suppress generating non-timestamp tokens at the beginning
             nd_logits
-                .slice_mut(s![.., ..WhisperTokenizer::TS_BEGIN])
+                .slice_mut(s![.., ..ts_begin])
                 .map_inplace(move |el| *el = f32::NEG_INFINITY);
 
             if self.max_initial_timestamp_index.is_some() {
-                let last_allowed = (WhisperTokenizer::TS_BEGIN as usize)
-                    + self.max_initial_timestamp_index.unwrap();
+                let last_allowed = (ts_begin as usize) + self.max_initial_timestamp_index.unwrap();
                 nd_logits
                     .slice_mut(s![.., last_allowed + 1..])
                     .map_inplace(move |el| *el = f32::NEG_INFINITY);
             }
         }
 
+        // if the sum of probability over timestamps is above any other token, sample timestamp
         let logprobs = nd_logits.log_softmax(1);
-        for _k in 0..nd_tokens.shape()[0] {
-            let timestamp_logprob = logprobs
-                .slice(s![.., WhisperTokenizer::TS_BEGIN..])
-                .logsumexp(1);
-            let text_logprobs = logprobs.slice(s![.., ..WhisperTokenizer::TS_BEGIN]);
-            let max_text_token_logprob = text_logprobs.max()?;
-            if timestamp_logprob > *max_text_token_logprob {
+        for k in 0..nd_tokens.shape()[0] {
+            let timestamp_logprob = logprobs.slice(s![k, ts_begin..]).logsumexp(0);
+            let max_text_token_logprob = *logprobs.slice(s![k, ..ts_begin]).max()?;
+            if timestamp_logprob > max_text_token_logprob {
                 nd_logits
-                    .slice_mut(s![.., ..WhisperTokenizer::TS_BEGIN])
+                    .slice_mut(s![k, ..ts_begin])
                     .map_inplace(move |el| *el = f32::NEG_INFINITY);
             }
         }
         Ok(Tensor::from(nd_logits))
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use ratchet::{shape, Device};
+
+    #[test]
+    fn timestamp_mass_is_judged_per_sequence() {
+        let specials = SpecialTokens::default();
+        let n_vocab = specials.n_vocab;
+        let ts_begin = specials.ts_begin as usize;
+        let mut data = vec![0f32; 2 * n_vocab];
+        // the first sequence favours timestamps, the second a text token
+        data[ts_begin..n_vocab].fill(5.);
+        data[n_vocab + 1] = 20.;
+        let logits = Tensor::from_data(data, shape![2, n_vocab], Device::CPU);
+        let tokens = [specials.sot, 1, specials.sot, 1];
+        let tokens = Tensor::from_data(tokens, shape![2, 2], Device::CPU);
+
+        let rules = ApplyTimestampRules::new(1, None, specials);
+        let logits = rules.apply(logits, Some(&tokens)).unwrap();
+        let logits = logits.into_ndarray::<f32>();
+        let at = |k: usize, t: usize| logits[[k, t].as_slice()];
+        assert_eq!(at(0, 1), f32::NEG_INFINITY);
+        assert_eq!(at(0, ts_begin), 5.);
+        assert_eq!(at(1, 1), 20.);
+        assert_eq!(at(1, ts_begin), 0.);
+    }
+}
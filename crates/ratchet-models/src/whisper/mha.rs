@@ -25,6 +25,22 @@ impl MultiHeadAttention {
             dk,
         }
     }
+
+    /// Projects the audio context to keys & values, these are constant for an entire window.
+    ///
+    /// The projections are written into a newly allocated entry by the returned writes,
+    /// which are lazy & must be resolved before the entry is read.
+    pub fn project_kv(&self, xa: &Tensor) -> anyhow::Result<(KVEntry, [Tensor; 2])> {
+        let mut entry = KVEntry::allocate(xa.shape(), xa.device());
+        entry.entries = xa.shape()[1];
+        let k = entry
+            .k_cache
+            .index_write(&self.k.forward(xa)?, rvec![0, 0, 0])?;
+        let v = entry
+            .v_cache
+            .index_write(&self.v.forward(xa)?, rvec![0, 0, 0])?;
+        Ok((entry, [k, v]))
+    }
 }
 
 #[derive(Debug, derive_new::new)]
@@ -51,39 +67,54 @@ impl Module for MultiHeadAttention {
         let q = self.q.forward(x)?;
         let [bs, n_ctx, n_state]: [usize; 3] = q.shape().try_into()?;
 
-        let to_project = xa.as_ref().unwrap_or(x);
-        let k = self.k.forward(to_project)?;
-        let v = self.v.forward(to_project)?;
-
-        let (k, v) = if let Some(kv) = cache {
-            let prev_entries = kv.entries;
-            let new_entries = prev_entries + n_ctx;
-            let k_cache = kv
-                .k_cache
-                .index_write(&k, rvec![0, prev_entries, 0])?
-                .view(shape![bs, new_entries, n_state])?;
-            let v_cache = kv
-                .v_cache
-                .index_write(&v, rvec![0, prev_entries, 0])?
-                .view(shape![bs, new_entries, n_state])?;
-            (k_cache, v_cache)
-        } else {
-            (k, v)
+        let (k, v, time_major) = match (xa, cache) {
+            //Cross attention cache holds the already projected audio context
+            (Some(_), Some(kv)) => (kv.k_cache.clone(), kv.v_cache.clone(), false),
+            (Some(xa), None) => (self.k.forward(xa)?, self.v.forward(xa)?, false),
+            (None, Some(kv)) => {
+                //The self attention cache is [MAX_CACHE, bs, n_state], such that the
+                //entries for all sequences form a contiguous prefix of the buffer.
+                let k = Self::to_time_major(self.k.forward(x)?)?;
+                let v = Self::to_time_major(self.v.forward(x)?)?;
+                let prev_entries = kv.entries;
+                let new_entries = prev_entries + n_ctx;
+                let k_cache = kv
+                    .k_cache
+                    .index_write(&k, rvec![prev_entries, 0, 0])?
+                    .view(shape![new_entries, bs, n_state])?;
+                let v_cache = kv
+                    .v_cache
+                    .index_write(&v, rvec![prev_entries, 0, 0])?
+                    .view(shape![new_entries, bs, n_state])?;
+                (k_cache, v_cache, true)
+            }
+            (None, None) => (self.k.forward(x)?, self.v.forward(x)?, false),
         };
 
-        self.qkv_attention(q, k, v, mask, xa.is_some(), *is_causal)
+        self.qkv_attention(q, k, v, mask, *is_causal, time_major)
     }
 }
 
 impl MultiHeadAttention {
+    /// [bs, n_ctx, n_state] -> [n_ctx, bs, n_state], free if either dim is 1.
+    fn to_time_major(x: Tensor) -> anyhow::Result<Tensor> {
+        let [bs, n_ctx, n_state]: [usize; 3] = x.shape().try_into()?;
+        if bs == 1 || n_ctx == 1 {
+            x.view(shape![n_ctx, bs, n_state])
+        } else {
+            x.permute(&[1, 0, 2])
+        }
+    }
+
+    /// `time_major` keys & values are [n_ctx, bs, n_state] rather than [bs, n_ctx, n_state].
     fn qkv_attention(
         &self,
         q: Tensor,
         k: Tensor,
         v: Tensor,
         mask: &Option<Tensor>,
-        x_attn: bool,
         is_causal: bool,
+        time_major: bool,
     ) -> anyhow::Result<Tensor> {
         let [bs, n_ctx, n_state]: [usize; 3] = q.shape().try_into()?;
         let [k0, k1, _]: [usize; 3] = k.shape().try_into()?;
@@ -95,13 +126,15 @@ impl MultiHeadAttention {
         let ks = shape![k0, k1, self.n_heads, hdim];
         let vs = shape![v0, v1, self.n_heads, hdim];
 
+        //Both layouts are permuted to [bs, n_heads, hdim, k_ctx] & [bs, n_heads, k_ctx, hdim]
+        let (k_perm, v_perm) = if time_major {
+            ([1, 2, 3, 0], [1, 2, 0, 3])
+        } else {
+            ([0, 2, 3, 1], [0, 2, 1, 3])
+        };
         let q = q.view(qs)?.permute(&[0, 2, 1, 3])?.mul(&self.dk)?;
-        let k = k.view(ks)?.permute(&[0, 2, 3, 1])?.mul(&self.dk)?;
-        let v = v.view(vs)?.permute(&[0, 2, 1, 3])?;
-
-        if x_attn {
-            //TODO: static caching
-        }
+        let k = k.view(ks)?.permute(&k_perm)?.mul(&self.dk)?;
+        let v = v.view(vs)?.permute(&v_perm)?;
 
         let mut qk = q.matmul(&k, false)?;
 
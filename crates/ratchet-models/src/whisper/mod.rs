@@ -1,3 +1,5 @@
+#[cfg(not(target_arch = "wasm32"))]
+mod convert;
 mod decoder;
 mod encoder;
 mod logit_mutators;
@@ -7,12 +9,19 @@ mod options;
 mod residual_block;
 mod samplers;
 mod spectrogram;
+mod streaming;
 mod task;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test_util;
 mod tokenizer;
 mod transcribe;
 mod transcript;
+mod vad;
 mod whisper;
+mod writers;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use convert::*;
 pub use decoder::*;
 pub use encoder::*;
 pub use logit_mutators::*;
@@ -22,8 +31,11 @@ pub use options::*;
 pub use residual_block::*;
 pub use samplers::*;
 pub use spectrogram::*;
+pub use streaming::*;
 pub use task::*;
 pub use tokenizer::*;
 pub use transcribe::*;
 pub use transcript::*;
+pub use vad::*;
 pub use whisper::*;
+pub use writers::*;
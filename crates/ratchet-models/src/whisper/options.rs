@@ -1,3 +1,4 @@
+use crate::{DecodingResult, SpecialTokens, VadOptions, WhisperTokenizer};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
@@ -8,6 +9,16 @@ pub enum Language {
     Token(i32),
 }
 
+impl Language {
+    /// The [crate::LANGUAGES] code, if this is a language supported by the model.
+    pub fn code(&self, specials: &SpecialTokens) -> Option<String> {
+        match self {
+            Language::String(s) => Some(s.clone()),
+            Language::Token(t) => specials.language_code(*t).map(String::from),
+        }
+    }
+}
+
 #[cfg_attr(target_arch = "wasm32", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub enum Prompt {
@@ -15,6 +26,15 @@ pub enum Prompt {
     Tokens(Vec<i32>),
 }
 
+impl Prompt {
+    pub fn tokens(&self, tokenizer: &WhisperTokenizer) -> Result<Vec<i32>, tokenizers::Error> {
+        match self {
+            Prompt::Tokens(tokens) => Ok(tokens.clone()),
+            Prompt::Text(text) => tokenizer.encode(format!(" {}", text).as_str(), false),
+        }
+    }
+}
+
 #[cfg_attr(
     target_arch = "wasm32",
     wasm_bindgen,
@@ -43,21 +63,86 @@ impl From<Task> for i32 {
 )]
 #[derive(Debug, Clone)]
 pub struct DecodingOptions {
-    pub(crate) task: Task,                         // default: "transcribe"
-    pub(crate) language: Option<Language>,         // default: None
-    pub(crate) temperature: f32,                   // default: 0.0
-    pub(crate) sample_len: Option<u32>,            // default: None
-    pub(crate) best_of: Option<u32>,               // default: None
-    pub(crate) beam_size: Option<u32>,             // default: None
-    pub(crate) patience: Option<f32>,              // default: None
-    pub(crate) length_penalty: Option<f32>,        // default: None
-    pub(crate) prompt: Option<Prompt>,             // default: None
-    pub(crate) prefix: Option<String>,             // default: None
-    pub(crate) suppress_tokens: Option<Vec<i32>>,  // default: Some("-1".to_string())
-    pub(crate) suppress_blank: bool,               // default: true
-    pub(crate) without_timestamps: bool,           // default: false
-    pub(crate) max_initial_timestamp: Option<f32>, // default: Some(1.0)
-    pub(crate) time_offset: Option<f64>,           // default: None
+    pub(crate) task: Task,                             // default: "transcribe"
+    pub(crate) language: Option<Language>,             // default: None
+    pub(crate) temperature: f32,                       // default: 0.0
+    pub(crate) sample_len: Option<u32>,                // default: None
+    pub(crate) best_of: Option<u32>,                   // default: None
+    pub(crate) beam_size: Option<u32>,                 // default: None
+    pub(crate) patience: Option<f32>,                  // default: None
+    pub(crate) length_penalty: Option<f32>,            // default: None
+    pub(crate) prompt: Option<Prompt>,                 // default: None
+    pub(crate) prefix: Option<String>,                 // default: None
+    pub(crate) suppress_tokens: Option<Vec<i32>>,      // default: Some("-1".to_string())
+    pub(crate) suppress_blank: bool,                   // default: true
+    pub(crate) without_timestamps: bool,               // default: false
+    pub(crate) max_initial_timestamp: Option<f32>,     // default: Some(1.0)
+    pub(crate) time_offset: Option<f64>,               // default: None
+    pub(crate) no_speech_threshold: Option<f32>,       // default: Some(0.6)
+    pub(crate) logprob_threshold: Option<f32>,         // default: Some(-1.0)
+    pub(crate) allowed_languages: Option<Vec<String>>, // default: None
+    pub(crate) vad: Option<VadOptions>,                // default: None
+    pub(crate) condition_on_previous_text: bool,       // default: true
+    pub(crate) prompt_reset_on_temperature: f32,       // default: 0.5
+    pub(crate) temperature_increment_on_fallback: Option<f32>, // default: Some(0.2)
+    pub(crate) compression_ratio_threshold: Option<f32>, // default: Some(2.4)
+    pub(crate) token_bias: Vec<(i32, f32)>,            // default: []
+    pub(crate) hotwords: Vec<(String, f32)>,           // default: []
+    pub(crate) repetition_penalty: Option<f32>,        // default: None
+    pub(crate) max_repetitions: Option<u32>,           // default: Some(4)
+    pub(crate) filter_repeated_segments: bool,         // default: true
+    pub(crate) clip_timestamps: Vec<f64>,              // default: []
+}
+
+impl DecodingOptions {
+    /// Whether a window is skipped as silence, as in OpenAI: its no speech probability is above
+    /// `no_speech_threshold`, and its text was not decoded confidently enough to keep it anyway.
+    pub(crate) fn is_silent(&self, no_speech_prob: f32, avg_logprob: f32) -> bool {
+        let no_speech = self
+            .no_speech_threshold
+            .is_some_and(|threshold| no_speech_prob > threshold);
+        let confident = self
+            .logprob_threshold
+            .is_some_and(|threshold| avg_logprob > threshold);
+        no_speech && !confident
+    }
+
+    /// Whether `decoded` is too repetitive or too unlikely to keep, as in OpenAI.
+    /// Silent windows are skipped rather than decoded again.
+    pub(crate) fn needs_fallback(&self, decoded: &DecodingResult) -> bool {
+        let repetitive = self
+            .compression_ratio_threshold
+            .is_some_and(|threshold| decoded.compression_ratio > threshold);
+        let unlikely = self
+            .logprob_threshold
+            .is_some_and(|threshold| decoded.avg_logprob < threshold);
+        (repetitive || unlikely) && !self.is_silent(decoded.no_speech_prob, decoded.avg_logprob)
+    }
+
+    /// The options to decode again with if the result [needs a fallback](Self::needs_fallback),
+    /// i.e at the next temperature, up to 1.
+    pub(crate) fn fallback(&self) -> Option<DecodingOptions> {
+        let increment = self.temperature_increment_on_fallback?;
+        let temperature = self.temperature + increment;
+        (temperature <= 1.0 + 1e-6).then(|| DecodingOptions {
+            temperature,
+            ..self.clone()
+        })
+    }
+
+    /// Whether a window may be decoded again, so its text is not final until decoding ends.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn may_fall_back(&self) -> bool {
+        let thresholds =
+            self.compression_ratio_threshold.is_some() || self.logprob_threshold.is_some();
+        thresholds && self.fallback().is_some()
+    }
+
+    /// Whether the text decoded at `temperature` should be dropped from the prompt
+    /// of subsequent windows.
+    pub(crate) fn resets_prompt(&self, temperature: f32) -> bool {
+        !self.condition_on_previous_text || temperature > self.prompt_reset_on_temperature
+    }
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
@@ -77,6 +162,20 @@ pub struct DecodingOptionsBuilder {
     without_timestamps: Option<bool>,
     max_initial_timestamp: Option<f32>,
     time_offset: Option<f64>,
+    no_speech_threshold: Option<f32>,
+    logprob_threshold: Option<f32>,
+    allowed_languages: Option<Vec<String>>,
+    vad: Option<VadOptions>,
+    condition_on_previous_text: Option<bool>,
+    prompt_reset_on_temperature: Option<f32>,
+    temperature_increment_on_fallback: Option<f32>,
+    compression_ratio_threshold: Option<f32>,
+    token_bias: Vec<(i32, f32)>,
+    hotwords: Vec<(String, f32)>,
+    repetition_penalty: Option<f32>,
+    max_repetitions: Option<u32>,
+    filter_repeated_segments: Option<bool>,
+    clip_timestamps: Vec<f64>,
 }
 
 impl Default for DecodingOptionsBuilder {
@@ -105,6 +204,20 @@ impl DecodingOptionsBuilder {
             max_initial_timestamp: Some(1.0),
             without_timestamps: Some(false),
             time_offset: None,
+            no_speech_threshold: Some(0.6),
+            logprob_threshold: Some(-1.0),
+            allowed_languages: None,
+            vad: None,
+            condition_on_previous_text: Some(true),
+            prompt_reset_on_temperature: Some(0.5),
+            temperature_increment_on_fallback: Some(0.2),
+            compression_ratio_threshold: Some(2.4),
+            token_bias: vec![],
+            hotwords: vec![],
+            repetition_penalty: None,
+            max_repetitions: Some(4),
+            filter_repeated_segments: Some(true),
+            clip_timestamps: vec![],
         }
     }
 
@@ -120,6 +233,7 @@ impl DecodingOptionsBuilder {
         self
     }
 
+    /// Temperature of the first attempt at each window, 0 decodes greedily.
     #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setTemperature"))]
     pub fn temperature(mut self, temperature: f32) -> Self {
         self.temperature = Some(temperature);
@@ -156,6 +270,8 @@ impl DecodingOptionsBuilder {
         self
     }
 
+    /// Text prompting every window, e.g to hint at vocabulary or style.
+    /// It precedes the previously decoded text & is never reset.
     #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setPrompt"))]
     pub fn prompt(mut self, prompt: String) -> Self {
         self.prompt = Some(prompt);
@@ -201,6 +317,133 @@ impl DecodingOptionsBuilder {
         self
     }
 
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setNoSpeechThreshold"))]
+    pub fn no_speech_threshold(mut self, no_speech_threshold: f32) -> Self {
+        self.no_speech_threshold = Some(no_speech_threshold);
+        self
+    }
+
+    /// Windows whose average token log-probability is above this are transcribed even when
+    /// their no speech probability is above the `no_speech_threshold`.
+    /// Below it, a window is decoded again at a higher temperature.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setLogprobThreshold"))]
+    pub fn logprob_threshold(mut self, logprob_threshold: f32) -> Self {
+        self.logprob_threshold = Some(logprob_threshold);
+        self
+    }
+
+    /// Restricts language detection to these language codes.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setAllowedLanguages"))]
+    pub fn allowed_languages(mut self, allowed_languages: Vec<String>) -> Self {
+        self.allowed_languages = Some(allowed_languages);
+        self
+    }
+
+    /// Only decode the speech regions found by a [crate::VoiceActivityDetector],
+    /// timestamps still refer to the original audio.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setVad"))]
+    pub fn vad(mut self, vad: bool) -> Self {
+        self.vad = vad.then(VadOptions::default);
+        self
+    }
+
+    /// Only decode these ranges of the audio, given as alternating start & end times in seconds,
+    /// e.g `[0., 30., 60., 90.]`. A missing final end runs to the end of the audio.
+    /// Timestamps still refer to the original audio.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setClipTimestamps"))]
+    pub fn clip_timestamps(mut self, clip_timestamps: Vec<f64>) -> Self {
+        self.clip_timestamps = clip_timestamps;
+        self
+    }
+
+    /// Prompt each window with the text decoded from the previous ones.
+    /// Disabling this makes the model less prone to repeating itself across windows,
+    /// at the cost of less consistent text between them.
+    #[cfg_attr(
+        target_arch = "wasm32",
+        wasm_bindgen(js_name = "setConditionOnPreviousText")
+    )]
+    pub fn condition_on_previous_text(mut self, condition_on_previous_text: bool) -> Self {
+        self.condition_on_previous_text = Some(condition_on_previous_text);
+        self
+    }
+
+    /// Text decoded at a temperature above this is dropped from the prompt of later windows.
+    /// The initial prompt is always kept.
+    #[cfg_attr(
+        target_arch = "wasm32",
+        wasm_bindgen(js_name = "setPromptResetOnTemperature")
+    )]
+    pub fn prompt_reset_on_temperature(mut self, prompt_reset_on_temperature: f32) -> Self {
+        self.prompt_reset_on_temperature = Some(prompt_reset_on_temperature);
+        self
+    }
+
+    /// Decodes a window again at a temperature this much higher, up to 1, while its text is
+    /// too repetitive or too unlikely, see `compression_ratio_threshold` & `logprob_threshold`.
+    /// Zero disables the fallback. Segments of windows that may be decoded again are only
+    /// streamed once the window is done.
+    #[cfg_attr(
+        target_arch = "wasm32",
+        wasm_bindgen(js_name = "setTemperatureIncrementOnFallback")
+    )]
+    pub fn temperature_increment_on_fallback(mut self, increment: f32) -> Self {
+        self.temperature_increment_on_fallback = (increment > 0.).then_some(increment);
+        self
+    }
+
+    /// Windows whose text compresses better than this with gzip are decoded again at a
+    /// higher temperature, as they are likely stuck repeating themselves.
+    #[cfg_attr(
+        target_arch = "wasm32",
+        wasm_bindgen(js_name = "setCompressionRatioThreshold")
+    )]
+    pub fn compression_ratio_threshold(mut self, compression_ratio_threshold: f32) -> Self {
+        self.compression_ratio_threshold = Some(compression_ratio_threshold);
+        self
+    }
+
+    /// Adds `bias` to the logit of `token` at every step, see [crate::LogitBias].
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "addTokenBias"))]
+    pub fn token_bias(mut self, token: i32, bias: f32) -> Self {
+        self.token_bias.push((token, bias));
+        self
+    }
+
+    /// Boosts `phrase`, e.g a product name, by adding `bias` to the logits of its tokens
+    /// as they become due, see [crate::LogitBias].
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "addHotword"))]
+    pub fn hotword(mut self, phrase: String, bias: f32) -> Self {
+        self.hotwords.push((phrase, bias));
+        self
+    }
+
+    /// Penalises text tokens already sampled in the window, see [crate::RepetitionPenalty].
+    /// Values above 1 discourage repetition.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setRepetitionPenalty"))]
+    pub fn repetition_penalty(mut self, repetition_penalty: f32) -> Self {
+        self.repetition_penalty = Some(repetition_penalty);
+        self
+    }
+
+    /// Stops decoding a window once an n-gram of its text is repeated more than this many
+    /// times in a row, dropping the repeats. Zero disables the check.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(js_name = "setMaxRepetitions"))]
+    pub fn max_repetitions(mut self, max_repetitions: u32) -> Self {
+        self.max_repetitions = (max_repetitions > 0).then_some(max_repetitions);
+        self
+    }
+
+    /// Drops segments whose text repeats a segment of the previous window.
+    #[cfg_attr(
+        target_arch = "wasm32",
+        wasm_bindgen(js_name = "setFilterRepeatedSegments")
+    )]
+    pub fn filter_repeated_segments(mut self, filter_repeated_segments: bool) -> Self {
+        self.filter_repeated_segments = Some(filter_repeated_segments);
+        self
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     pub fn build(&self) -> DecodingOptions {
         DecodingOptions {
@@ -219,6 +462,20 @@ impl DecodingOptionsBuilder {
             without_timestamps: self.without_timestamps.unwrap_or(false),
             max_initial_timestamp: self.max_initial_timestamp,
             time_offset: self.time_offset,
+            no_speech_threshold: self.no_speech_threshold,
+            logprob_threshold: self.logprob_threshold,
+            allowed_languages: self.allowed_languages.clone(),
+            vad: self.vad.clone(),
+            condition_on_previous_text: self.condition_on_previous_text.unwrap_or(true),
+            prompt_reset_on_temperature: self.prompt_reset_on_temperature.unwrap_or(0.5),
+            temperature_increment_on_fallback: self.temperature_increment_on_fallback,
+            compression_ratio_threshold: self.compression_ratio_threshold,
+            token_bias: self.token_bias.clone(),
+            hotwords: self.hotwords.clone(),
+            repetition_penalty: self.repetition_penalty,
+            max_repetitions: self.max_repetitions,
+            filter_repeated_segments: self.filter_repeated_segments.unwrap_or(true),
+            clip_timestamps: self.clip_timestamps.clone(),
         }
     }
 
@@ -240,14 +497,37 @@ impl DecodingOptionsBuilder {
             without_timestamps: self.without_timestamps.unwrap_or(false),
             max_initial_timestamp: self.max_initial_timestamp,
             time_offset: self.time_offset,
+            no_speech_threshold: self.no_speech_threshold,
+            logprob_threshold: self.logprob_threshold,
+            allowed_languages: self.allowed_languages.clone(),
+            vad: self.vad.clone(),
+            condition_on_previous_text: self.condition_on_previous_text.unwrap_or(true),
+            prompt_reset_on_temperature: self.prompt_reset_on_temperature.unwrap_or(0.5),
+            temperature_increment_on_fallback: self.temperature_increment_on_fallback,
+            compression_ratio_threshold: self.compression_ratio_threshold,
+            token_bias: self.token_bias.clone(),
+            hotwords: self.hotwords.clone(),
+            repetition_penalty: self.repetition_penalty,
+            max_repetitions: self.max_repetitions,
+            filter_repeated_segments: self.filter_repeated_segments.unwrap_or(true),
+            clip_timestamps: self.clip_timestamps.clone(),
         };
         serde_wasm_bindgen::to_value(&options).unwrap()
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl DecodingOptionsBuilder {
+    /// As [DecodingOptionsBuilder::vad], with tuned detection.
+    pub fn vad_options(mut self, options: VadOptions) -> Self {
+        self.vad = Some(options);
+        self
+    }
+}
+
 cfg_if::cfg_if! {
     if #[cfg(all(not(target_arch = "wasm32"), test))] {
-        use pyo3::types::{IntoPyDict, PyDict};
+        use pyo3::types::{IntoPyDict, PyDict, PyTuple};
         use pyo3::Python;
         use pyo3::types::PyString;
         use pyo3::IntoPy;
@@ -285,11 +565,12 @@ cfg_if::cfg_if! {
         impl IntoPyDict for DecodingOptions {
             fn into_py_dict(self, py: Python) -> &pyo3::types::PyDict {
                 let dict = PyDict::new(py);
+                let temperatures = std::iter::successors(Some(self.clone()), DecodingOptions::fallback).map(|o| o.temperature).collect::<Vec<_>>();
                 let supress_tokens_string = self.suppress_tokens.map(|v| v.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(","));
 
                 let _ = dict.set_item("task", self.task.into_py(py));
                 let _ = dict.set_item("language", self.language.map_or_else(|| py.None(), |v| v.into_py(py)));
-                let _ = dict.set_item("temperature", self.temperature.into_py(py));
+                let _ = dict.set_item("temperature", PyTuple::new(py, temperatures));
                 let _ = dict.set_item("sample_len", self.sample_len.map_or_else(|| py.None(), |v| v.into_py(py)));
                 let _ = dict.set_item("best_of", self.best_of.map_or_else(|| py.None(), |v| v.into_py(py)));
                 let _ = dict.set_item("beam_size", self.beam_size.map_or_else(|| py.None(), |v| v.into_py(py)));
@@ -301,6 +582,10 @@ cfg_if::cfg_if! {
                 let _ = dict.set_item("suppress_blank", self.suppress_blank.into_py(py));
                 let _ = dict.set_item("without_timestamps", self.without_timestamps.into_py(py));
                 let _ = dict.set_item("max_initial_timestamp", self.max_initial_timestamp.map_or_else(|| py.None(), |v| v.into_py(py)));
+                let _ = dict.set_item("no_speech_threshold", self.no_speech_threshold.map_or_else(|| py.None(), |v| v.into_py(py)));
+                let _ = dict.set_item("logprob_threshold", self.logprob_threshold.map_or_else(|| py.None(), |v| v.into_py(py)));
+                let _ = dict.set_item("compression_ratio_threshold", self.compression_ratio_threshold.map_or_else(|| py.None(), |v| v.into_py(py)));
+                let _ = dict.set_item("condition_on_previous_text", self.condition_on_previous_text.into_py(py));
 
                 dict
             }
@@ -22,12 +22,19 @@ pub struct ResidualAttentionBlockInputs {
     pub xa: Option<Tensor>,
     pub mask: Option<Tensor>,
     pub cache: Option<KVEntry>,
+    pub x_attn_cache: Option<KVEntry>,
 }
 
 impl Module for ResidualAttentionBlock {
     type Input = ResidualAttentionBlockInputs;
     fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
-        let ResidualAttentionBlockInputs { x, xa, mask, cache } = input;
+        let ResidualAttentionBlockInputs {
+            x,
+            xa,
+            mask,
+            cache,
+            x_attn_cache,
+        } = input;
         let attn_ln = self.attn_ln.forward(x)?;
         let self_attn = self.attn.forward(&MHAInputs::new(
             attn_ln,
@@ -42,8 +49,13 @@ impl Module for ResidualAttentionBlock {
         if let Some(ref xa_blck) = self.x_attn {
             if let Some(xa_ln) = &self.x_attn_ln {
                 let x_attn_ln = xa_ln.forward(&attn)?;
-                let x_attn =
-                    xa_blck.forward(&MHAInputs::new(x_attn_ln, xa.clone(), None, None, false))?;
+                let x_attn = xa_blck.forward(&MHAInputs::new(
+                    x_attn_ln,
+                    xa.clone(),
+                    None,
+                    x_attn_cache.clone(),
+                    false,
+                ))?;
                 attn = x_attn.add(&attn)?;
             }
         }
@@ -54,6 +66,15 @@ impl Module for ResidualAttentionBlock {
 }
 
 impl ResidualAttentionBlock {
+    /// Cross attention keys & values for `xa` & the writes filling them, see
+    /// [MultiHeadAttention::project_kv]. `None` if this block has no cross attention.
+    pub fn project_cross_attention(
+        &self,
+        xa: &Tensor,
+    ) -> anyhow::Result<Option<(KVEntry, [Tensor; 2])>> {
+        self.x_attn.as_ref().map(|a| a.project_kv(xa)).transpose()
+    }
+
     pub fn load<R: BufRead + Seek>(
         disk_model: &GGMLModel<Whisper>,
         reader: &mut R,
@@ -1,27 +1,122 @@
 use ndarray::Axis;
 use ndarray_stats::QuantileExt;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
 use ratchet::Tensor;
 
-use crate::{DecodeError, WhisperTokenizer};
+use crate::DecodeError;
 
+/// Samples one token at a time, greedily at a temperature of 0 as OpenAI's `GreedyDecoder`.
 pub struct GreedySampler;
 
 impl GreedySampler {
+    /// Appends a token sampled at `temperature` for each row of `logits`, see [Self::sample_at].
+    ///
+    /// Returns the log-probabilities of the sampled tokens, the extended tokens,
+    /// and whether the final token is `eot`.
     pub fn sample(
         mut tokens: Vec<i32>,
         logits: Tensor,
-    ) -> Result<(Tensor, Vec<i32>, bool), DecodeError> {
+        eot: i32,
+        temperature: f32,
+        rng: &mut impl Rng,
+    ) -> Result<(Vec<f32>, Vec<i32>, bool), DecodeError> {
+        let (next_tokens, logprobs): (Vec<_>, Vec<_>) = Self::sample_at(&logits, temperature, rng)
+            .into_iter()
+            .unzip();
+
+        tokens.extend_from_slice(&next_tokens);
+        let completed = tokens[tokens.len() - 1] == eot;
+        Ok((logprobs, tokens, completed))
+    }
+
+    /// The most likely token of each row of `logits` & its log-probability.
+    pub fn argmax(logits: &Tensor) -> Vec<(i32, f32)> {
         let nd_logits = logits.to_ndarray_view::<f32>();
-        let next_tokens = nd_logits
+        nd_logits
             .map_axis(Axis(1), |row| {
-                row.argmax_skipnan().expect("Sampling failed.")
+                let argmax = row.argmax_skipnan().expect("Sampling failed.");
+                (argmax as i32, Self::logprob(row, argmax))
             })
-            .iter()
-            .map(|&x| x as i32)
-            .collect::<Vec<_>>();
+            .into_iter()
+            .collect()
+    }
 
-        tokens.extend_from_slice(&next_tokens);
-        let completed = tokens[tokens.len() - 1] == WhisperTokenizer::EOT;
-        Ok((logits, tokens, completed))
+    /// A token drawn from the softmax of each row of `logits` divided by `temperature`,
+    /// or the most likely one at a temperature of 0.
+    ///
+    /// As in OpenAI, the log-probabilities are those of the unscaled logits.
+    pub fn sample_at(logits: &Tensor, temperature: f32, rng: &mut impl Rng) -> Vec<(i32, f32)> {
+        if temperature <= 0. {
+            return Self::argmax(logits);
+        }
+        let nd_logits = logits.to_ndarray_view::<f32>();
+        nd_logits
+            .map_axis(Axis(1), |row| {
+                let max = row.fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+                let weights = row.iter().map(|&x| ((x - max) / temperature).exp());
+                let index = WeightedIndex::new(weights)
+                    .expect("Sampling failed.")
+                    .sample(rng);
+                (index as i32, Self::logprob(row, index))
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Log-softmax of `row` evaluated at `index`.
+    fn logprob(row: ndarray::ArrayView1<f32>, index: usize) -> f32 {
+        let max = row.fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        let log_sum_exp = row.fold(0., |acc, &x| acc + (x - max).exp()).ln() + max;
+        row[index] - log_sum_exp
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use ratchet::shape;
+
+    #[test]
+    fn sample_returns_logprobs() {
+        let logits = Tensor::from_data(
+            vec![0f32, 1., f32::NEG_INFINITY, 3.],
+            shape![1, 4],
+            ratchet::Device::CPU,
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let (logprobs, tokens, completed) =
+            GreedySampler::sample(vec![7], logits, 3, 0., &mut rng).unwrap();
+        assert_eq!(tokens, vec![7, 3]);
+        assert!(completed);
+        let expected = 3. - (1f32 + 1f32.exp() + 3f32.exp()).ln();
+        assert!((logprobs[0] - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sampling_follows_the_temperature() {
+        let logits = Tensor::from_data(
+            vec![0f32, 1., f32::NEG_INFINITY, 1.5],
+            shape![1, 4],
+            ratchet::Device::CPU,
+        );
+        let draw = |temperature, seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..64)
+                .map(|_| GreedySampler::sample_at(&logits, temperature, &mut rng)[0])
+                .collect::<Vec<_>>()
+        };
+        assert!(draw(0., 0).iter().all(|(t, _)| *t == 3));
+
+        let sampled = draw(1., 0);
+        assert_eq!(sampled, draw(1., 0));
+        assert!(sampled.iter().all(|(t, _)| *t != 2));
+        assert!(sampled.iter().any(|(t, _)| *t != 3));
+        // log-probabilities ignore the temperature
+        let expected = 1.5 - (1f32 + 1f32.exp() + 1.5f32.exp()).ln();
+        let (_, logprob) = draw(0.5, 0).into_iter().find(|(t, _)| *t == 3).unwrap();
+        assert!((logprob - expected).abs() < 1e-6);
     }
 }
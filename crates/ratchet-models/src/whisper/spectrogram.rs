@@ -1,15 +1,14 @@
 //Adapted from: https://github.com/tanmayb123/OpenAI-Whisper-CoreML
-use ndarray::{Array1, Array2};
+use ndarray::{s, Array1, Array2};
 use ndarray_stats::QuantileExt;
 use num::complex::Complex;
-use ratchet::Tensor;
+use ratchet::{shape, Device, Tensor};
 use realfft::{RealFftPlanner, RealToComplex};
 use std::f32::consts::PI;
 use std::sync::Arc;
 
 pub static SAMPLE_RATE: usize = 16000;
 pub static N_FFT: usize = 400;
-pub static N_MELS: usize = 80;
 pub static HOP_LENGTH: usize = 160;
 pub static CHUNK_LENGTH: usize = 30;
 pub static N_AUDIO_CTX: usize = 1500; //same for all
@@ -17,6 +16,9 @@ pub static N_SAMPLES: usize = SAMPLE_RATE * CHUNK_LENGTH; // 480000
 pub static N_FRAMES: usize = N_SAMPLES / HOP_LENGTH; // 3000
 pub static FFT_PAD: usize = N_FFT / 2;
 
+/// Frames per matmul stack in the GPU path.
+const STACK_FRAMES: usize = 3000;
+
 #[derive(Debug, thiserror::Error)]
 pub enum AudioError {
     #[error("Audio must be 30 seconds long (with stft padding): {0} != {1}")]
@@ -25,36 +27,182 @@ pub enum AudioError {
     InvalidAudio(#[from] anyhow::Error),
 }
 
+/// Parameters of a [SpectrogramGenerator]. Defaults to those used by Whisper.
+#[derive(Debug, Clone)]
+pub struct SpectrogramConfig {
+    pub sample_rate: usize,
+    pub n_fft: usize,
+    pub hop_length: usize,
+    pub n_mels: usize,
+    /// Zeros appended to the audio before the STFT.
+    /// Whisper pads with `N_SAMPLES`, use 0 to only produce frames for the audio provided.
+    pub padding: usize,
+}
+
+impl Default for SpectrogramConfig {
+    fn default() -> Self {
+        Self::whisper(80)
+    }
+}
+
+impl SpectrogramConfig {
+    /// Most models use 80 mel bins, large-v3 uses 128.
+    pub fn whisper(n_mels: usize) -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            n_fft: N_FFT,
+            hop_length: HOP_LENGTH,
+            n_mels,
+            padding: N_SAMPLES,
+        }
+    }
+
+    pub fn n_freqs(&self) -> usize {
+        self.n_fft / 2 + 1
+    }
+}
+
+fn hz_to_mel(hz: f64) -> f64 {
+    const MIN_LOG_HZ: f64 = 1000.0;
+    let f_sp = 200.0 / 3.0;
+    if hz >= MIN_LOG_HZ {
+        let logstep = 6.4f64.ln() / 27.0;
+        MIN_LOG_HZ / f_sp + (hz / MIN_LOG_HZ).ln() / logstep
+    } else {
+        hz / f_sp
+    }
+}
+
+fn mel_to_hz(mel: f64) -> f64 {
+    const MIN_LOG_HZ: f64 = 1000.0;
+    let f_sp = 200.0 / 3.0;
+    let min_log_mel = MIN_LOG_HZ / f_sp;
+    if mel >= min_log_mel {
+        let logstep = 6.4f64.ln() / 27.0;
+        MIN_LOG_HZ * (logstep * (mel - min_log_mel)).exp()
+    } else {
+        mel * f_sp
+    }
+}
+
+/// Slaney-style mel filterbank, [n_mels, n_fft / 2 + 1], spanning 0Hz to Nyquist.
+///
+/// Matches `librosa.filters.mel(sr=sample_rate, n_fft=n_fft, n_mels=n_mels)`,
+/// which is how the filters shipped with Whisper were generated.
+pub fn mel_filters(sample_rate: usize, n_fft: usize, n_mels: usize) -> Array2<f32> {
+    let n_freqs = n_fft / 2 + 1;
+    let fft_freqs = (0..n_freqs)
+        .map(|i| i as f64 * sample_rate as f64 / n_fft as f64)
+        .collect::<Vec<_>>();
+
+    let (min_mel, max_mel) = (hz_to_mel(0.0), hz_to_mel(sample_rate as f64 / 2.0));
+    let mel_freqs = (0..n_mels + 2)
+        .map(|i| min_mel + (max_mel - min_mel) * i as f64 / (n_mels + 1) as f64)
+        .map(mel_to_hz)
+        .collect::<Vec<_>>();
+
+    Array2::from_shape_fn((n_mels, n_freqs), |(m, f)| {
+        let (left, center, right) = (mel_freqs[m], mel_freqs[m + 1], mel_freqs[m + 2]);
+        let lower = (fft_freqs[f] - left) / (center - left);
+        let upper = (right - fft_freqs[f]) / (right - center);
+        //slaney normalization, each filter has approximately constant energy
+        let enorm = 2.0 / (right - left);
+        (lower.min(upper).max(0.0) * enorm) as f32
+    })
+}
+
 pub struct SpectrogramGenerator {
+    config: SpectrogramConfig,
     fft_plan: Arc<dyn RealToComplex<f32>>,
     hann_window: Array1<f32>,
+    dft_basis: Array2<f32>,
     mels: Array2<f32>,
 }
 
 impl std::fmt::Debug for SpectrogramGenerator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("SpectrogramGenerator").finish()
+        f.debug_struct("SpectrogramGenerator")
+            .field("config", &self.config)
+            .finish()
     }
 }
 
 impl SpectrogramGenerator {
+    /// Whisper spectrogram generator using the filterbank shipped with the model.
+    ///
+    /// `mels` is a flattened [n_mels, N_FFT / 2 + 1] filterbank.
+    /// Most models use 80 mel bins, large-v3 uses 128.
     pub fn new(mels: Vec<f32>) -> Self {
+        let n_freqs = N_FFT / 2 + 1;
+        assert_eq!(
+            mels.len() % n_freqs,
+            0,
+            "Mel filterbank must have {} frequency bins",
+            n_freqs
+        );
+        let config = SpectrogramConfig::whisper(mels.len() / n_freqs);
+        let mels = Array2::from_shape_vec((config.n_mels, n_freqs), mels).unwrap();
+        Self::with_filters(config, mels)
+    }
+
+    /// Builds a Slaney-style filterbank from `config`, see [mel_filters].
+    pub fn from_config(config: SpectrogramConfig) -> Self {
+        let mels = mel_filters(config.sample_rate, config.n_fft, config.n_mels);
+        Self::with_filters(config, mels)
+    }
+
+    /// `mels` must be [config.n_mels, config.n_fft / 2 + 1].
+    pub fn with_filters(config: SpectrogramConfig, mels: Array2<f32>) -> Self {
+        assert_eq!(
+            mels.dim(),
+            (config.n_mels, config.n_freqs()),
+            "Mel filterbank does not match the config"
+        );
         let mut planner = RealFftPlanner::new();
+        let hann_window = Self::hann_window(config.n_fft);
         Self {
-            fft_plan: planner.plan_fft_forward(N_FFT),
-            hann_window: Self::hann_window(),
-            mels: Array2::from_shape_vec((N_MELS, N_FFT / 2 + 1), mels).unwrap(),
+            fft_plan: planner.plan_fft_forward(config.n_fft),
+            dft_basis: Self::dft_basis(&hann_window),
+            hann_window,
+            mels,
+            config,
         }
     }
 
-    fn hann_window() -> Array1<f32> {
-        let window = (0..N_FFT)
-            .map(|i| (i as f32 * 2.0 * PI) / N_FFT as f32)
+    pub fn config(&self) -> &SpectrogramConfig {
+        &self.config
+    }
+
+    pub fn n_mels(&self) -> usize {
+        self.mels.nrows()
+    }
+
+    fn hann_window(n_fft: usize) -> Array1<f32> {
+        let window = (0..n_fft)
+            .map(|i| (i as f32 * 2.0 * PI) / n_fft as f32)
             .map(|i| (1.0 - i.cos()) / 2.0)
             .collect::<Vec<_>>();
         Array1::from(window)
     }
 
+    /// Windowed real DFT basis, [n_fft, 2 * n_freqs].
+    /// The first n_freqs columns hold the cosine terms and the remainder the sine terms,
+    /// such that `frame @ basis` yields the real & imaginary parts of the spectrum.
+    fn dft_basis(hann_window: &Array1<f32>) -> Array2<f32> {
+        let n_fft = hann_window.len();
+        let n_freqs = n_fft / 2 + 1;
+        Array2::from_shape_fn((n_fft, 2 * n_freqs), |(n, k)| {
+            let f = k % n_freqs;
+            //reduce before converting to keep the phase accurate
+            let phase = 2.0 * PI * ((f * n) % n_fft) as f32 / n_fft as f32;
+            if k < n_freqs {
+                hann_window[n] * phase.cos()
+            } else {
+                -hann_window[n] * phase.sin()
+            }
+        })
+    }
+
     fn fft(&self, audio: &[f32]) -> Vec<Complex<f32>> {
         let mut input = Array1::from_vec(audio.to_vec());
         input *= &self.hann_window;
@@ -65,57 +213,183 @@ impl SpectrogramGenerator {
         spectrum
     }
 
+    /// As in Whisper, the final STFT frame is dropped.
+    fn n_frames(&self, padded_len: usize) -> usize {
+        (padded_len - self.config.n_fft) / self.config.hop_length
+    }
+
     fn mel_spectrogram(&self, audio: &[f32]) -> Tensor {
-        let n_frames = (audio.len() - N_FFT) / HOP_LENGTH;
-        let right_padding = N_SAMPLES + FFT_PAD; //padding is all 0s, so we can ignore it
+        let mut mel_spec = self.log_mel(audio);
+        let max = *mel_spec.max().unwrap();
+        mel_spec.mapv_inplace(|x| (x.max(max - 8.0) + 4.0) / 4.0);
+        let expanded = mel_spec.insert_axis(ndarray::Axis(0));
+        Tensor::from(expanded.into_dyn())
+    }
 
-        let mut spectrogram = Array2::<f32>::zeros((201, n_frames));
-        for i in (0..audio.len() - right_padding).step_by(HOP_LENGTH) {
-            if i / HOP_LENGTH >= n_frames {
-                break;
+    /// log10 mel power of already padded audio, [n_mels, n_frames], without Whisper's
+    /// dynamic range clamping & rescaling.
+    pub(crate) fn log_mel(&self, audio: &[f32]) -> Array2<f32> {
+        let SpectrogramConfig {
+            n_fft, hop_length, ..
+        } = self.config;
+        let n_frames = self.n_frames(audio.len());
+
+        let mut spectrogram = Array2::<f32>::zeros((self.config.n_freqs(), n_frames));
+        for frame in 0..n_frames {
+            let samples = &audio[frame * hop_length..frame * hop_length + n_fft];
+            if samples.iter().all(|&s| s == 0.0) {
+                continue; //padding is all 0s, so we can skip it
             }
-            let fft = self.fft(&audio[i..i + N_FFT]);
+            let fft = self.fft(samples);
             let spectrogram_col = fft.iter().map(|c| c.norm_sqr()).collect::<Array1<f32>>();
-            spectrogram
-                .column_mut(i / HOP_LENGTH)
-                .assign(&spectrogram_col);
+            spectrogram.column_mut(frame).assign(&spectrogram_col);
         }
 
         let mut mel_spec = self.mels.dot(&spectrogram);
         mel_spec.mapv_inplace(|x| x.max(1e-10).log10());
-        let max = *mel_spec.max().unwrap();
-        mel_spec.mapv_inplace(|x| (x.max(max - 8.0) + 4.0) / 4.0);
-        let expanded = mel_spec.insert_axis(ndarray::Axis(0));
-        Tensor::from(expanded.into_dyn())
+        mel_spec
     }
 
-    pub fn generate(&self, audio: Vec<f32>) -> Result<Tensor, AudioError> {
-        if audio.is_empty() {
-            return Err(AudioError::InvalidAudio(anyhow::anyhow!(
-                "Audio must be non-empty"
-            )));
+    /// Mel spectrogram built from ratchet operations, such that it runs on the GPU.
+    ///
+    /// The STFT is expressed as a matmul against `dft_basis`. A frame spans
+    /// `ceil(n_fft / hop_length)` consecutive rows of the audio viewed as [_, hop_length],
+    /// so each row block contributes a partial product against the matching rows of the basis.
+    /// Frames are processed in stacks of `STACK_FRAMES` to keep the dispatches within limits.
+    fn mel_spectrogram_gpu(&self, audio: &[f32], device: &Device) -> anyhow::Result<Tensor> {
+        let SpectrogramConfig {
+            n_fft, hop_length, ..
+        } = self.config;
+        let n_freqs = self.config.n_freqs();
+        let n_mels = self.n_mels();
+        let n_frames = self.n_frames(audio.len());
+        let n_blocks = n_fft.div_ceil(hop_length);
+
+        let n_stacks = n_frames.div_ceil(STACK_FRAMES);
+        let n_rows = STACK_FRAMES + n_blocks - 1;
+        let mut rows = vec![0.0; n_stacks * n_rows * hop_length];
+        for (stack, dst) in rows.chunks_mut(n_rows * hop_length).enumerate() {
+            let start = (stack * STACK_FRAMES * hop_length).min(audio.len());
+            let end = (start + dst.len()).min(audio.len());
+            dst[..end - start].copy_from_slice(&audio[start..end]);
+        }
+        let rows = Tensor::from_data(rows, shape![n_stacks, n_rows, hop_length], device.clone());
+
+        let mut spectrum: Option<Tensor> = None;
+        for block in 0..n_blocks {
+            let (start, end) = (block * hop_length, ((block + 1) * hop_length).min(n_fft));
+            let basis = self.dft_basis.slice(s![start..end, ..]);
+            let basis = Tensor::from_data(
+                basis.iter().copied().collect::<Vec<_>>(),
+                shape![end - start, 2 * n_freqs],
+                device.clone(),
+            );
+            let frames = rows.slice(&[0..n_stacks, block..block + STACK_FRAMES, 0..end - start])?;
+            let partial = frames.matmul(&basis, false)?;
+            spectrum = Some(match spectrum {
+                Some(s) => s.add(&partial)?,
+                None => partial,
+            });
+        }
+        let spectrum = spectrum.unwrap();
+        let real = spectrum.slice(&[0..n_stacks, 0..STACK_FRAMES, 0..n_freqs])?;
+        let imag = spectrum.slice(&[0..n_stacks, 0..STACK_FRAMES, n_freqs..2 * n_freqs])?;
+        let power = real.mul(&real)?.add(&imag.mul(&imag)?)?;
+
+        let filters = Tensor::from_data(
+            self.mels.iter().copied().collect::<Vec<_>>(),
+            shape![n_mels, n_freqs],
+            device.clone(),
+        );
+        let mel = power
+            .matmul(&filters, true)?
+            .view(shape![n_stacks * STACK_FRAMES, n_mels])?
+            .slice(&[0..n_frames, 0..n_mels])?;
+
+        let scalar = |v: f32| Tensor::from_data([v], shape![1, 1], device.clone());
+        //max(x, c) = c + relu(x - c)
+        let clamp_min = |x: &Tensor, c: &Tensor| x.sub(c)?.relu()?.add(c);
+
+        let log_spec = clamp_min(&mel, &scalar(1e-10))?
+            .log()?
+            .mul(&scalar(std::f32::consts::LOG10_E))?;
+        let max = Self::global_max(&log_spec, &scalar(0.5))?;
+        let floor = max.sub(&scalar(8.0))?;
+        let four = scalar(4.0);
+        let normalized = clamp_min(&log_spec, &floor)?.add(&four)?.div(&four)?;
+        Ok(normalized
+            .permute(&[1, 0])?
+            .view(shape![1, n_mels, n_frames])?
+            .resolve()?)
+    }
+
+    /// Maximum of a contiguous tensor, as a [1, 1] tensor.
+    ///
+    /// There is no reduction operation, so the tensor is repeatedly folded in half
+    /// using max(a, b) = (a + b + |a - b|) / 2.
+    fn global_max(x: &Tensor, half: &Tensor) -> anyhow::Result<Tensor> {
+        let mut len = x.shape().numel();
+        let mut max = x.view(shape![1, len])?;
+        while len > 1 {
+            let mid = len.div_ceil(2);
+            let a = max.slice(&[0..1, 0..mid])?;
+            let b = max.slice(&[0..1, len - mid..len])?;
+            max = a.add(&b)?.add(&a.sub(&b)?.abs()?)?.mul(half)?;
+            len = mid;
         }
-        let padded = Self::pad_audio(audio, N_SAMPLES);
+        Ok(max)
+    }
+
+    /// Generates the [1, n_mels, n_frames] log-mel spectrogram of `audio`,
+    /// padded as per the config.
+    pub fn generate(&self, audio: Vec<f32>) -> Result<Tensor, AudioError> {
+        let padded = self.pad_audio(audio)?;
         Ok(self.mel_spectrogram(&padded))
     }
 
+    /// Generates the log-mel spectrogram on `device`.
+    ///
+    /// On the GPU the result is computed & resolved on the device, avoiding the CPU FFT
+    /// and the upload of the spectrogram. On the CPU this is equivalent to `generate`.
+    pub fn generate_on(&self, audio: Vec<f32>, device: &Device) -> Result<Tensor, AudioError> {
+        if device.is_cpu() {
+            return self.generate(audio);
+        }
+        let padded = self.pad_audio(audio)?;
+        Ok(self.mel_spectrogram_gpu(&padded, device)?)
+    }
+
     //The padding done by OAI is as follows:
     //1. First explicitly pad with (CHUNK_LENGTH * SAMPLE_RATE) (480,000) zeros
-    //2. Then perform a reflection padding of FFT_PAD (200) samples on each side
+    //2. Then perform a reflection padding of n_fft / 2 (200) samples on each side
     //   This must be done with care, because we have already performed the explicit padding
-    //   the pre-padding will contain non-zero values, but the post-padding must be zero
-    pub fn pad_audio(audio: Vec<f32>, padding: usize) -> Vec<f32> {
-        let padded_len = FFT_PAD + audio.len() + padding + FFT_PAD;
-        let mut padded_samples = vec![0.0; padded_len];
-
-        let mut reflect_padding = vec![0.0; FFT_PAD];
-        for i in 0..FFT_PAD {
-            reflect_padding[i] = audio[FFT_PAD - i];
+    //   the pre-padding will contain non-zero values, but the post-padding will be zero
+    //   unless the explicit padding is shorter than the reflection
+    pub fn pad_audio(&self, mut audio: Vec<f32>) -> Result<Vec<f32>, AudioError> {
+        if audio.is_empty() {
+            return Err(AudioError::InvalidAudio(anyhow::anyhow!(
+                "Audio must be non-empty"
+            )));
+        }
+        let fft_pad = self.config.n_fft / 2;
+        audio.resize(audio.len() + self.config.padding, 0.0);
+        if audio.len() <= fft_pad {
+            return Err(AudioError::InvalidAudio(anyhow::anyhow!(
+                "Audio must be longer than {} samples",
+                fft_pad
+            )));
         }
 
-        padded_samples[0..FFT_PAD].copy_from_slice(&reflect_padding);
-        padded_samples[FFT_PAD..(FFT_PAD + audio.len())].copy_from_slice(&audio);
-        padded_samples
+        let mut padded_samples = Vec::with_capacity(audio.len() + 2 * fft_pad);
+        padded_samples.extend((1..=fft_pad).rev().map(|i| audio[i]));
+        padded_samples.extend_from_slice(&audio);
+        padded_samples.extend((1..=fft_pad).map(|i| audio[audio.len() - 1 - i]));
+        if self.n_frames(padded_samples.len()) == 0 {
+            return Err(AudioError::InvalidAudio(anyhow::anyhow!(
+                "Audio is too short to produce a frame"
+            )));
+        }
+        Ok(padded_samples)
     }
 }
 
@@ -124,7 +398,10 @@ mod tests {
     use std::path::PathBuf;
 
     use hf_hub::api::sync::Api;
-    use ratchet::test_util::run_py_prg;
+    use ndarray::Array1;
+    use ratchet::{test_util::run_py_prg, Device, DeviceRequest};
+
+    use crate::{SpectrogramConfig, SpectrogramGenerator, N_FFT};
 
     const MAX_DIFF: f32 = 1e-5;
 
@@ -133,14 +410,6 @@ mod tests {
         npyz::NpyFile::new(&bytes[..]).unwrap().into_vec().unwrap()
     }
 
-    fn load_sample(path: PathBuf) -> Vec<f32> {
-        let mut reader = hound::WavReader::open(path).unwrap();
-        reader
-            .samples::<i16>()
-            .map(|x| x.unwrap() as f32 / 32768.0)
-            .collect::<Vec<_>>()
-    }
-
     #[test]
     fn spectrogram_matches() {
         let api = Api::new().unwrap();
@@ -159,7 +428,99 @@ def ground_truth():
         );
         let ground_truth = run_py_prg(prg.to_string(), &[], &[]).unwrap();
         let generator = crate::SpectrogramGenerator::new(load_npy(mels));
-        let result = generator.generate(load_sample(gb0)).unwrap();
+        let result = generator.generate(crate::load_audio(gb0).unwrap()).unwrap();
         ground_truth.all_close(&result, MAX_DIFF, MAX_DIFF).unwrap();
     }
+
+    #[test]
+    fn gpu_spectrogram_matches() {
+        let api = Api::new().unwrap();
+        let repo = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
+        let gb0 = repo.get("erwin_jp.wav").unwrap();
+        let mels = repo.get("mel_filters.npy").unwrap();
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+
+        let generator = crate::SpectrogramGenerator::new(load_npy(mels));
+        let audio = crate::load_audio(gb0).unwrap();
+        let expected = generator.generate(audio.clone()).unwrap();
+        let result = generator
+            .generate_on(audio, &device)
+            .unwrap()
+            .to(&Device::CPU)
+            .unwrap();
+        expected.all_close(&result, MAX_DIFF, MAX_DIFF).unwrap();
+    }
+
+    #[test]
+    fn dft_basis_matches_fft() {
+        let generator = crate::SpectrogramGenerator::new(vec![0.0; N_FFT / 2 + 1]);
+        let frame = (0..N_FFT)
+            .map(|i| (i as f32 * 0.37).sin() + (i as f32 * 0.011).cos() * 0.5)
+            .collect::<Vec<_>>();
+        let expected = generator.fft(&frame);
+
+        let spectrum = Array1::from(frame).dot(&generator.dft_basis);
+        let n_freqs = N_FFT / 2 + 1;
+        for (k, c) in expected.iter().enumerate() {
+            let (re, im) = (spectrum[k], spectrum[n_freqs + k]);
+            assert!((re - c.re).abs() < 1e-3, "re[{}]: {} != {}", k, re, c.re);
+            assert!((im - c.im).abs() < 1e-3, "im[{}]: {} != {}", k, im, c.im);
+        }
+    }
+
+    #[test]
+    fn slaney_filters_match_shipped() {
+        let api = Api::new().unwrap();
+        let repo = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
+        let mels = load_npy(repo.get("mel_filters.npy").unwrap());
+
+        let filters = crate::mel_filters(16000, N_FFT, 80);
+        for (i, (a, b)) in filters.iter().zip(mels.iter()).enumerate() {
+            assert!((a - b).abs() < 1e-6, "filter[{}]: {} != {}", i, a, b);
+        }
+    }
+
+    #[test]
+    fn slaney_filters_are_triangular() {
+        let filters = crate::mel_filters(22050, 1024, 64);
+        assert_eq!(filters.dim(), (64, 513));
+        let mut last_peak = 0;
+        for row in filters.rows() {
+            assert!(row.iter().all(|&w| w >= 0.0));
+            let peak = row
+                .iter()
+                .enumerate()
+                .fold(
+                    (0, 0.0),
+                    |acc, (i, &w)| if w > acc.1 { (i, w) } else { acc },
+                )
+                .0;
+            assert!(peak >= last_peak, "filter peaks must be ascending");
+            last_peak = peak;
+            //weights rise to the peak, then fall
+            let (rising, falling) = row.as_slice().unwrap().split_at(peak);
+            assert!(rising.windows(2).all(|w| w[0] <= w[1]));
+            assert!(falling.windows(2).all(|w| w[0] >= w[1]));
+        }
+    }
+
+    #[test]
+    fn arbitrary_length_without_padding() {
+        let config = SpectrogramConfig {
+            sample_rate: 8000,
+            n_fft: 256,
+            hop_length: 64,
+            n_mels: 40,
+            padding: 0,
+        };
+        let generator = SpectrogramGenerator::from_config(config);
+        let audio = (0..1000)
+            .map(|i| (i as f32 * 0.2).sin())
+            .collect::<Vec<_>>();
+        let mel = generator.generate(audio).unwrap();
+        //1000 samples + 2 * 128 reflection, less the dropped frame
+        assert_eq!(mel.shape().to_vec(), vec![1, 40, (1256 - 256) / 64]);
+
+        assert!(generator.generate(vec![0.0; 100]).is_err());
+    }
 }
@@ -0,0 +1,433 @@
+use crate::{
+    DecodingOptions, DecodingResult, DecodingTask, Language, PromptHistory, Segment,
+    StreamedSegment, Whisper, WhisperTokenizer, HOP_LENGTH, N_AUDIO_CTX, N_FRAMES, N_SAMPLES,
+    SAMPLE_RATE,
+};
+use ratchet_nn::Module;
+
+/// Controls the latency / stability tradeoff of a [StreamingTranscriber].
+#[derive(Debug, Clone)]
+pub struct StreamingOptions {
+    /// Seconds of new audio required before the buffer is decoded again.
+    /// Tentative text lags the input by roughly this plus the decode time.
+    pub step: f64,
+    /// Upper bound on the seconds of uncommitted audio. Once exceeded, everything but the
+    /// trailing segment is committed without waiting for agreement.
+    pub max_buffer: f64,
+    /// Seconds of audio retained when a window is classified as silent,
+    /// so speech starting right at the end of it is not lost.
+    pub silence_keep: f64,
+}
+
+impl Default for StreamingOptions {
+    fn default() -> Self {
+        Self {
+            step: 1.0,
+            max_buffer: 25.0,
+            silence_keep: 1.0,
+        }
+    }
+}
+
+/// Incrementally transcribes audio as it arrives.
+///
+/// Every `step` seconds the uncommitted audio is re-decoded in a single window.
+/// A segment becomes stable once two consecutive decodes agree on its text, at which point
+/// it is committed and its audio dropped from the buffer. Everything after the last stable
+/// segment is re-decoded next time around, so words split across a boundary are recovered.
+///
+/// Each call returns the newly stable segments followed by the current tentative ones,
+/// tentative segments replace any previously returned tentative segments.
+pub struct StreamingTranscriber {
+    decode_options: DecodingOptions,
+    options: StreamingOptions,
+    tokenizer: Option<WhisperTokenizer>,
+    buffer: Vec<f32>,
+    buffer_offset: f64,
+    pending: usize,
+    hypothesis: Vec<Segment>,
+    committed: Vec<Segment>,
+    history: PromptHistory,
+    /// Temperature the latest hypothesis was decoded at.
+    temperature: f32,
+    pass_idx: u64,
+}
+
+impl StreamingTranscriber {
+    pub fn new(decode_options: DecodingOptions, options: StreamingOptions) -> Self {
+        Self {
+            decode_options,
+            options,
+            tokenizer: None,
+            buffer: Vec::with_capacity(N_SAMPLES),
+            buffer_offset: 0.0,
+            pending: 0,
+            hypothesis: vec![],
+            committed: vec![],
+            history: PromptHistory::default(),
+            temperature: 0.0,
+            pass_idx: 0,
+        }
+    }
+
+    /// Segments committed so far.
+    pub fn segments(&self) -> &[Segment] {
+        &self.committed
+    }
+
+    fn seconds(samples: usize) -> f64 {
+        samples as f64 / SAMPLE_RATE as f64
+    }
+
+    fn buffer_end(&self) -> f64 {
+        self.buffer_offset + Self::seconds(self.buffer.len())
+    }
+
+    /// Appends PCM, returning whether enough new audio has arrived to decode again.
+    fn append(&mut self, pcm: &[f32]) -> bool {
+        self.buffer.extend_from_slice(pcm);
+        self.pending += pcm.len();
+        Self::seconds(self.pending) >= self.options.step
+    }
+
+    fn window_options(&self) -> DecodingOptions {
+        let mut options = self.decode_options.clone();
+        options.time_offset = Some(self.buffer_offset);
+        if !self.committed.is_empty() {
+            // the prefix only constrains how the transcript starts
+            options.prefix = None;
+        }
+        options.prompt = self.history.prompt();
+        options
+    }
+
+    fn resolve_language(&mut self, model: &Whisper) {
+        if self.decode_options.language.is_none() && !model.is_multilingual() {
+            log::warn!("No language specified, using English");
+            self.decode_options.language = Some(Language::String("en".to_string()));
+        }
+    }
+
+    /// Splits decoded tokens into segments, keeping any trailing unterminated text as a
+    /// final segment running to the end of the buffer.
+    fn segment(&self, decoded: &DecodingResult, window_frames: usize) -> Vec<Segment> {
+        let tokens = &decoded.tokens;
+        let offset = self.buffer_offset;
+        let buffer_end = self.buffer_end();
+        let input_stride = N_FRAMES / N_AUDIO_CTX;
+        let duration = (window_frames * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+        let tokenizer = self.tokenizer.as_ref().unwrap();
+        let specials = tokenizer.specials();
+        if !tokens.iter().any(|t| *t < specials.eot) {
+            return vec![];
+        }
+
+        let (mut segments, _) = DecodingTask::build_segments(
+            tokens.clone(),
+            offset,
+            window_frames,
+            duration,
+            input_stride,
+            specials,
+        );
+        let consumed = segments.iter().map(|s| s.tokens.len()).sum::<usize>();
+        let trailing = &tokens[consumed.min(tokens.len())..];
+        if trailing.iter().any(|t| *t < specials.eot) {
+            let start = segments.last().map_or(offset, |s| s.stop);
+            let trailing = trailing.iter().map(|t| *t as u32).collect();
+            segments.push(Segment::new(start, buffer_end, trailing, false));
+        }
+        decoded.annotate(&mut segments, tokenizer);
+        segments.retain(|s| s.tokens.iter().any(|t| *t < specials.eot as u32));
+        for s in segments.iter_mut() {
+            s.stop = s.stop.min(buffer_end);
+            s.start = s.start.min(s.stop);
+        }
+        segments
+    }
+
+    fn text_tokens(segment: &Segment, eot: i32) -> Vec<u32> {
+        segment
+            .tokens
+            .iter()
+            .copied()
+            .filter(|t| *t < eot as u32)
+            .collect()
+    }
+
+    /// Commits agreed upon segments and returns the segments to emit.
+    fn update(&mut self, hypothesis: Option<Vec<Segment>>, flush: bool) -> Vec<StreamedSegment> {
+        let Some(hypothesis) = hypothesis else {
+            // silent window, keep only the tail in case speech is starting
+            let keep = (self.options.silence_keep * SAMPLE_RATE as f64) as usize;
+            let drop = self.buffer.len().saturating_sub(keep);
+            self.buffer.drain(..drop);
+            self.buffer_offset += Self::seconds(drop);
+            self.hypothesis.clear();
+            return vec![];
+        };
+
+        let eot = self.tokenizer.as_ref().unwrap().specials().eot;
+        let agreed = if flush {
+            hypothesis.len()
+        } else {
+            let agreed = hypothesis
+                .iter()
+                .zip(self.hypothesis.iter())
+                .take_while(|(a, b)| Self::text_tokens(a, eot) == Self::text_tokens(b, eot))
+                .count();
+            // the trailing segment may still be cut off mid word
+            let mut agreed = agreed.min(hypothesis.len().saturating_sub(1));
+            if Self::seconds(self.buffer.len()) > self.options.max_buffer {
+                agreed = hypothesis.len().saturating_sub(1).max(agreed);
+                if agreed == 0 {
+                    agreed = hypothesis.len();
+                }
+            }
+            agreed
+        };
+
+        let mut hypothesis = hypothesis.into_iter();
+        let newly_committed = hypothesis.by_ref().take(agreed).collect::<Vec<_>>();
+        self.hypothesis = hypothesis.collect();
+
+        let tokenizer = self.tokenizer.as_ref().unwrap();
+        let mut emitted = newly_committed
+            .iter()
+            .map(|s| StreamedSegment::from_segment(tokenizer, s, false))
+            .collect::<Vec<_>>();
+        emitted.extend(self.hypothesis.iter().map(|s| {
+            let mut tentative = StreamedSegment::from_segment(tokenizer, s, false);
+            tentative.stable = false;
+            tentative
+        }));
+
+        let committed_until = newly_committed.last().map(|s| s.stop);
+        for segment in newly_committed {
+            self.history
+                .extend(segment.tokens.iter().map(|t| *t as i32));
+            self.committed.push(segment);
+        }
+        if self.decode_options.resets_prompt(self.temperature) {
+            self.history.reset();
+        }
+
+        let forced = !flush && Self::seconds(self.buffer.len()) > self.options.max_buffer;
+        let drop_until = if flush || (forced && self.hypothesis.is_empty()) {
+            Some(self.buffer_end())
+        } else {
+            committed_until
+        };
+        if let Some(until) = drop_until {
+            let drop = (((until - self.buffer_offset) * SAMPLE_RATE as f64).round() as usize)
+                .min(self.buffer.len());
+            self.buffer.drain(..drop);
+            self.buffer_offset += Self::seconds(drop);
+        }
+        // the remaining tentative segments lead the next window, so they're compared by position
+        emitted
+    }
+
+    fn finish_segments(&mut self, mut emitted: Vec<StreamedSegment>) -> Vec<StreamedSegment> {
+        match emitted.last_mut() {
+            Some(last) => last.last = true,
+            None => {
+                let end = self.buffer_end();
+                emitted.push(StreamedSegment::new(end, end, String::new(), true));
+            }
+        }
+        emitted
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StreamingTranscriber {
+    /// Feeds a chunk of mono PCM at `SAMPLE_RATE`, decoding if a full `step` has accumulated.
+    pub fn push(
+        &mut self,
+        model: &mut Whisper,
+        pcm: &[f32],
+    ) -> anyhow::Result<Vec<StreamedSegment>> {
+        if !self.append(pcm) {
+            return Ok(vec![]);
+        }
+        let hypothesis = self.decode(model)?;
+        Ok(self.update(hypothesis, false))
+    }
+
+    /// Decodes any remaining audio, committing everything. The final segment has `last` set.
+    pub fn finish(&mut self, model: &mut Whisper) -> anyhow::Result<Vec<StreamedSegment>> {
+        let emitted = if self.buffer.is_empty() {
+            vec![]
+        } else {
+            let hypothesis = self.decode(model)?;
+            self.update(hypothesis, true)
+        };
+        Ok(self.finish_segments(emitted))
+    }
+
+    fn decode(&mut self, model: &mut Whisper) -> anyhow::Result<Option<Vec<Segment>>> {
+        self.pending = 0;
+        self.resolve_language(model);
+        let window_frames = (self.buffer.len() / HOP_LENGTH).min(N_FRAMES);
+        let window = self.buffer[..self.buffer.len().min(N_SAMPLES)].to_vec();
+
+        model.device.try_gpu()?.begin_pass(self.pass_idx);
+        self.pass_idx += 1;
+        let mel = model.specgen.generate_on(window, &model.device)?;
+        let mel = mel.slice(&[0..1, 0..model.n_mels(), 0..N_FRAMES])?;
+
+        if self.decode_options.language.is_none() {
+            log::warn!("No language specified, using language detection");
+            let allowed = self.decode_options.allowed_languages.clone();
+            let detection = model.detect_language_probs(mel.clone(), allowed.as_deref())?;
+            self.decode_options.language = Some(Language::Token(detection.best().token));
+        }
+        if self.tokenizer.is_none() {
+            let language = self.decode_options.language.clone().unwrap();
+            let task = self.decode_options.task;
+            self.tokenizer = Some(WhisperTokenizer::load(
+                model.tokenizer.clone(),
+                model.n_vocab(),
+                language,
+                task,
+            )?);
+            let tokenizer = self.tokenizer.as_ref().unwrap();
+            self.history = PromptHistory::new(self.decode_options.prompt.as_ref(), tokenizer)?;
+        }
+        let tokenizer = self.tokenizer.as_ref().unwrap();
+
+        let hs = model.encoder.forward(&mel)?.resolve()?;
+        let (task, decoded) = DecodingTask::run_with_fallback(
+            self.window_options(),
+            &mut model.decoder,
+            hs,
+            tokenizer,
+            None,
+        )?;
+        self.temperature = decoded.temperature;
+        if task.is_silent(&decoded) {
+            return Ok(None);
+        }
+        Ok(Some(self.segment(&decoded, window_frames)))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl StreamingTranscriber {
+    /// Feeds a chunk of mono PCM at `SAMPLE_RATE`, decoding if a full `step` has accumulated.
+    pub async fn push(
+        &mut self,
+        model: &mut Whisper,
+        pcm: &[f32],
+    ) -> anyhow::Result<Vec<StreamedSegment>> {
+        if !self.append(pcm) {
+            return Ok(vec![]);
+        }
+        let hypothesis = self.decode(model).await?;
+        Ok(self.update(hypothesis, false))
+    }
+
+    /// Decodes any remaining audio, committing everything. The final segment has `last` set.
+    pub async fn finish(&mut self, model: &mut Whisper) -> anyhow::Result<Vec<StreamedSegment>> {
+        let emitted = if self.buffer.is_empty() {
+            vec![]
+        } else {
+            let hypothesis = self.decode(model).await?;
+            self.update(hypothesis, true)
+        };
+        Ok(self.finish_segments(emitted))
+    }
+
+    async fn decode(&mut self, model: &mut Whisper) -> anyhow::Result<Option<Vec<Segment>>> {
+        self.pending = 0;
+        self.resolve_language(model);
+        let window_frames = (self.buffer.len() / HOP_LENGTH).min(N_FRAMES);
+        let window = self.buffer[..self.buffer.len().min(N_SAMPLES)].to_vec();
+
+        model.device.try_gpu()?.begin_pass(self.pass_idx);
+        self.pass_idx += 1;
+        let mel = model.specgen.generate_on(window, &model.device)?;
+        let mel = mel.slice(&[0..1, 0..model.n_mels(), 0..N_FRAMES])?;
+
+        if self.decode_options.language.is_none() {
+            log::warn!("No language specified, using language detection");
+            let allowed = self.decode_options.allowed_languages.clone();
+            let detection = model
+                .detect_language_probs(mel.clone(), allowed.as_deref())
+                .await?;
+            self.decode_options.language = Some(Language::Token(detection.best().token));
+        }
+        if self.tokenizer.is_none() {
+            let language = self.decode_options.language.clone().unwrap();
+            let task = self.decode_options.task;
+            self.tokenizer = Some(
+                WhisperTokenizer::load(model.tokenizer.clone(), model.n_vocab(), language, task)
+                    .await?,
+            );
+            let tokenizer = self.tokenizer.as_ref().unwrap();
+            self.history = PromptHistory::new(self.decode_options.prompt.as_ref(), tokenizer)?;
+        }
+        let tokenizer = self.tokenizer.as_ref().unwrap();
+
+        let hs = model.encoder.forward(&mel)?.resolve()?;
+        let (task, decoded) = DecodingTask::run_with_fallback(
+            self.window_options(),
+            &mut model.decoder,
+            hs,
+            tokenizer,
+            &None::<fn(StreamedSegment)>,
+        )
+        .await?;
+        self.temperature = decoded.temperature;
+        if task.is_silent(&decoded) {
+            return Ok(None);
+        }
+        Ok(Some(self.segment(&decoded, window_frames)))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use crate::{
+        load_audio, DecodingOptionsBuilder, StreamingOptions, StreamingTranscriber, Whisper,
+    };
+    use hf_hub::api::sync::Api;
+    use ratchet::{Device, DeviceRequest};
+    use ratchet_loader::GGMLCompatible;
+
+    #[test]
+    pub fn streaming_end_to_end() {
+        let api = Api::new().unwrap();
+        let model = api.model("FL33TW00D-HF/ratchet-whisper".to_string());
+        let model_path = model.get("tiny_q8.bin").unwrap();
+
+        let dataset = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
+        let audio_path = dataset.get("mm0.wav").unwrap();
+        let samples = load_audio(audio_path).unwrap();
+
+        let mut reader = std::io::BufReader::new(std::fs::File::open(model_path).unwrap());
+        let gg_disk = Whisper::load_ggml(&mut reader).unwrap();
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let mut whisper = Whisper::load(&gg_disk, &mut reader, device).unwrap();
+
+        let options = DecodingOptionsBuilder::new().build();
+        let mut streamer = StreamingTranscriber::new(options, StreamingOptions::default());
+        let mut stable = vec![];
+        // 100ms chunks, as a microphone would deliver them
+        for chunk in samples.chunks(1600) {
+            let emitted = streamer.push(&mut whisper, chunk).unwrap();
+            stable.extend(emitted.into_iter().filter(|s| s.stable));
+        }
+        stable.extend(streamer.finish(&mut whisper).unwrap());
+
+        assert!(stable.last().unwrap().last);
+        assert!(stable.windows(2).all(|w| w[0].start <= w[1].start));
+        let text = stable.iter().map(|s| s.text.as_str()).collect::<String>();
+        assert!(!text.trim().is_empty());
+        assert_eq!(
+            streamer.segments().len(),
+            stable.iter().filter(|s| !s.text.is_empty()).count()
+        );
+    }
+}
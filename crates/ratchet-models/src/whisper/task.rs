@@ -1,16 +1,23 @@
 use ndarray::s;
 use ndarray::Axis;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use ratchet::prelude::shape;
 use ratchet::Device;
+use ratchet::NDArrayExt;
 use ratchet::Tensor;
 use ratchet_nn::Module;
 
+use crate::compression_ratio;
 use crate::ApplyTimestampRules;
+use crate::CancellationToken;
 use crate::DecodingOptions;
 use crate::GreedySampler;
+use crate::LogitBias;
 use crate::LogitMutator;
-use crate::Prompt;
+use crate::RepetitionPenalty;
 use crate::Segment;
+use crate::SpecialTokens;
 use crate::StreamedSegment;
 use crate::WhisperDecoder;
 use crate::WhisperTokenizer;
@@ -19,6 +26,29 @@ use crate::HOP_LENGTH;
 use crate::N_AUDIO_CTX;
 use crate::SAMPLE_RATE;
 
+/// Length of the decoder's text context, `n_text_ctx` of every Whisper model.
+pub(crate) const N_TEXT_CTX: usize = 448;
+
+/// Longest prompt kept ahead of the SOT sequence, equivalent to `self.n_ctx // 2 - 1` in python.
+pub(crate) const MAX_PROMPT_LENGTH: usize = N_TEXT_CTX / 2 - 1;
+
+/// If the text tokens (those before `eot`) of `sampled` end with an n-gram repeated more than
+/// `max_repetitions` times in a row, the index in `sampled` at which the repeats begin.
+fn repetition_start(sampled: &[i32], eot: i32, max_repetitions: usize) -> Option<usize> {
+    const MAX_NGRAM: usize = 16;
+    let copies = max_repetitions + 1;
+    let text = (0..sampled.len())
+        .filter(|&i| sampled[i] < eot)
+        .collect::<Vec<_>>();
+    (1..=MAX_NGRAM)
+        .take_while(|n| n * copies <= text.len())
+        .find_map(|n| {
+            let tail = &text[text.len() - n * copies..];
+            let ngram = |c: usize| tail[c * n..(c + 1) * n].iter().map(|&i| sampled[i]);
+            (1..copies).all(|c| ngram(c).eq(ngram(0))).then(|| tail[n])
+        })
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum DecodeError {
     #[error("No valid logits found")]
@@ -29,6 +59,40 @@ pub enum DecodeError {
     UnknownError(#[from] anyhow::Error),
     #[error("Failed to resolve tensor: {0}")]
     TensorResolveError(#[from] ratchet::TensorError),
+    #[error("Decoding was cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodingResult {
+    pub tokens: Vec<i32>,
+    /// Log-probability of each token in `tokens`.
+    pub logprobs: Vec<f32>,
+    /// Mean log-probability of `tokens` plus `EOT`, i.e sum / (tokens.len() + 1), as in OpenAI.
+    pub avg_logprob: f32,
+    /// gzip compression ratio of the decoded text, high values indicate repetition.
+    pub compression_ratio: f32,
+    pub no_speech_prob: f32,
+    /// Temperature the tokens were sampled at.
+    pub temperature: f32,
+    /// Tokens dropped as the text started looping, see [crate::DecodingOptionsBuilder::max_repetitions].
+    /// Empty unless decoding was stopped early.
+    pub dropped_repetition: Vec<i32>,
+}
+
+impl DecodingResult {
+    /// Copies the decoding statistics onto `segments`, which must have been built from
+    /// a prefix of `self.tokens`, in order.
+    pub(crate) fn annotate(&self, segments: &mut [Segment], tokenizer: &WhisperTokenizer) {
+        let mut offset = 0;
+        for segment in segments.iter_mut() {
+            let end = (offset + segment.tokens.len()).min(self.logprobs.len());
+            segment.token_logprobs = self.logprobs[offset.min(end)..end].to_vec();
+            offset += segment.tokens.len();
+            segment.annotate(tokenizer);
+            segment.no_speech_prob = self.no_speech_prob;
+        }
+    }
 }
 
 pub struct DecodingTask {
@@ -37,30 +101,46 @@ pub struct DecodingTask {
     logit_mutators: Vec<Box<dyn LogitMutator>>,
     initial_tokens: Option<Vec<i32>>,
     initial_tokens_len: Option<usize>,
+    sot_index: usize,
+    specials: SpecialTokens,
+    cancel: Option<CancellationToken>,
 }
 
 impl DecodingTask {
-    fn get_initial_tokens(&self, tokenizer: &WhisperTokenizer) -> Vec<i32> {
-        let mut init_tokens = tokenizer.sot_sequence();
-        if let Some(prompt) = &self.options.prompt {
-            let prompt_tokens = match prompt {
-                Prompt::Tokens(tokens) => tokens.clone(),
-                Prompt::Text(text) => tokenizer
-                    .encode(format!(" {}", text).as_str(), false)
-                    .unwrap(),
+    fn get_initial_tokens(&self, tokenizer: &WhisperTokenizer) -> Result<Vec<i32>, DecodeError> {
+        let mut init_tokens = if self.options.without_timestamps {
+            tokenizer.sot_sequence_including_notimestamps()
+        } else {
+            tokenizer.sot_sequence()
+        };
+        if let Some(prefix) = &self.options.prefix {
+            let prefix_tokens = tokenizer.encode(format!(" {}", prefix.trim()).as_str(), false)?;
+            // As in OpenAI, the prefix keeps `n_ctx // 2 - sample_len` tokens, or all of them when
+            // sampling takes the whole half, within the half of the context left by the prompt.
+            let room = N_TEXT_CTX / 2 - init_tokens.len() - 1;
+            let max_prefix_length = match (N_TEXT_CTX / 2).saturating_sub(self.sample_len as _) {
+                0 => room,
+                n => n.min(room),
             };
-            let max_prompt_length = 448 / 2 - 1; // equivalent to self.n_ctx // 2 - 1 in python
-            let prompt_length = prompt_tokens.len().min(max_prompt_length);
-            let mut tokens = vec![WhisperTokenizer::START_OF_PREV];
+            let prefix_length = prefix_tokens.len().min(max_prefix_length);
+            init_tokens.extend_from_slice(&prefix_tokens[prefix_tokens.len() - prefix_length..]);
+        }
+        if let Some(prompt) = &self.options.prompt {
+            let prompt_tokens = prompt.tokens(tokenizer)?;
+            let prompt_length = prompt_tokens.len().min(MAX_PROMPT_LENGTH);
+            let mut tokens = vec![tokenizer.specials().start_of_prev];
             tokens.extend_from_slice(&prompt_tokens[prompt_tokens.len() - prompt_length..]);
             tokens.extend(init_tokens);
             init_tokens = tokens;
         }
-        init_tokens
+        Ok(init_tokens)
     }
 
-    pub fn new(options: DecodingOptions, tokenizer: &WhisperTokenizer) -> Self {
-        let sample_len = options.sample_len.unwrap_or(256);
+    pub fn new(
+        options: DecodingOptions,
+        tokenizer: &WhisperTokenizer,
+    ) -> Result<Self, DecodeError> {
+        let sample_len = options.sample_len.unwrap_or(N_TEXT_CTX as u32 / 2);
         let _selected_lang = options.language.as_ref().unwrap();
         let max_initial_timestamp = options.max_initial_timestamp;
         let mut task = DecodingTask {
@@ -69,9 +149,22 @@ impl DecodingTask {
             sample_len,
             initial_tokens: None,
             initial_tokens_len: None,
+            sot_index: 0,
+            specials: tokenizer.specials(),
+            cancel: None,
         };
-        task.initial_tokens = Some(task.get_initial_tokens(tokenizer));
+        task.initial_tokens = Some(task.get_initial_tokens(tokenizer)?);
         task.initial_tokens_len = Some(task.initial_tokens.as_ref().unwrap().len());
+        // sampling stops once the text context is full
+        let n_remaining = N_TEXT_CTX - task.initial_tokens_len.unwrap();
+        task.sample_len = task.sample_len.min(n_remaining as u32);
+        task.sot_index = task
+            .initial_tokens
+            .as_ref()
+            .unwrap()
+            .iter()
+            .position(|&t| t == task.specials.sot)
+            .unwrap();
 
         let mut max_initial_timestamp_index = None;
         if let Some(max_initial_timestamp) = max_initial_timestamp {
@@ -79,49 +172,150 @@ impl DecodingTask {
             max_initial_timestamp_index =
                 Some((max_initial_timestamp / precision).round() as usize);
         }
-        task.logit_mutators.push(Box::new(ApplyTimestampRules {
-            sample_begin: task.initial_tokens_len.unwrap(),
-            max_initial_timestamp_index,
-        }));
+        let logit_bias = task.options.token_bias.iter().fold(
+            LogitBias::new(task.initial_tokens_len.unwrap()),
+            |bias, (t, b)| bias.token(*t, *b),
+        );
+        let logit_bias = task
+            .options
+            .hotwords
+            .iter()
+            .try_fold(logit_bias, |bias, (p, b)| bias.phrase(tokenizer, p, *b))?;
+        if !logit_bias.is_empty() {
+            task.logit_mutators.push(Box::new(logit_bias));
+        }
+        if let Some(penalty) = task.options.repetition_penalty {
+            task.logit_mutators.push(Box::new(RepetitionPenalty::new(
+                penalty,
+                task.initial_tokens_len.unwrap(),
+                task.specials,
+            )));
+        }
+        if !task.options.without_timestamps {
+            task.logit_mutators.push(Box::new(ApplyTimestampRules {
+                sample_begin: task.initial_tokens_len.unwrap(),
+                max_initial_timestamp_index,
+                specials: task.specials,
+            }));
+        }
 
-        task
+        Ok(task)
     }
 
     #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn initial_tokens(&self) -> &[i32] {
+        self.initial_tokens.as_deref().unwrap()
+    }
+
+    /// Checks `cancel` before every decoder step, returning `DecodeError::Cancelled` once set.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Index in `sampled` at which the text started looping, see [repetition_start].
+    fn looping_from(&self, sampled: &[i32]) -> Option<usize> {
+        let max_repetitions = self.options.max_repetitions? as usize;
+        repetition_start(sampled, self.specials.eot, max_repetitions)
+    }
+
+    fn check_cancelled(&self) -> Result<(), DecodeError> {
+        match &self.cancel {
+            Some(cancel) if cancel.is_cancelled() => Err(DecodeError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decodes every window of `audio_ctx` in lockstep, one token per sequence per step.
+    ///
+    /// Sequences that have produced `EOT`, started looping, or whose window is silent,
+    /// are completed and padded with `EOT` until the whole batch is done.
+    /// The repeats of looping sequences are split off & returned separately.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[allow(clippy::type_complexity)]
     fn main_loop(
         &self,
         decoder: &mut WhisperDecoder,
         audio_ctx: Tensor,
-        mut tokens: Vec<i32>,
-    ) -> Result<Vec<i32>, DecodeError> {
+        initial_tokens: Vec<i32>,
+    ) -> Result<Vec<(Vec<i32>, Vec<f32>, f32, Vec<i32>)>, DecodeError> {
         let device = audio_ctx.device().clone();
+        let batch_size = audio_ctx.shape()[0];
+        let sample_begin = initial_tokens.len();
+        decoder.set_batch_size(batch_size);
+
+        let mut tokens = vec![initial_tokens; batch_size];
+        let mut logprobs = vec![vec![]; batch_size];
+        let mut no_speech_probs = vec![f32::NAN; batch_size];
+        let mut completed = vec![false; batch_size];
+        let mut repetition_starts = vec![None; batch_size];
+        let mut rng = StdRng::from_entropy();
 
         for idx in 0..self.sample_len {
+            self.check_cancelled()?;
             device.try_gpu().unwrap().begin_pass(idx as _);
-            let input = if tokens.len() > self.initial_tokens_len.unwrap() {
-                &tokens[tokens.len() - 1..]
-            } else {
-                &tokens
-            };
-            let input_t = Tensor::from_data(input, shape![1, input.len()], device.clone());
+            let input_len = if idx == 0 { tokens[0].len() } else { 1 };
+            let input = tokens
+                .iter()
+                .flat_map(|t| t[t.len() - input_len..].iter().copied())
+                .collect::<Vec<_>>();
+            let input_t = Tensor::from_data(input, shape![batch_size, input_len], device.clone());
 
-            let logits = decoder.forward(&[audio_ctx.clone(), input_t])?.resolve()?;
-            decoder.cache_mut().update(input.len());
+            let logits = self.decoder_step(decoder, idx == 0, [audio_ctx.clone(), input_t])?;
+            decoder.cache_mut().update(input_len);
 
-            let mut logits = Self::slice_logits(logits.to(&Device::CPU)?);
-            let token_t = Tensor::from_data(tokens.clone(), shape![1, tokens.len()], Device::CPU);
+            let logits = logits.to(&Device::CPU)?;
+            if idx == 0 {
+                for (b, prob) in no_speech_probs.iter_mut().enumerate() {
+                    *prob = self.no_speech_prob(&logits, b);
+                }
+            }
+
+            let mut logits = Self::slice_logits(logits, self.specials.n_vocab);
+            let flat_tokens = tokens.iter().flatten().copied().collect::<Vec<_>>();
+            let token_t = Tensor::from_data(
+                flat_tokens,
+                shape![batch_size, tokens[0].len()],
+                Device::CPU,
+            );
             for m in &self.logit_mutators {
                 logits = m.apply(logits, Some(&token_t))?;
             }
 
-            let (_, new_tokens, completed) = GreedySampler::sample(tokens, logits)?;
-
-            tokens = new_tokens;
-            if completed {
+            let sampled = GreedySampler::sample_at(&logits, self.options.temperature, &mut rng);
+            for (b, (token, logprob)) in sampled.into_iter().enumerate() {
+                if completed[b] {
+                    tokens[b].push(self.specials.eot);
+                    continue;
+                }
+                tokens[b].push(token);
+                logprobs[b].push(logprob);
+                completed[b] = token == self.specials.eot;
+                if let Some(start) = self.looping_from(&tokens[b][sample_begin..]) {
+                    repetition_starts[b] = Some(sample_begin + start);
+                    completed[b] = true;
+                }
+            }
+            if completed.iter().all(|&c| c) {
                 break;
             }
         }
-        Ok(tokens)
+        Ok(tokens
+            .into_iter()
+            .zip(logprobs)
+            .zip(no_speech_probs)
+            .zip(repetition_starts)
+            .map(|(((mut t, mut l), p), start)| {
+                let dropped = match start {
+                    Some(start) => {
+                        l.truncate(start - sample_begin);
+                        t.split_off(start)
+                    }
+                    None => vec![],
+                };
+                (t, l, p, dropped)
+            })
+            .collect())
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -132,11 +326,18 @@ impl DecodingTask {
         mut tokens: Vec<i32>,
         tokenizer: &WhisperTokenizer,
         callback: &Option<impl Fn(StreamedSegment)>,
-    ) -> Result<Vec<i32>, DecodeError> {
+    ) -> Result<(Vec<i32>, Vec<f32>, f32, Vec<i32>), DecodeError> {
         let device = audio_ctx.device().clone();
+        let sample_begin = self.initial_tokens_len.unwrap();
+        let mut dropped = vec![];
+        decoder.set_batch_size(1);
         let mut timestamps_seen = 0;
+        let mut no_speech_prob = f32::NAN;
+        let mut logprobs = vec![];
+        let mut rng = StdRng::from_entropy();
 
         for idx in 0..self.sample_len {
+            self.check_cancelled()?;
             device.try_gpu().unwrap().begin_pass(idx as _);
             let input = if tokens.len() > self.initial_tokens_len.unwrap() {
                 &tokens[tokens.len() - 1..]
@@ -145,46 +346,75 @@ impl DecodingTask {
             };
             let input_t = Tensor::from_data(input, shape![1, input.len()], device.clone());
 
-            let logits = decoder.forward(&[audio_ctx.clone(), input_t])?.resolve()?;
+            let logits = self.decoder_step(decoder, idx == 0, [audio_ctx.clone(), input_t])?;
             decoder.cache_mut().update(input.len());
 
-            let mut logits = Self::slice_logits(logits.to(&Device::CPU).await?);
+            let logits = logits.to(&Device::CPU).await?;
+            if idx == 0 {
+                no_speech_prob = self.no_speech_prob(&logits, 0);
+            }
+
+            let mut logits = Self::slice_logits(logits, self.specials.n_vocab);
             let token_t = Tensor::from_data(tokens.clone(), shape![1, tokens.len()], Device::CPU);
             for m in &self.logit_mutators {
                 logits = m.apply(logits, Some(&token_t))?;
             }
 
-            let (_, new_tokens, completed) = GreedySampler::sample(tokens, logits)?;
-
-            if let Some(ref cb) = callback {
-                self.handle_callback(tokenizer, &new_tokens, &mut timestamps_seen, cb);
+            let (new_logprobs, new_tokens, completed) = GreedySampler::sample(
+                tokens,
+                logits,
+                self.specials.eot,
+                self.options.temperature,
+                &mut rng,
+            )?;
+            logprobs.extend(new_logprobs);
+
+            match callback {
+                Some(cb) if self.streams_live(no_speech_prob) => self.handle_callback(
+                    tokenizer,
+                    &new_tokens,
+                    &logprobs,
+                    &mut timestamps_seen,
+                    cb,
+                ),
+                _ => {}
             }
 
             tokens = new_tokens;
+            if let Some(start) = self.looping_from(&tokens[sample_begin..]) {
+                logprobs.truncate(start);
+                dropped = tokens.split_off(sample_begin + start);
+                break;
+            }
             if completed {
                 break;
             }
         }
-        Ok(tokens)
+        Ok((tokens, logprobs, no_speech_prob, dropped))
     }
 
     fn handle_callback(
         &self,
         tokenizer: &WhisperTokenizer,
         new_tokens: &[i32],
+        logprobs: &[f32],
         timestamps_seen: &mut i32,
         callback: impl Fn(StreamedSegment),
     ) {
-        if WhisperTokenizer::is_timestamp(new_tokens[new_tokens.len() - 1]) {
+        if tokenizer.is_timestamp(new_tokens[new_tokens.len() - 1]) {
             *timestamps_seen += 1;
             if *timestamps_seen % 2 == 0 {
                 let previous_timestamp = new_tokens[..new_tokens.len() - 2]
                     .iter()
-                    .rposition(|x| WhisperTokenizer::is_timestamp(*x));
+                    .rposition(|x| tokenizer.is_timestamp(*x));
                 if let Some(previous_timestamp) = previous_timestamp {
+                    let sampled_from = previous_timestamp
+                        .saturating_sub(self.initial_tokens_len.unwrap())
+                        .min(logprobs.len());
                     callback(StreamedSegment::from_tokens(
                         tokenizer,
                         &new_tokens[previous_timestamp..],
+                        &logprobs[sampled_from..],
                         self.options.time_offset.unwrap_or(0.0),
                         false,
                     ));
@@ -193,11 +423,64 @@ impl DecodingTask {
         }
     }
 
-    /// Slice logits from [1xnum_tokensx51872] -> [1x1x51865]
-    pub(crate) fn slice_logits(logits: Tensor) -> Tensor {
+    /// Runs a decoder step. The `first` step also yields the logits of the SOT position,
+    /// needed for [Self::no_speech_prob], but not those of the prompt before it.
+    fn decoder_step(
+        &self,
+        decoder: &WhisperDecoder,
+        first: bool,
+        input: [Tensor; 2],
+    ) -> Result<Tensor, DecodeError> {
+        let logits = match first {
+            true => decoder.forward_from(&input, self.sot_index)?,
+            false => decoder.forward(&input)?,
+        };
+        Ok(logits.resolve()?)
+    }
+
+    /// Probability of `NO_CAPTIONS` at the SOT position of sequence `batch_index`, computed from
+    /// the raw logits of the first decoder step, which start at the SOT position.
+    /// A high value means the model believes the window contains no speech.
+    fn no_speech_prob(&self, logits: &Tensor, batch_index: usize) -> f32 {
+        let nd_logits = logits.to_ndarray_view::<f32>();
+        let sot_logits = nd_logits.slice(s![batch_index, 0, ..self.specials.n_vocab]);
+        let probs = sot_logits.softmax(0);
+        probs[self.specials.no_captions as usize]
+    }
+
+    /// Whether `decoded` should be skipped as silence, see [DecodingOptions::is_silent].
+    pub(crate) fn is_silent(&self, decoded: &DecodingResult) -> bool {
+        self.options
+            .is_silent(decoded.no_speech_prob, decoded.avg_logprob)
+    }
+
+    /// Segments are streamed as they are decoded, unless the window may yet be skipped as
+    /// silence or decoded again once its text is scored.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn streams_live(&self, no_speech_prob: f32) -> bool {
+        !self.options.without_timestamps
+            && !self.options.may_fall_back()
+            && !self.options.is_silent(no_speech_prob, f32::MIN)
+    }
+
+    /// The options to decode the window of `decoded` again with, if it needs a fallback.
+    pub(crate) fn fallback(&self, decoded: &DecodingResult) -> Option<DecodingOptions> {
+        self.options
+            .fallback()
+            .filter(|_| self.options.needs_fallback(decoded))
+    }
+
+    /// Whether the text of `decoded` should be left out of the prompt of later windows.
+    pub(crate) fn resets_prompt(&self, decoded: &DecodingResult) -> bool {
+        self.options.resets_prompt(decoded.temperature)
+    }
+
+    /// Logits of the final position of each sequence, [batch_size, num_tokens, n_vocab_padded]
+    /// -> [batch_size, n_vocab], dropping the padding of the model's vocabulary.
+    pub(crate) fn slice_logits(logits: Tensor, n_vocab: usize) -> Tensor {
         let nd_logits = logits.into_ndarray::<f32>();
         let sliced = nd_logits
-            .slice(s![.., -1.., ..WhisperTokenizer::SIZE])
+            .slice(s![.., -1.., ..n_vocab])
             .remove_axis(Axis(1));
         Tensor::from(sliced.to_owned().into_dyn())
     }
@@ -206,8 +489,9 @@ impl DecodingTask {
         tokens: Vec<i32>,
         offset: f64,
         segment_size: usize,
-        segment_duration: usize,
+        segment_duration: f64,
         input_stride: usize,
+        specials: SpecialTokens,
     ) -> (Vec<Segment>, usize) {
         let content_tokens = tokens;
         let content_length = content_tokens.len();
@@ -221,13 +505,13 @@ impl DecodingTask {
         );
 
         let single_timestamp_ending =
-            !WhisperTokenizer::is_timestamp(penultimate) && WhisperTokenizer::is_timestamp(last);
+            !specials.is_timestamp(penultimate) && specials.is_timestamp(last);
 
         let mut consecutive = content_tokens
             .windows(2)
             .enumerate()
             .filter_map(|(i, x)| {
-                if WhisperTokenizer::is_timestamp(x[0]) && WhisperTokenizer::is_timestamp(x[1]) {
+                if specials.is_timestamp(x[0]) && specials.is_timestamp(x[1]) {
                     Some(i + 1)
                 } else {
                     None
@@ -249,24 +533,28 @@ impl DecodingTask {
                     .iter()
                     .fold((Vec::new(), 0), |(mut acc, last_slice), &slice| {
                         let segment_tokens = &content_tokens[last_slice..slice];
-                        acc.push(Segment::from_tokens(segment_tokens, offset, false));
+                        acc.push(Segment::from_tokens(
+                            segment_tokens,
+                            offset,
+                            false,
+                            specials.ts_begin,
+                        ));
                         (acc, slice)
                     });
 
             advance = if single_timestamp_ending {
                 segment_size
             } else {
-                let last_timestamp_pos =
-                    content_tokens[last_slice - 1] - WhisperTokenizer::TS_BEGIN;
+                let last_timestamp_pos = content_tokens[last_slice - 1] - specials.ts_begin;
                 last_timestamp_pos as usize * input_stride
             }
         } else {
             let duration = content_tokens
                 .iter()
-                .filter(|&x| WhisperTokenizer::is_timestamp(*x))
+                .filter(|&x| specials.is_timestamp(*x))
                 .last()
-                .map_or(segment_duration as f64, |&last_ts| {
-                    let last_timestamp_pos = last_ts - WhisperTokenizer::TS_BEGIN;
+                .map_or(segment_duration, |&last_ts| {
+                    let last_timestamp_pos = last_ts - specials.ts_begin;
                     last_timestamp_pos as f64 * input_stride as f64 * (HOP_LENGTH as f64)
                         / (SAMPLE_RATE as f64)
                 });
@@ -284,6 +572,77 @@ impl DecodingTask {
         (segments, advance)
     }
 
+    /// Decodes the window in `audio_ctx` with `options`, then again at each higher temperature
+    /// while the result [needs a fallback](DecodingOptions::needs_fallback), as OpenAI's
+    /// `decode_with_fallback`.
+    ///
+    /// Returns the task of the final attempt & its result, the decoder is reset after each.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn run_with_fallback(
+        mut options: DecodingOptions,
+        decoder: &mut WhisperDecoder,
+        audio_ctx: Tensor,
+        tokenizer: &WhisperTokenizer,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<(Self, DecodingResult), DecodeError> {
+        loop {
+            let mut task = DecodingTask::new(options, tokenizer)?;
+            if let Some(cancel) = cancel {
+                task = task.with_cancellation(cancel.clone());
+            }
+            let decoded = task.run(decoder, audio_ctx.clone(), tokenizer);
+            decoder.reset();
+            let decoded = decoded?;
+            match task.fallback(&decoded) {
+                Some(next) => {
+                    log::info!(
+                        "Decoding again at temperature {:.1}: compression ratio {:.2}, average log-probability {:.2}",
+                        next.temperature,
+                        decoded.compression_ratio,
+                        decoded.avg_logprob
+                    );
+                    options = next;
+                }
+                _ => return Ok((task, decoded)),
+            }
+        }
+    }
+
+    /// Decodes the window in `audio_ctx` with `options`, then again at each higher temperature
+    /// while the result [needs a fallback](DecodingOptions::needs_fallback), as OpenAI's
+    /// `decode_with_fallback`.
+    ///
+    /// Returns the task of the final attempt & its result, the decoder is reset after each.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) async fn run_with_fallback(
+        mut options: DecodingOptions,
+        decoder: &mut WhisperDecoder,
+        audio_ctx: Tensor,
+        tokenizer: &WhisperTokenizer,
+        callback: &Option<impl Fn(StreamedSegment)>,
+    ) -> Result<(Self, DecodingResult), DecodeError> {
+        loop {
+            let task = DecodingTask::new(options, tokenizer)?;
+            let decoded = task
+                .run(decoder, audio_ctx.clone(), tokenizer, callback)
+                .await;
+            decoder.reset();
+            let decoded = decoded?;
+            match task.fallback(&decoded) {
+                Some(next) => {
+                    log::info!(
+                        "Decoding again at temperature {:.1}: compression ratio {:.2}, average log-probability {:.2}",
+                        next.temperature,
+                        decoded.compression_ratio,
+                        decoded.avg_logprob
+                    );
+                    options = next;
+                }
+                _ => return Ok((task, decoded)),
+            }
+        }
+    }
+
     #[cfg(target_arch = "wasm32")]
     pub async fn run(
         &self,
@@ -291,39 +650,160 @@ impl DecodingTask {
         audio_ctx: Tensor,
         tokenizer: &WhisperTokenizer,
         callback: &Option<impl Fn(StreamedSegment)>,
-    ) -> Result<Vec<i32>, DecodeError> {
-        let mut tokens = self
+    ) -> Result<DecodingResult, DecodeError> {
+        let (tokens, logprobs, no_speech_prob, dropped) = self
             .main_loop(
                 decoder,
                 audio_ctx,
-                self.get_initial_tokens(tokenizer),
+                self.initial_tokens.clone().unwrap(),
                 tokenizer,
                 &callback,
             )
             .await?;
 
-        tokens = tokens.drain(self.initial_tokens_len.unwrap()..).collect();
-        let eot_index = tokens.iter().position(|x| *x == WhisperTokenizer::EOT);
-        if let Some(eot_index) = eot_index {
-            tokens.truncate(eot_index);
-        }
-        Ok(tokens)
+        Ok(self.finalize(tokens, logprobs, no_speech_prob, dropped, tokenizer))
     }
 
+    /// Decodes the single window in `audio_ctx`, see [Self::run_batch].
     #[cfg(not(target_arch = "wasm32"))]
     pub fn run(
         &self,
         decoder: &mut WhisperDecoder,
         audio_ctx: Tensor,
         tokenizer: &WhisperTokenizer,
-    ) -> Result<Vec<i32>, DecodeError> {
-        let mut tokens = self.main_loop(decoder, audio_ctx, self.get_initial_tokens(tokenizer))?;
+    ) -> Result<DecodingResult, DecodeError> {
+        let mut results = self.run_batch(decoder, audio_ctx, tokenizer)?;
+        Ok(results.swap_remove(0))
+    }
 
+    /// Decodes a batch of windows, `audio_ctx` is [batch_size, n_audio_ctx, n_audio_state].
+    ///
+    /// All windows share this task's options & initial tokens,
+    /// the decoder cache is reallocated if the batch size changes.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_batch(
+        &self,
+        decoder: &mut WhisperDecoder,
+        audio_ctx: Tensor,
+        tokenizer: &WhisperTokenizer,
+    ) -> Result<Vec<DecodingResult>, DecodeError> {
+        let sequences = self.main_loop(decoder, audio_ctx, self.initial_tokens().to_vec())?;
+        Ok(sequences
+            .into_iter()
+            .map(|(tokens, logprobs, no_speech_prob, dropped)| {
+                self.finalize(tokens, logprobs, no_speech_prob, dropped, tokenizer)
+            })
+            .collect())
+    }
+
+    fn finalize(
+        &self,
+        mut tokens: Vec<i32>,
+        mut logprobs: Vec<f32>,
+        no_speech_prob: f32,
+        dropped_repetition: Vec<i32>,
+        tokenizer: &WhisperTokenizer,
+    ) -> DecodingResult {
         tokens = tokens.drain(self.initial_tokens_len.unwrap()..).collect();
-        let eot_index = tokens.iter().position(|x| *x == WhisperTokenizer::EOT);
+        let eot_index = tokens.iter().position(|x| *x == self.specials.eot);
         if let Some(eot_index) = eot_index {
             tokens.truncate(eot_index);
         }
-        Ok(tokens)
+        let avg_logprob = logprobs.iter().sum::<f32>() / (tokens.len() + 1) as f32;
+        logprobs.truncate(tokens.len());
+
+        let text_tokens = tokens
+            .iter()
+            .filter(|t| **t < self.specials.eot)
+            .map(|t| *t as u32)
+            .collect::<Vec<_>>();
+        let text = tokenizer.decode(&text_tokens, true).unwrap_or_default();
+        DecodingResult {
+            tokens,
+            logprobs,
+            avg_logprob,
+            compression_ratio: compression_ratio(&text),
+            no_speech_prob,
+            temperature: self.options.temperature,
+            dropped_repetition: dropped_repetition
+                .into_iter()
+                .filter(|t| *t != self.specials.eot)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::whisper::test_util::byte_tokenizer;
+    use crate::DecodingOptionsBuilder;
+
+    const EOT: i32 = 100;
+    const TS: i32 = 200;
+
+    #[test]
+    fn detects_looping_ngrams() {
+        // "a b" four times, interleaved with timestamps which are ignored
+        let mut sampled = vec![TS, 7];
+        for _ in 0..4 {
+            sampled.extend([TS, 1, 2]);
+        }
+        assert_eq!(repetition_start(&sampled, EOT, 4), None);
+        // the first "a b" is kept, the repeats begin at the second
+        assert_eq!(repetition_start(&sampled, EOT, 3), Some(6));
+        assert_eq!(repetition_start(&sampled, EOT, 2), Some(9));
+
+        let sampled = [3, 3, 3, 3];
+        assert_eq!(repetition_start(&sampled, EOT, 4), None);
+        assert_eq!(repetition_start(&sampled, EOT, 3), Some(1));
+        assert_eq!(repetition_start(&[1, 2, 3, 1, 2, 4], EOT, 1), None);
+    }
+
+    /// Byte level tokenizer, each ASCII character is its own token.
+    fn initial_tokens(options: DecodingOptionsBuilder) -> (Vec<i32>, u32) {
+        let options = options.language("en".to_string()).build();
+        let task = DecodingTask::new(options, &byte_tokenizer()).unwrap();
+        (task.initial_tokens().to_vec(), task.sample_len)
+    }
+
+    #[test]
+    fn initial_tokens_follow_the_options() {
+        let sot_sequence = vec![50258, 50259, 50359];
+        let (tokens, sample_len) = initial_tokens(DecodingOptionsBuilder::new());
+        assert_eq!(tokens, sot_sequence);
+        assert_eq!(sample_len, 224);
+
+        let options = DecodingOptionsBuilder::new().without_timestamps(true);
+        assert_eq!(initial_tokens(options).0, [50258, 50259, 50359, 50363]);
+
+        let options = DecodingOptionsBuilder::new()
+            .prefix("hi ".to_string())
+            .prompt("ok".to_string());
+        let (tokens, sample_len) = initial_tokens(options);
+        assert_eq!(
+            tokens,
+            [50361, 32, 111, 107, 50258, 50259, 50359, 32, 104, 105]
+        );
+        assert_eq!(sample_len, 224);
+    }
+
+    #[test]
+    fn long_prefixes_are_clamped() {
+        let prefix = "x".repeat(500);
+        let options = DecodingOptionsBuilder::new()
+            .prefix(prefix.clone())
+            .sample_len(220);
+        let (tokens, sample_len) = initial_tokens(options);
+        assert_eq!(tokens[3..], [120; 4]);
+        assert_eq!(sample_len, 220);
+
+        // sampling takes the whole half of the context, the prefix fills the rest of it
+        let options = DecodingOptionsBuilder::new()
+            .prefix(prefix.clone())
+            .prompt(prefix);
+        let (tokens, sample_len) = initial_tokens(options);
+        assert_eq!(tokens.len(), MAX_PROMPT_LENGTH + 1 + N_TEXT_CTX / 2 - 1);
+        assert_eq!(sample_len as usize, N_TEXT_CTX - tokens.len());
     }
 }
@@ -0,0 +1,15 @@
+use crate::{Language, Task, TokenizerSource, WhisperTokenizer};
+
+/// A tokenizer of single bytes padded to the size of the GPT-2 vocabulary, which needs no
+/// download.
+pub(crate) fn byte_tokenizer() -> WhisperTokenizer {
+    let mut vocabulary = (0..=255u8).map(|b| vec![b]).collect::<Vec<_>>();
+    vocabulary.extend((256..50257).map(|i| format!("#{}", i).into_bytes()));
+    WhisperTokenizer::load(
+        TokenizerSource::Vocabulary(vocabulary),
+        51865,
+        Language::String("en".to_string()),
+        Task::Transcribe,
+    )
+    .unwrap()
+}
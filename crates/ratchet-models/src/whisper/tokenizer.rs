@@ -1,6 +1,9 @@
 use crate::{Language, Task};
+use std::collections::HashMap;
 use std::ops::RangeInclusive;
-use tokenizers::Tokenizer;
+use tokenizers::decoders::byte_level::ByteLevel;
+use tokenizers::models::bpe::BPE;
+use tokenizers::{AddedToken, Tokenizer};
 
 #[cfg(not(target_arch = "wasm32"))]
 use hf_hub::api::sync::Api;
@@ -8,7 +11,7 @@ use hf_hub::api::sync::Api;
 use {ratchet_hub::ApiBuilder, ratchet_hub::RepoType, wasm_bindgen::JsError};
 
 lazy_static::lazy_static! {
-    pub static ref LANGUAGES: [&'static str; 99] = {
+    pub static ref LANGUAGES: [&'static str; 100] = {
         [
             "en", "zh", "de", "es", "ru", "ko", "fr", "ja", "pt", "tr", "pl", "ca", "nl", "ar",
             "sv", "it", "id", "hi", "fi", "vi", "he", "uk", "el", "ms", "cs", "ro", "da", "hu",
@@ -17,20 +20,167 @@ lazy_static::lazy_static! {
             "bs", "kk", "sq", "sw", "gl", "mr", "pa", "si", "km", "sn", "yo", "so", "af", "oc",
             "ka", "be", "tg", "sd", "gu", "am", "yi", "lo", "uz", "fo", "ht", "ps", "tk", "nn",
             "mt", "sa", "lb", "my", "bo", "tl", "mg", "as", "tt", "haw", "ln", "ha", "ba", "jw",
-            "su",
+            "su", "yue",
         ]
     };
 }
 
+/// Special token ids, which depend on the vocabulary of the model.
+///
+/// English only models lack the multilingual offset, and large-v3 adds a 100th language
+/// token ("yue"), shifting every special token after the languages up by one.
+///
+/// The task tokens & timestamps always end the vocabulary, so the languages are whatever
+/// lies between them & `<|startoftranscript|>`. Fine-tuned models with more languages than
+/// [LANGUAGES] have unnamed language tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpecialTokens {
+    pub n_vocab: usize,
+    pub n_languages: usize,
+    pub eot: i32,
+    pub sot: i32,
+    pub translate: i32,
+    pub transcribe: i32,
+    pub sot_lm: i32,
+    pub start_of_prev: i32,
+    pub no_captions: i32,
+    pub no_timestamps: i32,
+    pub ts_begin: i32,
+    pub ts_end: i32,
+}
+
+impl SpecialTokens {
+    //https://github.com/openai/whisper/blob/ba3f3cd54b0e5b8ce1ab3de13e32122d0d5f98ab/whisper/tokenizer.py#L332
+    pub fn new(n_vocab: usize) -> anyhow::Result<Self> {
+        const N_TIMESTAMPS: usize = 1501;
+        const N_TASK_TOKENS: usize = 6;
+        let multilingual = (n_vocab >= 51865) as i32;
+        let eot = 50256 + multilingual;
+        let sot = eot + 1;
+        let translate = n_vocab as i32 - (N_TASK_TOKENS + N_TIMESTAMPS) as i32;
+        anyhow::ensure!(
+            translate > sot,
+            "Vocabulary of {} tokens is too small for the Whisper special tokens",
+            n_vocab
+        );
+        let n_languages = (translate - sot - 1) as usize;
+        let ts_begin = translate + N_TASK_TOKENS as i32;
+        Ok(Self {
+            n_vocab,
+            n_languages,
+            eot,
+            sot,
+            translate,
+            transcribe: translate + 1,
+            sot_lm: translate + 2,
+            start_of_prev: translate + 3,
+            no_captions: translate + 4,
+            no_timestamps: translate + 5,
+            ts_begin,
+            ts_end: ts_begin + N_TIMESTAMPS as i32 - 1,
+        })
+    }
+
+    #[inline]
+    pub fn is_multilingual(&self) -> bool {
+        self.n_vocab >= 51865
+    }
+
+    #[inline]
+    pub fn languages(&self) -> RangeInclusive<i32> {
+        self.sot + 1..=self.sot + self.n_languages as i32
+    }
+
+    #[inline]
+    pub fn timestamps(&self) -> RangeInclusive<i32> {
+        self.ts_begin..=self.ts_end
+    }
+
+    #[inline]
+    pub fn is_timestamp(&self, token: i32) -> bool {
+        self.timestamps().contains(&token)
+    }
+
+    /// The [LANGUAGES] code of a language token, `None` for unnamed languages.
+    pub fn language_code(&self, token: i32) -> Option<&'static str> {
+        if !self.languages().contains(&token) {
+            return None;
+        }
+        LANGUAGES.get((token - self.sot - 1) as usize).copied()
+    }
+
+    /// Codes of the named languages of the vocabulary, in token order.
+    pub fn language_codes(&self) -> &'static [&'static str] {
+        &LANGUAGES[..self.n_languages.min(LANGUAGES.len())]
+    }
+
+    #[inline]
+    pub fn task(&self, task: Task) -> i32 {
+        match task {
+            Task::Transcribe => self.transcribe,
+            Task::Translate => self.translate,
+        }
+    }
+}
+
+impl Default for SpecialTokens {
+    fn default() -> Self {
+        Self::new(WhisperTokenizer::SIZE).unwrap()
+    }
+}
+
+/// Where the tokenizer for a model is loaded from.
+#[derive(Clone)]
+pub enum TokenizerSource {
+    /// Fetch `tokenizer.json` for the vocabulary from the HuggingFace hub.
+    Hub,
+    /// A local `tokenizer.json`.
+    #[cfg(not(target_arch = "wasm32"))]
+    File(std::path::PathBuf),
+    /// The contents of a `tokenizer.json`.
+    Bytes(Vec<u8>),
+    /// The raw byte-level BPE token table, as stored in the header of GGML model files.
+    Vocabulary(Vec<Vec<u8>>),
+}
+
+impl std::fmt::Debug for TokenizerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hub => write!(f, "Hub"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::File(path) => write!(f, "File({:?})", path),
+            Self::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Self::Vocabulary(tokens) => write!(f, "Vocabulary({} tokens)", tokens.len()),
+        }
+    }
+}
+
+impl TokenizerSource {
+    /// Uses the token table from a model header, if it has one.
+    ///
+    /// Files written before the table was preserved contain empty entries,
+    /// these fall back to the hub.
+    pub fn from_header(tokens: &[Vec<u8>]) -> Self {
+        if tokens.is_empty() || tokens.iter().all(|t| t.is_empty()) {
+            Self::Hub
+        } else {
+            Self::Vocabulary(tokens.to_vec())
+        }
+    }
+}
+
 //Wrapper around tokenizers::Tokenizer with helpers
 #[derive(Clone)]
 pub struct WhisperTokenizer {
     inner: Tokenizer,
+    specials: SpecialTokens,
     language: i32,
     task: Task,
 }
 
 impl WhisperTokenizer {
+    //The constants below are for the 51865 token multilingual vocabulary,
+    //use `specials()` for anything that may run against other vocabularies.
     pub const SOT: i32 = 50258;
     pub const EOT: i32 = 50257;
     pub const TRANSLATE: i32 = 50358;
@@ -58,93 +208,251 @@ impl WhisperTokenizer {
         49870, 50254,
     ];
 
+    /// The tokenizer.json matching a models vocabulary.
+    fn tokenizer_repo(n_vocab: usize) -> &'static str {
+        match n_vocab {
+            51866 => "openai/whisper-large-v3",
+            51864 => "openai/whisper-tiny.en",
+            _ => "openai/whisper-tiny",
+        }
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn load_inner(bytes: Option<Vec<u8>>) -> Tokenizer {
-        if let Some(bytes) = bytes {
-            Tokenizer::from_bytes(bytes).unwrap()
-        } else {
-            Self::fetch()
+    pub fn load_inner(source: TokenizerSource, n_vocab: usize) -> anyhow::Result<Tokenizer> {
+        match source {
+            TokenizerSource::Hub => Self::fetch(n_vocab),
+            TokenizerSource::File(path) => Tokenizer::from_file(path).map_err(anyhow::Error::msg),
+            TokenizerSource::Bytes(bytes) => {
+                Tokenizer::from_bytes(bytes).map_err(anyhow::Error::msg)
+            }
+            TokenizerSource::Vocabulary(tokens) => {
+                Self::from_vocabulary(&tokens, n_vocab).map_err(anyhow::Error::msg)
+            }
         }
     }
 
+    /// `n_vocab` is the vocabulary size of the model, see [SpecialTokens].
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn load(bytes: Option<Vec<u8>>, language: Language, task: Task) -> Self {
-        let inner = Self::load_inner(bytes);
+    pub fn load(
+        source: TokenizerSource,
+        n_vocab: usize,
+        language: Language,
+        task: Task,
+    ) -> anyhow::Result<Self> {
+        let inner = Self::load_inner(source, n_vocab)?;
         let mut tokenizer = Self {
             inner,
+            specials: SpecialTokens::new(n_vocab)?,
             language: -1,
             task,
         };
-        tokenizer.set_language(language);
-        tokenizer
+        tokenizer.set_language(language)?;
+        Ok(tokenizer)
     }
 
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn fetch() -> Tokenizer {
-        let api = Api::new().unwrap();
-        let tokenizer_repo = api.model("openai/whisper-tiny".to_string());
-        let tokenizer_path = tokenizer_repo.get("tokenizer.json").unwrap();
-        Tokenizer::from_file(tokenizer_path).unwrap()
+    pub fn fetch(n_vocab: usize) -> anyhow::Result<Tokenizer> {
+        let api = Api::new()?;
+        let tokenizer_repo = api.model(Self::tokenizer_repo(n_vocab).to_string());
+        let tokenizer_path = tokenizer_repo.get("tokenizer.json")?;
+        Tokenizer::from_file(tokenizer_path).map_err(anyhow::Error::msg)
     }
 
     #[cfg(target_arch = "wasm32")]
-    pub async fn load_inner(bytes: Option<Vec<u8>>) -> Tokenizer {
+    pub async fn load_inner(source: TokenizerSource, n_vocab: usize) -> anyhow::Result<Tokenizer> {
         use wasm_bindgen::JsValue;
 
-        if let Some(bytes) = bytes {
-            Tokenizer::from_bytes(bytes).unwrap()
-        } else {
-            Self::fetch().await.map_err(JsValue::from).unwrap()
+        match source {
+            TokenizerSource::Hub => Self::fetch(n_vocab)
+                .await
+                .map_err(|e| anyhow::anyhow!("{:?}", JsValue::from(e))),
+            TokenizerSource::Bytes(bytes) => {
+                Tokenizer::from_bytes(bytes).map_err(anyhow::Error::msg)
+            }
+            TokenizerSource::Vocabulary(tokens) => {
+                Self::from_vocabulary(&tokens, n_vocab).map_err(anyhow::Error::msg)
+            }
         }
     }
 
+    /// `n_vocab` is the vocabulary size of the model, see [SpecialTokens].
     #[cfg(target_arch = "wasm32")]
-    pub async fn load(bytes: Option<Vec<u8>>, language: Language, task: Task) -> Self {
-        let inner = Self::load_inner(bytes).await;
+    pub async fn load(
+        source: TokenizerSource,
+        n_vocab: usize,
+        language: Language,
+        task: Task,
+    ) -> anyhow::Result<Self> {
+        let inner = Self::load_inner(source, n_vocab).await?;
         let mut tokenizer = Self {
             inner,
+            specials: SpecialTokens::new(n_vocab)?,
             language: -1,
             task,
         };
-        tokenizer.set_language(language);
-        tokenizer
+        tokenizer.set_language(language)?;
+        Ok(tokenizer)
     }
 
     #[cfg(target_arch = "wasm32")]
-    pub async fn fetch() -> Result<Tokenizer, JsError> {
-        let model_repo = ApiBuilder::from_hf("openai/whisper-tiny", RepoType::Model).build();
+    pub async fn fetch(n_vocab: usize) -> Result<Tokenizer, JsError> {
+        let model_repo =
+            ApiBuilder::from_hf(Self::tokenizer_repo(n_vocab), RepoType::Model).build();
         let model_bytes = model_repo.get("tokenizer.json").await?;
-        Ok(Tokenizer::from_bytes(model_bytes.to_vec()).unwrap())
+        Tokenizer::from_bytes(model_bytes.to_vec()).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Builds a byte-level BPE tokenizer from a tiktoken style token table, where the
+    /// merge priority of a pair is the rank of the token it produces.
+    /// Special tokens are appended from [SpecialTokens].
+    pub fn from_vocabulary(tokens: &[Vec<u8>], n_vocab: usize) -> tokenizers::Result<Tokenizer> {
+        let specials = SpecialTokens::new(n_vocab).map_err(|e| e.to_string())?;
+        if tokens.len() != specials.eot as usize {
+            return Err(format!(
+                "Token table has {} entries, expected {} for a vocabulary of {}",
+                tokens.len(),
+                specials.eot,
+                n_vocab
+            )
+            .into());
+        }
+
+        let byte_chars = Self::byte_chars();
+        let ordered = tokens
+            .iter()
+            .map(|t| {
+                t.iter()
+                    .map(|b| byte_chars[*b as usize])
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        let vocab = ordered
+            .iter()
+            .enumerate()
+            .map(|(rank, t)| (t.clone(), rank as u32))
+            .collect::<HashMap<_, _>>();
+
+        let mut merges = vec![];
+        for (rank, token) in ordered.iter().enumerate() {
+            let mut local = token
+                .char_indices()
+                .skip(1)
+                .filter_map(|(i, _)| {
+                    let (l, r) = token.split_at(i);
+                    Some((*vocab.get(l)?, *vocab.get(r)?, l, r))
+                })
+                .collect::<Vec<_>>();
+            local.sort_unstable_by_key(|(l, r, _, _)| (*l, *r));
+            merges.extend(local.into_iter().map(|(_, _, l, r)| (rank, l, r)));
+        }
+        merges.sort_by_key(|(rank, _, _)| *rank);
+        let merges = merges
+            .into_iter()
+            .map(|(_, l, r)| (l.to_string(), r.to_string()))
+            .collect();
+
+        let bpe = BPE::builder().vocab_and_merges(vocab, merges).build()?;
+        let mut tokenizer = Tokenizer::new(bpe);
+        tokenizer.with_pre_tokenizer(ByteLevel::new(false, true, true));
+        tokenizer.with_decoder(ByteLevel::default());
+
+        let mut special_tokens = vec![
+            "<|endoftext|>".to_string(),
+            "<|startoftranscript|>".to_string(),
+        ];
+        special_tokens.extend((0..specials.n_languages).map(|i| match LANGUAGES.get(i) {
+            Some(l) => format!("<|{}|>", l),
+            None => format!("<|language{}|>", i),
+        }));
+        special_tokens.extend(
+            [
+                "<|translate|>",
+                "<|transcribe|>",
+                "<|startoflm|>",
+                "<|startofprev|>",
+                "<|nocaptions|>",
+                "<|notimestamps|>",
+            ]
+            .map(String::from),
+        );
+        special_tokens.extend(
+            specials
+                .timestamps()
+                .map(|t| format!("<|{:.2}|>", (t - specials.ts_begin) as f32 * 0.02)),
+        );
+        let added = special_tokens
+            .into_iter()
+            .map(|t| AddedToken::from(t, true))
+            .collect::<Vec<_>>();
+        tokenizer.add_special_tokens(&added);
+        Ok(tokenizer)
+    }
+
+    /// GPT-2's reversible mapping of bytes onto printable characters.
+    fn byte_chars() -> [char; 256] {
+        let printable = |b: u8| matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+        let mut chars = ['\0'; 256];
+        let mut n = 0;
+        for b in 0..=255u8 {
+            chars[b as usize] = if printable(b) {
+                b as char
+            } else {
+                n += 1;
+                char::from_u32(255 + n).unwrap()
+            };
+        }
+        chars
     }
 
-    pub fn set_language(&mut self, language: Language) {
+    pub fn set_language(&mut self, language: Language) -> anyhow::Result<()> {
         let token = match language {
             Language::String(s) => {
-                let lang_position = LANGUAGES.iter().position(|x| *x == s);
-                if lang_position.is_none() {
-                    panic!("Language {} not found", s);
-                }
-
-                WhisperTokenizer::SOT + 1 + lang_position.unwrap() as i32
+                let lang_position = LANGUAGES
+                    .iter()
+                    .position(|x| *x == s)
+                    .ok_or_else(|| anyhow::anyhow!("Language {} not found", s))?;
+                anyhow::ensure!(
+                    lang_position < self.specials.n_languages,
+                    "Language {} not supported by this model",
+                    s
+                );
+                self.specials.sot + 1 + lang_position as i32
             }
             Language::Token(t) => t,
         };
         self.language = token;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn specials(&self) -> SpecialTokens {
+        self.specials
     }
 
     #[inline]
     pub fn sot_sequence(&self) -> Vec<i32> {
-        vec![Self::SOT, self.language, self.task.into()]
+        vec![
+            self.specials.sot,
+            self.language,
+            self.specials.task(self.task),
+        ]
     }
 
     #[inline]
-    pub fn is_timestamp(token: i32) -> bool {
-        Self::TIMESTAMPS.contains(&token)
+    pub fn sot_sequence_including_notimestamps(&self) -> Vec<i32> {
+        let mut sequence = self.sot_sequence();
+        sequence.push(self.specials.no_timestamps);
+        sequence
+    }
+
+    #[inline]
+    pub fn is_timestamp(&self, token: i32) -> bool {
+        self.specials.is_timestamp(token)
     }
 
     #[inline]
     pub fn is_multilingual(&self) -> bool {
-        self.inner.get_vocab_size(true) == Self::SIZE
+        self.specials.is_multilingual()
     }
 
     pub fn encode(&self, text: &str, skip_special: bool) -> Result<Vec<i32>, tokenizers::Error> {
@@ -161,3 +469,141 @@ impl WhisperTokenizer {
         self.inner.decode(tokens, skip_special)
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_tokens_match_vocabulary() {
+        let multilingual = SpecialTokens::new(51865).unwrap();
+        assert_eq!(multilingual, SpecialTokens::default());
+        assert_eq!(multilingual.sot, WhisperTokenizer::SOT);
+        assert_eq!(multilingual.eot, WhisperTokenizer::EOT);
+        assert_eq!(
+            multilingual.task(Task::Translate),
+            WhisperTokenizer::TRANSLATE
+        );
+        assert_eq!(multilingual.start_of_prev, WhisperTokenizer::START_OF_PREV);
+        assert_eq!(multilingual.no_timestamps, WhisperTokenizer::NO_TIMESTAMPS);
+        assert_eq!(multilingual.timestamps(), WhisperTokenizer::TIMESTAMPS);
+        assert_eq!(multilingual.languages(), WhisperTokenizer::LANGUAGES);
+
+        let large_v3 = SpecialTokens::new(51866).unwrap();
+        assert_eq!(large_v3.n_languages, 100);
+        assert_eq!(*large_v3.languages().end(), 50358);
+        assert_eq!(large_v3.transcribe, 50360);
+        assert_eq!(large_v3.no_timestamps, 50364);
+        assert_eq!(large_v3.ts_begin, 50365);
+        assert_eq!(large_v3.ts_end as usize, large_v3.n_vocab - 1);
+
+        let english = SpecialTokens::new(51864).unwrap();
+        assert!(!english.is_multilingual());
+        assert_eq!(english.eot, 50256);
+        assert_eq!(english.sot, 50257);
+        assert_eq!(english.transcribe, 50358);
+        assert_eq!(english.ts_begin, 50363);
+
+        assert!(SpecialTokens::new(50000).is_err());
+
+        //A fine-tune with two extra languages
+        let extended = SpecialTokens::new(51868).unwrap();
+        assert_eq!(extended.n_languages, 102);
+        assert_eq!(extended.translate, 50361);
+        assert_eq!(extended.ts_end as usize, extended.n_vocab - 1);
+        assert_eq!(extended.language_codes().len(), 100);
+        assert_eq!(extended.language_code(50358), Some("yue"));
+        assert_eq!(extended.language_code(50360), None);
+    }
+
+    #[test]
+    fn invalid_sources_are_errors() {
+        let load = |source| {
+            let language = Language::String("en".to_string());
+            WhisperTokenizer::load(source, 51865, language, Task::Transcribe)
+        };
+        assert!(load(TokenizerSource::Bytes(b"not json".to_vec())).is_err());
+        assert!(load(TokenizerSource::File("missing/tokenizer.json".into())).is_err());
+        assert!(load(TokenizerSource::Vocabulary(vec![vec![0]])).is_err());
+    }
+
+    fn synthetic_vocabulary() -> Vec<Vec<u8>> {
+        let mut tokens = (0..=255u8).map(|b| vec![b]).collect::<Vec<_>>();
+        for merged in [
+            "he", "ll", "hell", "hello", " w", "or", " wor", "ld", " world",
+        ] {
+            tokens.push(merged.as_bytes().to_vec());
+        }
+        let mut i = 0;
+        while tokens.len() < 50257 {
+            tokens.push(format!("#{}", i).into_bytes());
+            i += 1;
+        }
+        tokens
+    }
+
+    #[test]
+    fn vocabulary_round_trips() {
+        let tokens = synthetic_vocabulary();
+        let inner = WhisperTokenizer::from_vocabulary(&tokens, 51865).unwrap();
+        let mut tokenizer = WhisperTokenizer {
+            inner,
+            specials: SpecialTokens::new(51865).unwrap(),
+            language: -1,
+            task: Task::Transcribe,
+        };
+
+        let ids = tokenizer.encode("hello world!", false).unwrap();
+        assert_eq!(ids, vec![259, 264, 33]);
+        let ids = ids.iter().map(|t| *t as u32).collect::<Vec<_>>();
+        assert_eq!(tokenizer.decode(&ids, true).unwrap(), "hello world!");
+
+        let inner = &tokenizer.inner;
+        assert_eq!(inner.token_to_id("<|endoftext|>"), Some(50257));
+        assert_eq!(inner.token_to_id("<|startoftranscript|>"), Some(50258));
+        assert_eq!(inner.token_to_id("<|en|>"), Some(50259));
+        assert_eq!(inner.token_to_id("<|notimestamps|>"), Some(50363));
+        assert_eq!(inner.token_to_id("<|0.00|>"), Some(50364));
+        assert_eq!(inner.token_to_id("<|30.00|>"), Some(51864));
+        assert!(WhisperTokenizer::from_vocabulary(&tokens[1..], 51865).is_err());
+
+        //Cantonese is only in the v3 vocabulary
+        assert!(tokenizer
+            .set_language(Language::String("yue".into()))
+            .is_err());
+        assert!(tokenizer
+            .set_language(Language::String("xx".into()))
+            .is_err());
+        tokenizer
+            .set_language(Language::String("en".into()))
+            .unwrap();
+        assert_eq!(tokenizer.language, 50259);
+    }
+
+    #[test]
+    fn embedded_vocabulary_matches_hub() {
+        use crate::Whisper;
+        use ratchet_loader::GGMLCompatible;
+
+        let api = Api::new().unwrap();
+        let model = api.model("ggerganov/whisper.cpp".to_string());
+        let path = model.get("ggml-tiny.bin").unwrap();
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        let header = Whisper::load_header(&mut reader).unwrap();
+        let n_vocab = header.hparams.n_vocab as usize;
+
+        let embedded = WhisperTokenizer::from_vocabulary(&header.tokens, n_vocab).unwrap();
+        let hub = WhisperTokenizer::fetch(n_vocab).unwrap();
+        let text =
+            " And so my fellow Americans, ask not what your country can do for you. Ça va? 東京";
+        let expected = hub.encode(text, false).unwrap();
+        assert_eq!(
+            embedded.encode(text, false).unwrap().get_ids(),
+            expected.get_ids()
+        );
+        assert_eq!(
+            embedded.decode(expected.get_ids(), false).unwrap(),
+            hub.decode(expected.get_ids(), false).unwrap()
+        );
+    }
+}
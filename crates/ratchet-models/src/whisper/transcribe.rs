@@ -1,92 +1,618 @@
 use crate::StreamedSegment;
 use crate::{
-    DecodingOptions, DecodingTask, Language, Prompt, TranscriptionResult, Whisper,
-    WhisperTokenizer, HOP_LENGTH, N_AUDIO_CTX, N_FRAMES, SAMPLE_RATE,
+    DecodingOptions, DecodingResult, DecodingTask, Intervention, InterventionKind, Language,
+    Prompt, Segment, SpeechRegion, SpeechTimeline, TranscriptionResult, VoiceActivityDetector,
+    Whisper, WhisperTokenizer, HOP_LENGTH, MAX_PROMPT_LENGTH, N_AUDIO_CTX, N_FRAMES, SAMPLE_RATE,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use ratchet::rvec;
+use ratchet::{shape, Tensor};
 use ratchet_nn::Module;
+#[cfg(not(target_arch = "wasm32"))]
 use std::cmp::min;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use web_time::Instant;
 
+/// Cheaply cloneable flag used to abort a transcription from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The prompt of each window: the initial prompt followed by the text decoded since the
+/// prompt was last reset, truncated from the front of the decoded text.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PromptHistory {
+    initial: Vec<i32>,
+    tokens: Vec<i32>,
+    reset_since: usize,
+}
+
+impl PromptHistory {
+    /// Keeps the end of an `initial` prompt longer than [MAX_PROMPT_LENGTH], as OpenAI does.
+    pub(crate) fn new(
+        initial: Option<&Prompt>,
+        tokenizer: &WhisperTokenizer,
+    ) -> anyhow::Result<Self> {
+        let mut initial = match initial {
+            Some(prompt) => prompt.tokens(tokenizer).map_err(anyhow::Error::msg)?,
+            None => vec![],
+        };
+        initial.drain(..initial.len().saturating_sub(MAX_PROMPT_LENGTH));
+        Ok(Self {
+            initial,
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn extend(&mut self, tokens: impl IntoIterator<Item = i32>) {
+        self.tokens.extend(tokens);
+    }
+
+    /// Drops the text decoded so far from future prompts.
+    pub(crate) fn reset(&mut self) {
+        self.reset_since = self.tokens.len();
+    }
+
+    pub(crate) fn prompt(&self) -> Option<Prompt> {
+        let decoded = &self.tokens[self.reset_since..];
+        let room = MAX_PROMPT_LENGTH.saturating_sub(self.initial.len());
+        let mut prompt = self.initial.clone();
+        prompt.extend_from_slice(&decoded[decoded.len().saturating_sub(room)..]);
+        (!prompt.is_empty()).then_some(Prompt::Tokens(prompt))
+    }
+}
+
+/// Suppresses repetition across windows, recording every change made as an [Intervention].
+#[derive(Debug, Default)]
+struct RepetitionFilter {
+    enabled: bool,
+    /// Normalised text of each segment of the previous window.
+    previous: Vec<String>,
+    interventions: Vec<Intervention>,
+}
+
+impl RepetitionFilter {
+    fn new(decode_options: &DecodingOptions) -> Self {
+        Self {
+            enabled: decode_options.filter_repeated_segments,
+            ..Default::default()
+        }
+    }
+
+    fn normalise(text: &str) -> String {
+        text.trim().to_lowercase()
+    }
+
+    /// Records the loop cut from `decoded`, if any, & drops the `segments` repeating the
+    /// previous window. `window` is the start & stop of the window `decoded` is from.
+    fn apply(
+        &mut self,
+        decoded: &DecodingResult,
+        segments: &mut Vec<Segment>,
+        window: (f64, f64),
+        tokenizer: &WhisperTokenizer,
+    ) {
+        let eot = tokenizer.specials().eot;
+        if !decoded.dropped_repetition.is_empty() {
+            let text_tokens = decoded
+                .dropped_repetition
+                .iter()
+                .filter(|t| **t < eot)
+                .map(|t| *t as u32)
+                .collect::<Vec<_>>();
+            let text = tokenizer.decode(&text_tokens, true).unwrap_or_default();
+            log::warn!("Decoding stopped as the text started looping: {}", text);
+            self.interventions.push(Intervention {
+                kind: InterventionKind::LoopTruncated,
+                start: window.0,
+                stop: window.1,
+                text,
+            });
+        }
+
+        let texts = segments
+            .iter()
+            .map(|s| Self::normalise(&s.text(tokenizer)))
+            .collect::<Vec<_>>();
+        if self.enabled {
+            let mut kept = Vec::with_capacity(segments.len());
+            for (segment, text) in segments.drain(..).zip(&texts) {
+                if text.is_empty() || !self.previous.contains(text) {
+                    kept.push(segment);
+                    continue;
+                }
+                log::warn!("Dropping segment repeating the previous window: {}", text);
+                self.interventions.push(Intervention {
+                    kind: InterventionKind::RepeatedSegmentDropped,
+                    start: segment.start,
+                    stop: segment.stop,
+                    text: segment.text(tokenizer),
+                });
+            }
+            *segments = kept;
+        }
+        self.previous = texts;
+    }
+}
+
+/// Callbacks & cancellation for a native [transcribe_with_hooks] call.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub struct TranscribeHooks<'a> {
+    /// Called with each segment once its window has been decoded,
+    /// followed by an empty segment with `last` set.
+    pub on_segment: Option<Box<dyn FnMut(StreamedSegment) + 'a>>,
+    /// Called after each window with the fraction of the audio processed, in [0, 1].
+    pub on_progress: Option<Box<dyn FnMut(f32) + 'a>>,
+    /// Checked between windows and decoder steps.
+    pub cancel: Option<CancellationToken>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> TranscribeHooks<'a> {
+    pub fn on_segment(mut self, f: impl FnMut(StreamedSegment) + 'a) -> Self {
+        self.on_segment = Some(Box::new(f));
+        self
+    }
+
+    pub fn on_progress(mut self, f: impl FnMut(f32) + 'a) -> Self {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    fn segment(&mut self, segment: StreamedSegment) {
+        if let Some(ref mut f) = self.on_segment {
+            f(segment);
+        }
+    }
+
+    fn progress(&mut self, processed: usize, total: usize) {
+        if let Some(ref mut f) = self.on_progress {
+            f((processed as f32 / total.max(1) as f32).min(1.0));
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), crate::DecodeError> {
+        match &self.cancel {
+            Some(cancel) if cancel.is_cancelled() => Err(crate::DecodeError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn transcribe(
+    model: &mut Whisper,
+    audio: Vec<f32>,
+    decode_options: DecodingOptions,
+) -> anyhow::Result<TranscriptionResult> {
+    transcribe_with_hooks(model, audio, decode_options, TranscribeHooks::default())
+}
+
+/// As [transcribe], reporting segments & progress as they are produced.
+///
+/// Returns `DecodeError::Cancelled` if the cancellation token is set.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn transcribe_with_hooks(
     model: &mut Whisper,
     audio: Vec<f32>,
     mut decode_options: DecodingOptions,
+    mut hooks: TranscribeHooks,
 ) -> anyhow::Result<TranscriptionResult> {
     let runtime = Instant::now();
-    let mel = model.specgen.generate(audio)?.to(&model.device)?;
+    let duration = audio.len() as f64 / SAMPLE_RATE as f64;
+    let (audio, timeline) = select_audio(audio, &decode_options);
+    if timeline.is_some() && audio.is_empty() {
+        log::info!("No audio to transcribe");
+        hooks.segment(StreamedSegment::new(
+            duration,
+            duration,
+            String::new(),
+            true,
+        ));
+        return Ok(TranscriptionResult::new(
+            runtime.elapsed(),
+            vec![],
+            Some(String::new()),
+        ));
+    }
+    let mel = model.specgen.generate_on(audio, &model.device)?;
     let content_frames = mel.shape()[mel.rank() - 1] - N_FRAMES;
 
-    if decode_options.language.is_none() {
-        if !model.is_multilingual() {
-            log::warn!("No language specified, using English");
-            decode_options.language = Some(Language::String("en".to_string()));
-        } else {
-            log::warn!("No language specified, using language detection");
-            let mel = mel.slice(&[0..1, 0..80, 0..3000])?;
-            decode_options.language = Some(model.detect_language(mel)?);
-        }
-    }
+    let (language, language_probability) = resolve_language(model, &mel, &decode_options)?;
+    decode_options.language = Some(language);
 
     let language = decode_options.language.as_ref().unwrap();
     let task = decode_options.task;
-    let tokenizer = WhisperTokenizer::load(None, language.clone(), task);
+    let tokenizer = WhisperTokenizer::load(
+        model.tokenizer.clone(),
+        model.n_vocab(),
+        language.clone(),
+        task,
+    )?;
 
     let mut seek = 0;
     let input_stride = N_FRAMES / N_AUDIO_CTX;
-    let mut all_tokens = Vec::with_capacity(512);
+    let mut history = PromptHistory::new(decode_options.prompt.as_ref(), &tokenizer)?;
+    let mut repetition = RepetitionFilter::new(&decode_options);
     let mut all_segments = Vec::with_capacity(512);
-    let prompt_since_reset = 0;
 
     let mut pass_idx = 0;
     while seek < content_frames {
+        hooks.check_cancelled()?;
         model.device.try_gpu()?.begin_pass(pass_idx);
-        println!("seek: {}", seek);
         let mut decode_options = decode_options.clone();
         let time_offset = (seek * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
         decode_options.time_offset = Some(time_offset);
-        let mel_segment = mel.slice(&[0..1, 0..80, seek..(seek + N_FRAMES)])?;
-        log::info!(
-            "processing segment - from: {}, to: {}",
-            seek,
-            seek + N_FRAMES
-        );
+        if seek > 0 {
+            // the prefix only constrains how the transcript starts
+            decode_options.prefix = None;
+        }
+        let end = window_end(timeline.as_ref(), seek, content_frames);
+        let mel_segment = mel_window(&mel, seek, end, content_frames)?;
+        log::info!("processing segment - from: {}, to: {}", seek, end);
 
-        let segment_size = min(N_FRAMES, content_frames - seek);
-        let segment_duration = segment_size * HOP_LENGTH / SAMPLE_RATE;
+        let segment_size = end - seek;
+        let segment_duration = (segment_size * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
 
-        if !all_tokens.is_empty() {
-            decode_options.prompt = Some(Prompt::Tokens(all_tokens[prompt_since_reset..].to_vec()));
-        }
+        decode_options.prompt = history.prompt();
 
         let hs = model.encoder.forward(&mel_segment)?.resolve()?;
 
-        let task = DecodingTask::new(decode_options, &tokenizer);
-        let decoded = task.run(&mut model.decoder, hs, &tokenizer)?;
-        let (segments, advance) = DecodingTask::build_segments(
-            decoded,
+        let (task, decoded) = DecodingTask::run_with_fallback(
+            decode_options,
+            &mut model.decoder,
+            hs,
+            &tokenizer,
+            hooks.cancel.as_ref(),
+        )?;
+        if task.is_silent(&decoded) {
+            log::info!(
+                "skipping silent segment - no speech probability: {}",
+                decoded.no_speech_prob
+            );
+            seek += segment_size;
+            pass_idx += 1;
+            hooks.progress(seek, content_frames);
+            continue;
+        }
+
+        let (mut segments, advance) = DecodingTask::build_segments(
+            decoded.tokens.clone(),
             time_offset,
             segment_size,
             segment_duration,
             input_stride,
+            tokenizer.specials(),
         );
-        let all_segment_tokens = segments
-            .iter()
-            .flat_map(|s| s.tokens.iter().copied())
-            .map(|x| x as i32)
-            .collect::<Vec<_>>();
-        all_tokens.extend(all_segment_tokens);
+        decoded.annotate(&mut segments, &tokenizer);
+        let mut window = (time_offset, time_offset + segment_duration);
+        if let Some(timeline) = &timeline {
+            timeline.restore(&mut segments);
+            window = (
+                timeline.to_original(window.0, false),
+                timeline.to_original(window.1, true),
+            );
+        }
+        repetition.apply(&decoded, &mut segments, window, &tokenizer);
+        for segment in &segments {
+            hooks.segment(StreamedSegment::from_segment(&tokenizer, segment, false));
+        }
+        history.extend(
+            segments
+                .iter()
+                .flat_map(|s| s.tokens.iter().map(|t| *t as i32)),
+        );
+        if task.resets_prompt(&decoded) {
+            history.reset();
+        }
         all_segments.extend(segments);
-        model.decoder.reset();
         seek += advance;
         pass_idx += 1;
+        hooks.progress(seek, content_frames);
     }
 
+    let end = (content_frames * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+    let end = timeline.map_or(end, |t| t.to_original(end, true));
+    hooks.segment(StreamedSegment::new(end, end, String::new(), true));
+
     let mut t = TranscriptionResult::new(runtime.elapsed(), all_segments, None);
+    t.language = language.code(&tokenizer.specials());
+    t.language_probability = language_probability;
+    t.interventions = repetition.interventions;
     t.generate_formatted(&tokenizer);
     Ok(t)
 }
 
+/// The language to decode `mel` with, detecting it from the first window if not provided.
+///
+/// Also returns the probability of the detected language.
+#[cfg(not(target_arch = "wasm32"))]
+fn resolve_language(
+    model: &mut Whisper,
+    mel: &Tensor,
+    decode_options: &DecodingOptions,
+) -> anyhow::Result<(Language, Option<f32>)> {
+    if let Some(language) = &decode_options.language {
+        return Ok((language.clone(), None));
+    }
+    if !model.is_multilingual() {
+        log::warn!("No language specified, using English");
+        return Ok((Language::String("en".to_string()), None));
+    }
+    log::warn!("No language specified, using language detection");
+    let mel = mel.slice(&[0..1, 0..model.n_mels(), 0..N_FRAMES])?;
+    let allowed = decode_options.allowed_languages.as_deref();
+    let detection = model.detect_language_probs(mel, allowed)?;
+    let best = detection.best();
+    log::info!(
+        "Detected language {} ({:.2})",
+        best.language,
+        best.probability
+    );
+    Ok((Language::Token(best.token), Some(best.probability)))
+}
+
+/// The audio to decode, restricted to the requested clips & to their speech regions if voice
+/// activity detection is enabled, along with the timeline to restore the decoded timestamps with.
+fn select_audio(
+    audio: Vec<f32>,
+    decode_options: &DecodingOptions,
+) -> (Vec<f32>, Option<SpeechTimeline>) {
+    let clips = match decode_options.clip_timestamps.is_empty() {
+        true => vec![SpeechRegion {
+            start: 0,
+            end: audio.len(),
+        }],
+        false => SpeechRegion::from_clip_timestamps(&decode_options.clip_timestamps, audio.len()),
+    };
+    let clips = match &decode_options.vad {
+        None if decode_options.clip_timestamps.is_empty() => return (audio, None),
+        None => clips.into_iter().map(|clip| vec![clip]).collect::<Vec<_>>(),
+        Some(vad) => {
+            let detector = VoiceActivityDetector::new(vad.clone());
+            clips
+                .iter()
+                .map(|clip| {
+                    detector
+                        .detect(&audio[clip.start..clip.end])
+                        .into_iter()
+                        .map(|region| SpeechRegion {
+                            start: clip.start + region.start,
+                            end: clip.start + region.end,
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    };
+    let (selected, timeline) = SpeechTimeline::gather_clips(&audio, &clips);
+    log::info!(
+        "Selected {} regions, {:.1}s of {:.1}s",
+        clips.iter().map(Vec::len).sum::<usize>(),
+        selected.len() as f64 / SAMPLE_RATE as f64,
+        audio.len() as f64 / SAMPLE_RATE as f64
+    );
+    (selected, Some(timeline))
+}
+
+/// The frame the window starting at `seek` ends at, the end of its clip if that is within
+/// [N_FRAMES], so that a window never spans two clips.
+fn window_end(timeline: Option<&SpeechTimeline>, seek: usize, content_frames: usize) -> usize {
+    let clip_end = timeline.map_or(content_frames, |t| {
+        t.clip_end(seek * HOP_LENGTH).div_ceil(HOP_LENGTH)
+    });
+    clip_end.min(content_frames).min(seek + N_FRAMES)
+}
+
+/// The [N_FRAMES] window of `mel` from `seek`, with the frames from `end` on silenced
+/// by the padding frames at the end of the mel.
+fn mel_window(
+    mel: &Tensor,
+    seek: usize,
+    end: usize,
+    content_frames: usize,
+) -> anyhow::Result<Tensor> {
+    let n_mels = mel.shape()[1];
+    if seek + N_FRAMES == end || end == content_frames {
+        return mel.slice(&[0..1, 0..n_mels, seek..(seek + N_FRAMES)]);
+    }
+    //Frames are selected with masks, as index writes only copy contiguous blocks
+    let keep = (0..N_FRAMES)
+        .map(|f| if seek + f < end { 1f32 } else { 0. })
+        .collect::<Vec<_>>();
+    let silence = keep.iter().map(|k| 1. - k).collect::<Vec<_>>();
+    let keep = Tensor::from_data(keep, shape![1, 1, N_FRAMES], mel.device().clone());
+    let silence = Tensor::from_data(silence, shape![1, 1, N_FRAMES], mel.device().clone());
+    let window = mel.slice(&[0..1, 0..n_mels, seek..(seek + N_FRAMES)])?;
+    let padding = mel.slice(&[0..1, 0..n_mels, content_frames..(content_frames + N_FRAMES)])?;
+    window.mul(&keep)?.add(&padding.mul(&silence)?)
+}
+
+/// A recording being transcribed by [transcribe_batch].
+#[cfg(not(target_arch = "wasm32"))]
+struct BatchItem {
+    /// [1, n_mels, n_frames], kept on the device for the windows to be sliced from.
+    mel: Tensor,
+    content_frames: usize,
+    seek: usize,
+    tokenizer: WhisperTokenizer,
+    language: Language,
+    language_probability: Option<f32>,
+    segments: Vec<Segment>,
+    repetition: RepetitionFilter,
+}
+
+/// Transcribes several recordings, encoding & decoding their windows in batches of up to
+/// `batch_size`.
+///
+/// Each pass takes the next window of every unfinished recording. Windows that share
+/// initial tokens, i.e the same language, are batched together, so recordings of different
+/// languages are decoded in separate batches.
+///
+/// Unlike [transcribe], windows are not prompted with the previously decoded text,
+/// as every recording would need a different prompt, and neither voice activity detection
+/// nor clip timestamps are applied.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn transcribe_batch(
+    model: &mut Whisper,
+    audios: Vec<Vec<f32>>,
+    decode_options: DecodingOptions,
+    batch_size: usize,
+) -> anyhow::Result<Vec<TranscriptionResult>> {
+    anyhow::ensure!(batch_size > 0, "Batch size must be at least 1");
+    let runtime = Instant::now();
+    let n_mels = model.n_mels();
+
+    let mut tokenizer: Option<WhisperTokenizer> = None;
+    let mut items = Vec::with_capacity(audios.len());
+    for audio in audios {
+        let mel = model.specgen.generate_on(audio, &model.device)?;
+        let content_frames = mel.shape()[mel.rank() - 1] - N_FRAMES;
+        let (language, language_probability) = resolve_language(model, &mel, &decode_options)?;
+        let mut item_tokenizer = match &tokenizer {
+            Some(t) => t.clone(),
+            None => WhisperTokenizer::load(
+                model.tokenizer.clone(),
+                model.n_vocab(),
+                language.clone(),
+                decode_options.task,
+            )?,
+        };
+        item_tokenizer.set_language(language.clone())?;
+        tokenizer.get_or_insert_with(|| item_tokenizer.clone());
+
+        items.push(BatchItem {
+            mel,
+            content_frames,
+            seek: 0,
+            tokenizer: item_tokenizer,
+            language,
+            language_probability,
+            segments: Vec::with_capacity(512),
+            repetition: RepetitionFilter::new(&decode_options),
+        });
+    }
+
+    let input_stride = N_FRAMES / N_AUDIO_CTX;
+    let mut pass_idx = 0;
+    loop {
+        //Group the next window of each recording by its initial tokens
+        let mut groups: Vec<(DecodingTask, Vec<usize>)> = vec![];
+        for (idx, item) in items.iter().enumerate() {
+            if item.seek >= item.content_frames {
+                continue;
+            }
+            let mut options = decode_options.clone();
+            options.language = Some(item.language.clone());
+            if item.seek > 0 {
+                // the prefix only constrains how the transcript starts
+                options.prefix = None;
+            }
+            let task = DecodingTask::new(options, &item.tokenizer)?;
+            match groups.iter_mut().find(|(t, members)| {
+                t.initial_tokens() == task.initial_tokens() && members.len() < batch_size
+            }) {
+                Some((_, members)) => members.push(idx),
+                None => groups.push((task, vec![idx])),
+            }
+        }
+        if groups.is_empty() {
+            break;
+        }
+
+        for (task, members) in groups {
+            model.device.try_gpu()?.begin_pass(pass_idx);
+            pass_idx += 1;
+            //Windows are written into the batch on the device, & encoded in the same pass
+            let shape = shape![members.len(), n_mels, N_FRAMES];
+            let mut windows = Tensor::zeros::<f32>(&shape, &model.device);
+            for (b, &idx) in members.iter().enumerate() {
+                let BatchItem { mel, seek, .. } = &items[idx];
+                let window = mel.slice(&[0..1, 0..n_mels, *seek..*seek + N_FRAMES])?;
+                windows = windows.index_write(&window, rvec![b, 0, 0])?;
+            }
+            log::info!("processing batch of {} windows", members.len());
+
+            let hs = model.encoder.forward(&windows)?.resolve()?;
+            let tokenizer = &items[members[0]].tokenizer;
+            let mut results = task.run_batch(&mut model.decoder, hs.clone(), tokenizer)?;
+            model.decoder.reset();
+            //Windows needing a fallback are decoded again one at a time
+            for (b, decoded) in results.iter_mut().enumerate() {
+                let Some(options) = task.fallback(decoded) else {
+                    continue;
+                };
+                let (n_ctx, n_state) = (hs.shape()[1], hs.shape()[2]);
+                let window_ctx = hs.slice(&[b..b + 1, 0..n_ctx, 0..n_state])?;
+                (_, *decoded) = DecodingTask::run_with_fallback(
+                    options,
+                    &mut model.decoder,
+                    window_ctx,
+                    tokenizer,
+                    None,
+                )?;
+            }
+
+            for (idx, decoded) in members.into_iter().zip(results) {
+                let item = &mut items[idx];
+                let segment_size = min(N_FRAMES, item.content_frames - item.seek);
+                if task.is_silent(&decoded) {
+                    item.seek += segment_size;
+                    continue;
+                }
+                let time_offset = (item.seek * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+                let segment_duration = (segment_size * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+                let (mut segments, advance) = DecodingTask::build_segments(
+                    decoded.tokens.clone(),
+                    time_offset,
+                    segment_size,
+                    segment_duration,
+                    input_stride,
+                    item.tokenizer.specials(),
+                );
+                decoded.annotate(&mut segments, &item.tokenizer);
+                let window = (time_offset, time_offset + segment_duration);
+                item.repetition
+                    .apply(&decoded, &mut segments, window, &item.tokenizer);
+                item.segments.extend(segments);
+                item.seek += advance;
+            }
+        }
+    }
+
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            let mut t = TranscriptionResult::new(runtime.elapsed(), item.segments, None);
+            t.language = item.language.code(&item.tokenizer.specials());
+            t.language_probability = item.language_probability;
+            t.interventions = item.repetition.interventions;
+            t.generate_formatted(&item.tokenizer);
+            t
+        })
+        .collect())
+}
+
 #[cfg(target_arch = "wasm32")]
 pub async fn transcribe(
     model: &mut Whisper,
@@ -95,87 +621,398 @@ pub async fn transcribe(
     callback: Option<impl Fn(StreamedSegment)>,
 ) -> anyhow::Result<TranscriptionResult> {
     let runtime = Instant::now();
-    let mel = model.specgen.generate(audio)?.to(&model.device).await?;
+    let duration = audio.len() as f64 / SAMPLE_RATE as f64;
+    let (audio, timeline) = select_audio(audio, &decode_options);
+    if timeline.is_some() && audio.is_empty() {
+        log::info!("No audio to transcribe");
+        if let Some(cb) = callback {
+            cb(StreamedSegment::new(
+                duration,
+                duration,
+                String::new(),
+                true,
+            ));
+        }
+        return Ok(TranscriptionResult::new(
+            runtime.elapsed(),
+            vec![],
+            Some(String::new()),
+        ));
+    }
+    //segments streamed while decoding are timed against the speech only audio
+    let callback_timeline = timeline.clone();
+    let callback = callback.map(|cb| {
+        move |mut segment: StreamedSegment| {
+            if let Some(timeline) = &callback_timeline {
+                segment.start = timeline.to_original(segment.start, false);
+                segment.stop = timeline.to_original(segment.stop, true);
+            }
+            cb(segment)
+        }
+    });
+    let mel = model.specgen.generate_on(audio, &model.device)?;
     let content_frames = mel.shape()[mel.rank() - 1] - N_FRAMES;
+    let n_mels = model.n_mels();
 
+    let mut language_probability = None;
     if decode_options.language.is_none() {
         if !model.is_multilingual() {
             log::warn!("No language specified, using English");
             decode_options.language = Some(Language::String("en".to_string()));
         } else {
             log::warn!("No language specified, using language detection");
-            let mel = mel.slice(&[0..1, 0..80, 0..3000])?;
-            decode_options.language = Some(model.detect_language(mel).await?);
+            let mel = mel.slice(&[0..1, 0..n_mels, 0..N_FRAMES])?;
+            let allowed = decode_options.allowed_languages.as_deref();
+            let detection = model.detect_language_probs(mel, allowed).await?;
+            let best = detection.best();
+            log::info!(
+                "Detected language {} ({:.2})",
+                best.language,
+                best.probability
+            );
+            language_probability = Some(best.probability);
+            decode_options.language = Some(Language::Token(best.token));
         }
     }
 
     let language = decode_options.language.as_ref().unwrap();
     let task = decode_options.task;
-    let tokenizer = WhisperTokenizer::load(None, language.clone(), task.clone()).await;
+    let tokenizer = WhisperTokenizer::load(
+        model.tokenizer.clone(),
+        model.n_vocab(),
+        language.clone(),
+        task,
+    )
+    .await?;
 
     let mut seek = 0;
     let input_stride = N_FRAMES / N_AUDIO_CTX;
-    let mut all_tokens = Vec::with_capacity(512);
+    let mut history = PromptHistory::new(decode_options.prompt.as_ref(), &tokenizer)?;
+    let mut repetition = RepetitionFilter::new(&decode_options);
     let mut all_segments = Vec::with_capacity(512);
-    let prompt_since_reset = 0;
 
     let mut pass_idx = 0;
     while seek < content_frames {
         model.device.try_gpu()?.begin_pass(pass_idx);
-        println!("seek: {}", seek);
         let mut decode_options = decode_options.clone();
         let time_offset = (seek * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
         decode_options.time_offset = Some(time_offset);
-        let mel_segment = mel.slice(&[0..1, 0..80, seek..(seek + N_FRAMES)])?;
-        log::info!(
-            "processing segment - from: {}, to: {}",
-            seek,
-            seek + N_FRAMES
-        );
+        if seek > 0 {
+            // the prefix only constrains how the transcript starts
+            decode_options.prefix = None;
+        }
+        let end = window_end(timeline.as_ref(), seek, content_frames);
+        let mel_segment = mel_window(&mel, seek, end, content_frames)?;
+        log::info!("processing segment - from: {}, to: {}", seek, end);
 
-        let segment_size = min(N_FRAMES, content_frames - seek);
-        let segment_duration = segment_size * HOP_LENGTH / SAMPLE_RATE;
+        let segment_size = end - seek;
+        let segment_duration = (segment_size * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
 
-        if !all_tokens.is_empty() {
-            decode_options.prompt = Some(Prompt::Tokens(all_tokens[prompt_since_reset..].to_vec()));
-        }
+        decode_options.prompt = history.prompt();
 
         let hs = model.encoder.forward(&mel_segment)?.resolve()?;
 
-        let task = DecodingTask::new(decode_options, &tokenizer);
-        let decoded = task
-            .run(&mut model.decoder, hs, &tokenizer, &callback)
-            .await?;
+        let (task, decoded) = DecodingTask::run_with_fallback(
+            decode_options,
+            &mut model.decoder,
+            hs,
+            &tokenizer,
+            &callback,
+        )
+        .await?;
+        if task.is_silent(&decoded) {
+            log::info!(
+                "skipping silent segment - no speech probability: {}",
+                decoded.no_speech_prob
+            );
+            seek += segment_size;
+            pass_idx += 1;
+            continue;
+        }
 
-        let (segments, advance) = DecodingTask::build_segments(
-            decoded,
+        let (mut segments, advance) = DecodingTask::build_segments(
+            decoded.tokens.clone(),
             time_offset,
             segment_size,
             segment_duration,
             input_stride,
+            tokenizer.specials(),
         );
-        let all_segment_tokens = segments
-            .iter()
-            .flat_map(|s| s.tokens.iter().copied())
-            .map(|x| x as i32)
-            .collect::<Vec<_>>();
-        all_tokens.extend(all_segment_tokens);
+        decoded.annotate(&mut segments, &tokenizer);
+        let mut window = (time_offset, time_offset + segment_duration);
+        if let Some(timeline) = &timeline {
+            timeline.restore(&mut segments);
+            window = (
+                timeline.to_original(window.0, false),
+                timeline.to_original(window.1, true),
+            );
+        }
+        repetition.apply(&decoded, &mut segments, window, &tokenizer);
+        if !task.streams_live(decoded.no_speech_prob) {
+            if let Some(ref cb) = callback {
+                for segment in &segments {
+                    cb(StreamedSegment::from_segment(&tokenizer, segment, false));
+                }
+            }
+        }
+        history.extend(
+            segments
+                .iter()
+                .flat_map(|s| s.tokens.iter().map(|t| *t as i32)),
+        );
+        if task.resets_prompt(&decoded) {
+            history.reset();
+        }
         all_segments.extend(segments);
-        model.decoder.reset();
         seek += advance;
         pass_idx += 1;
     }
 
     if let Some(cb) = callback {
-        cb(StreamedSegment::from_tokens(
-            &tokenizer,
-            &[WhisperTokenizer::EOT],
-            content_frames as f64 / 100.,
-            true,
-        ));
+        let end = (content_frames * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+        let end = timeline.map_or(end, |t| t.to_original(end, true));
+        cb(StreamedSegment::new(end, end, String::new(), true));
     }
 
     let mut t = TranscriptionResult::new(runtime.elapsed(), all_segments, None);
+    t.language = language.code(&tokenizer.specials());
+    t.language_probability = language_probability;
+    t.interventions = repetition.interventions;
     t.generate_formatted(&tokenizer);
     Ok(t)
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::whisper::test_util::byte_tokenizer;
+    use crate::DecodingOptionsBuilder;
+
+    fn tokens(prompt: Option<Prompt>) -> Vec<i32> {
+        match prompt {
+            Some(Prompt::Tokens(tokens)) => tokens,
+            Some(Prompt::Text(_)) => panic!("prompts are passed as tokens"),
+            None => vec![],
+        }
+    }
+
+    #[test]
+    fn initial_prompt_survives_reset() {
+        let mut history = PromptHistory {
+            initial: vec![1, 2],
+            ..Default::default()
+        };
+        assert_eq!(tokens(history.prompt()), vec![1, 2]);
+        history.extend([3, 4]);
+        assert_eq!(tokens(history.prompt()), vec![1, 2, 3, 4]);
+        history.reset();
+        assert_eq!(tokens(history.prompt()), vec![1, 2]);
+        history.extend([5]);
+        assert_eq!(tokens(history.prompt()), vec![1, 2, 5]);
+
+        assert!(PromptHistory::default().prompt().is_none());
+    }
+
+    #[test]
+    fn long_history_is_truncated_from_the_front() {
+        let mut history = PromptHistory {
+            initial: vec![-1; 3],
+            ..Default::default()
+        };
+        history.extend(0..500);
+        let prompt = tokens(history.prompt());
+        assert_eq!(prompt.len(), MAX_PROMPT_LENGTH);
+        assert_eq!(prompt[..3], [-1, -1, -1]);
+        assert_eq!(*prompt.last().unwrap(), 499);
+        assert_eq!(prompt[3], 500 - (MAX_PROMPT_LENGTH as i32 - 3));
+    }
+
+    #[test]
+    fn long_initial_prompt_keeps_its_end() {
+        let initial = Prompt::Tokens((0..300).collect());
+        let mut history = PromptHistory::new(Some(&initial), &byte_tokenizer()).unwrap();
+        history.extend([-1]);
+        let prompt = tokens(history.prompt());
+        assert_eq!(prompt.len(), MAX_PROMPT_LENGTH);
+        assert_eq!(prompt[0], 300 - MAX_PROMPT_LENGTH as i32);
+        assert_eq!(*prompt.last().unwrap(), 299);
+    }
+
+    #[test]
+    fn prompt_resets() {
+        let options = DecodingOptionsBuilder::new().build();
+        assert!(!options.resets_prompt(0.0));
+        assert!(!options.resets_prompt(0.5));
+        assert!(options.resets_prompt(0.6));
+
+        let options = DecodingOptionsBuilder::new()
+            .prompt_reset_on_temperature(1.0)
+            .build();
+        assert!(!options.resets_prompt(0.6));
+
+        let options = DecodingOptionsBuilder::new()
+            .condition_on_previous_text(false)
+            .build();
+        assert!(options.resets_prompt(0.0));
+    }
+
+    #[test]
+    fn fallback_temperatures() {
+        let temperatures = |options: DecodingOptions| {
+            std::iter::successors(Some(options), DecodingOptions::fallback)
+                .map(|o| (o.temperature * 10.).round() / 10.)
+                .collect::<Vec<_>>()
+        };
+        let options = DecodingOptionsBuilder::new().build();
+        assert_eq!(temperatures(options), [0.0, 0.2, 0.4, 0.6, 0.8, 1.0]);
+
+        let options = DecodingOptionsBuilder::new()
+            .temperature(0.5)
+            .temperature_increment_on_fallback(0.25)
+            .build();
+        assert_eq!(temperatures(options), [0.5, 0.8, 1.0]);
+
+        let options = DecodingOptionsBuilder::new()
+            .temperature_increment_on_fallback(0.)
+            .build();
+        assert_eq!(temperatures(options), [0.0]);
+    }
+
+    #[test]
+    fn fallback_on_repetitive_or_unlikely_text() {
+        let options = DecodingOptionsBuilder::new().build();
+        let result = |compression_ratio, avg_logprob, no_speech_prob| DecodingResult {
+            compression_ratio,
+            avg_logprob,
+            no_speech_prob,
+            ..decoded(vec![])
+        };
+        assert!(!options.needs_fallback(&result(1.5, -0.5, 0.1)));
+        assert!(options.needs_fallback(&result(3.0, -0.5, 0.1)));
+        assert!(options.needs_fallback(&result(1.5, -1.5, 0.1)));
+        // silent windows are skipped, not decoded again
+        assert!(!options.needs_fallback(&result(1.5, -1.5, 0.9)));
+
+        let options = DecodingOptionsBuilder::new()
+            .compression_ratio_threshold(4.0)
+            .build();
+        assert!(!options.needs_fallback(&result(3.0, -0.5, 0.1)));
+    }
+
+    #[test]
+    fn windows_end_at_clip_boundaries() {
+        let audio = vec![0.0; 12 * SAMPLE_RATE];
+        let options = DecodingOptionsBuilder::new()
+            .clip_timestamps(vec![0.0, 1.0, 10.0, 11.0])
+            .build();
+        let (audio, timeline) = select_audio(audio, &options);
+        let timeline = timeline.unwrap();
+        let content_frames = audio.len() / HOP_LENGTH;
+        assert_eq!(content_frames, 200);
+
+        assert_eq!(window_end(Some(&timeline), 0, content_frames), 100);
+        assert_eq!(window_end(Some(&timeline), 40, content_frames), 100);
+        assert_eq!(window_end(Some(&timeline), 100, content_frames), 200);
+        assert_eq!(window_end(None, 0, 5000), N_FRAMES);
+    }
+
+    #[test]
+    fn silence_requires_low_confidence() {
+        let options = DecodingOptionsBuilder::new().build();
+        assert!(!options.is_silent(0.5, -2.0));
+        assert!(options.is_silent(0.7, -2.0));
+        // confidently decoded text overrides the no speech probability
+        assert!(!options.is_silent(0.7, -0.5));
+
+        let options = DecodingOptionsBuilder::new().logprob_threshold(0.).build();
+        assert!(options.is_silent(0.7, -0.5));
+    }
+
+    /// Byte level tokenizer, each ASCII character is its own token.
+    fn segment(start: f64, text: &str) -> Segment {
+        Segment::new(
+            start,
+            start + 1.,
+            text.bytes().map(u32::from).collect(),
+            false,
+        )
+    }
+
+    #[test]
+    fn confidence_requires_logprobs() {
+        let tokenizer = byte_tokenizer();
+        let mut segment = segment(0., " Hello");
+        let streamed = StreamedSegment::from_segment(&tokenizer, &segment, false);
+        assert_eq!(streamed.confidence, None);
+
+        segment.token_logprobs = vec![-0.5; 6];
+        segment.annotate(&tokenizer);
+        let streamed = StreamedSegment::from_segment(&tokenizer, &segment, false);
+        assert_eq!(streamed.confidence, Some((-0.5f32).exp()));
+    }
+
+    fn decoded(dropped_repetition: Vec<i32>) -> DecodingResult {
+        DecodingResult {
+            tokens: vec![],
+            logprobs: vec![],
+            avg_logprob: 0.,
+            compression_ratio: 1.,
+            no_speech_prob: 0.,
+            temperature: 0.,
+            dropped_repetition,
+        }
+    }
+
+    #[test]
+    fn repetition_is_filtered_and_reported() {
+        let tokenizer = byte_tokenizer();
+        let options = DecodingOptionsBuilder::new().build();
+        let mut filter = RepetitionFilter::new(&options);
+
+        let mut first = vec![segment(0., " Thank you."), segment(1., " Bye.")];
+        filter.apply(&decoded(vec![]), &mut first, (0., 30.), &tokenizer);
+        assert_eq!(first.len(), 2);
+        assert!(filter.interventions.is_empty());
+
+        let looped = " you".bytes().map(i32::from).collect();
+        let mut second = vec![segment(30., " thank you. "), segment(31., " Hello")];
+        filter.apply(&decoded(looped), &mut second, (30., 60.), &tokenizer);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].text(&tokenizer), " Hello");
+        assert_eq!(
+            filter.interventions,
+            [
+                Intervention {
+                    kind: InterventionKind::LoopTruncated,
+                    start: 30.,
+                    stop: 60.,
+                    text: " you".to_string(),
+                },
+                Intervention {
+                    kind: InterventionKind::RepeatedSegmentDropped,
+                    start: 30.,
+                    stop: 31.,
+                    text: " thank you. ".to_string(),
+                },
+            ]
+        );
+
+        let options = DecodingOptionsBuilder::new()
+            .filter_repeated_segments(false)
+            .build();
+        let mut filter = RepetitionFilter::new(&options);
+        for window in [0., 30.] {
+            let mut segments = vec![
+                segment(window, " Thank you."),
+                segment(window + 1., " Bye."),
+            ];
+            filter.apply(
+                &decoded(vec![]),
+                &mut segments,
+                (window, window + 30.),
+                &tokenizer,
+            );
+            assert_eq!(segments.len(), 2);
+        }
+    }
+}
@@ -11,6 +11,32 @@ pub struct TranscriptionResult {
     pub processing_time: Duration,
     pub segments: Vec<Segment>,
     pub formatted: Option<String>,
+    /// Language code of the transcript, either provided or detected.
+    #[new(default)]
+    pub language: Option<String>,
+    /// Probability of `language`, only present when it was detected.
+    #[new(default)]
+    pub language_probability: Option<f32>,
+    /// Changes made to the decoded text to suppress repetition, in order.
+    #[new(default)]
+    pub interventions: Vec<Intervention>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InterventionKind {
+    /// Decoding of a window was stopped as its text started looping, the repeats were dropped.
+    LoopTruncated,
+    /// A segment repeating the text of the previous window was dropped.
+    RepeatedSegmentDropped,
+}
+
+/// A change made to the decoded text of `start..stop`, with the `text` that was removed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Intervention {
+    pub kind: InterventionKind,
+    pub start: f64,
+    pub stop: f64,
+    pub text: String,
 }
 
 impl TranscriptionResult {
@@ -24,13 +50,7 @@ impl TranscriptionResult {
             .segments
             .iter()
             .fold(String::new(), |transcript, fragment| {
-                let fragment_tokens = fragment
-                    .tokens
-                    .iter()
-                    .copied()
-                    .filter(|x| *x < WhisperTokenizer::EOT as _)
-                    .collect::<Vec<u32>>();
-                let fragment_text = tokenizer.decode(fragment_tokens.as_slice(), true).unwrap();
+                let fragment_text = fragment.text(tokenizer);
                 transcript
                     + format!(
                         "[{} --> {}]  {}\n",
@@ -43,7 +63,7 @@ impl TranscriptionResult {
         oai.to_string()
     }
 
-    fn format_timestamp(num: f64, always_include_hours: bool, decimal_marker: &str) -> String {
+    pub fn format_timestamp(num: f64, always_include_hours: bool, decimal_marker: &str) -> String {
         assert!(num >= 0.0, "non-negative timestamp expected");
         let milliseconds: i64 = (num * 1000.0) as i64;
 
@@ -71,15 +91,72 @@ pub struct Segment {
     pub stop: f64,
     pub tokens: Vec<u32>,
     pub last: bool,
+    /// Probability that the window this segment was decoded from contains no speech.
+    #[new(default)]
+    pub no_speech_prob: f32,
+    /// Word level timings, empty unless they have been computed for this segment.
+    #[new(default)]
+    pub words: Vec<WordTiming>,
+    /// Log-probability of each token in `tokens`.
+    #[new(default)]
+    pub token_logprobs: Vec<f32>,
+    /// Mean of `token_logprobs`.
+    #[new(default)]
+    pub avg_logprob: f32,
+    /// gzip compression ratio of the segment text.
+    #[new(default)]
+    pub compression_ratio: f32,
+}
+
+/// Ratio of the UTF-8 length of `text` to its zlib compressed length, as in OpenAI.
+///
+/// Repetitive (hallucinated) text compresses well, so has a high ratio.
+pub fn compression_ratio(text: &str) -> f32 {
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    let bytes = text.as_bytes();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).unwrap();
+    let compressed = encoder.finish().unwrap();
+    bytes.len() as f32 / compressed.len() as f32
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, derive_new::new)]
+pub struct WordTiming {
+    pub word: String,
+    pub start: f64,
+    pub stop: f64,
+    pub probability: f32,
 }
 
 impl Segment {
-    pub fn from_tokens(sliced_tokens: &[i32], offset: f64, last: bool) -> Self {
+    /// Decoded text of the segment, with all special & timestamp tokens removed.
+    pub fn text(&self, tokenizer: &WhisperTokenizer) -> String {
+        let text_tokens = self
+            .tokens
+            .iter()
+            .copied()
+            .filter(|x| *x < tokenizer.specials().eot as _)
+            .collect::<Vec<u32>>();
+        tokenizer.decode(text_tokens.as_slice(), true).unwrap()
+    }
+
+    /// Derives `avg_logprob` & `compression_ratio` once `token_logprobs` is populated.
+    pub(crate) fn annotate(&mut self, tokenizer: &WhisperTokenizer) {
+        if !self.token_logprobs.is_empty() {
+            self.avg_logprob =
+                self.token_logprobs.iter().sum::<f32>() / self.token_logprobs.len() as f32;
+        }
+        self.compression_ratio = compression_ratio(&self.text(tokenizer));
+    }
+
+    pub fn from_tokens(sliced_tokens: &[i32], offset: f64, last: bool, ts_begin: i32) -> Self {
         let input_stride = N_FRAMES / N_AUDIO_CTX; // mel frames per output token: 2
         let time_precision: f64 = input_stride as f64 * (HOP_LENGTH as f64) / (SAMPLE_RATE as f64); // time per output token: 0.02 (seconds)
 
-        let start_timestamp_pos = sliced_tokens[0] - WhisperTokenizer::TS_BEGIN;
-        let end_timestamp_pos = sliced_tokens[sliced_tokens.len() - 1] - WhisperTokenizer::TS_BEGIN;
+        let start_timestamp_pos = sliced_tokens[0] - ts_begin;
+        let end_timestamp_pos = sliced_tokens[sliced_tokens.len() - 1] - ts_begin;
 
         let segment_tokens = sliced_tokens.iter().map(|x| *x as u32).collect::<Vec<_>>();
 
@@ -102,6 +179,12 @@ pub struct StreamedSegment {
     pub stop: f64,
     pub text: String,
     pub last: bool,
+    /// Tentative segments may still be revised by a [crate::StreamingTranscriber].
+    #[new(value = "true")]
+    pub stable: bool,
+    /// Geometric mean of the token probabilities, in [0, 1], if they were recorded.
+    #[new(default)]
+    pub confidence: Option<f32>,
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
@@ -122,19 +205,44 @@ impl StreamedSegment {
         self.last
     }
 
+    pub fn stable(&self) -> bool {
+        self.stable
+    }
+
+    pub fn confidence(&self) -> Option<f32> {
+        self.confidence
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
     pub(crate) fn from_tokens(
         tokenizer: &WhisperTokenizer,
         sliced_tokens: &[i32],
+        logprobs: &[f32],
         offset: f64,
         last: bool,
     ) -> Self {
-        let segment = Segment::from_tokens(sliced_tokens, offset, last);
+        let mut segment =
+            Segment::from_tokens(sliced_tokens, offset, last, tokenizer.specials().ts_begin);
+        segment.token_logprobs = logprobs.to_vec();
+        segment.annotate(tokenizer);
+        Self::from_segment(tokenizer, &segment, last)
+    }
+
+    pub(crate) fn from_segment(
+        tokenizer: &WhisperTokenizer,
+        segment: &Segment,
+        last: bool,
+    ) -> Self {
         let segment_tokens = segment
             .tokens
-            .into_iter()
-            .filter(|t| *t < WhisperTokenizer::TS_BEGIN as _)
+            .iter()
+            .copied()
+            .filter(|t| *t < tokenizer.specials().ts_begin as _)
             .collect::<Vec<_>>();
         let segment_text = tokenizer.decode(segment_tokens.as_slice(), true).unwrap();
-        StreamedSegment::new(segment.start, segment.stop, segment_text, last)
+        let mut streamed = StreamedSegment::new(segment.start, segment.stop, segment_text, last);
+        streamed.confidence =
+            (!segment.token_logprobs.is_empty()).then(|| segment.avg_logprob.exp());
+        streamed
     }
 }
@@ -0,0 +1,438 @@
+use crate::{Segment, SpectrogramConfig, SpectrogramGenerator, HOP_LENGTH, N_FFT, SAMPLE_RATE};
+use ndarray::Array2;
+
+/// Mel bands used for detection, far fewer than the model needs.
+const VAD_MELS: usize = 40;
+/// Fraction of frames assumed to be background, used to estimate the noise floor.
+const NOISE_PERCENTILE: f32 = 0.1;
+/// Frames either side of a frame over which the spectral flux is averaged (0.25s).
+const FLUX_CONTEXT: usize = 25;
+
+/// Tunes the [VoiceActivityDetector].
+#[cfg_attr(target_arch = "wasm32", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct VadOptions {
+    /// dB above the estimated noise floor a frame must reach to be considered speech.
+    pub energy_threshold: f32,
+    /// Mean energy weighted spectral flux, in log10 units per frame, required around a frame.
+    /// Stationary sounds such as hum, tones or held notes fall below it.
+    pub flux_threshold: f32,
+    /// Regions shorter than this many seconds are discarded.
+    pub min_speech_duration: f64,
+    /// Pauses shorter than this many seconds do not split a region.
+    pub min_silence_duration: f64,
+    /// Seconds of audio kept either side of each region.
+    pub speech_pad: f64,
+}
+
+impl Default for VadOptions {
+    fn default() -> Self {
+        Self {
+            energy_threshold: 12.0,
+            flux_threshold: 0.05,
+            min_speech_duration: 0.25,
+            min_silence_duration: 1.0,
+            speech_pad: 0.2,
+        }
+    }
+}
+
+/// A span of speech, in samples of the analysed audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeechRegion {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl SpeechRegion {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    pub fn start_secs(&self) -> f64 {
+        self.start as f64 / SAMPLE_RATE as f64
+    }
+
+    pub fn end_secs(&self) -> f64 {
+        self.end as f64 / SAMPLE_RATE as f64
+    }
+
+    /// The regions of `n_samples` of audio covered by alternating start & end times in
+    /// seconds, sorted & merged. A missing final end runs to the end of the audio.
+    pub fn from_clip_timestamps(timestamps: &[f64], n_samples: usize) -> Vec<SpeechRegion> {
+        let sample = |secs: f64| ((secs.max(0.) * SAMPLE_RATE as f64) as usize).min(n_samples);
+        let mut clips = timestamps
+            .chunks(2)
+            .map(|clip| SpeechRegion {
+                start: sample(clip[0]),
+                end: clip.get(1).map_or(n_samples, |end| sample(*end)),
+            })
+            .filter(|clip| !clip.is_empty())
+            .collect::<Vec<_>>();
+        clips.sort_by_key(|clip| clip.start);
+
+        let mut regions: Vec<SpeechRegion> = vec![];
+        for clip in clips {
+            match regions.last_mut() {
+                Some(last) if clip.start <= last.end => last.end = last.end.max(clip.end),
+                _ => regions.push(clip),
+            }
+        }
+        regions
+    }
+}
+
+/// Energy & spectral flux based voice activity detection.
+///
+/// A frame of the log mel spectrogram is considered speech when it is loud enough relative to
+/// the noise floor of the recording, and the spectrum around it is changing, as it does
+/// from syllable to syllable. Frames are then merged into regions, bridging short pauses.
+#[derive(Debug)]
+pub struct VoiceActivityDetector {
+    options: VadOptions,
+    specgen: SpectrogramGenerator,
+}
+
+impl Default for VoiceActivityDetector {
+    fn default() -> Self {
+        Self::new(VadOptions::default())
+    }
+}
+
+impl VoiceActivityDetector {
+    pub fn new(options: VadOptions) -> Self {
+        let specgen = SpectrogramGenerator::from_config(SpectrogramConfig {
+            sample_rate: SAMPLE_RATE,
+            n_fft: N_FFT,
+            hop_length: HOP_LENGTH,
+            n_mels: VAD_MELS,
+            padding: 0,
+        });
+        Self { options, specgen }
+    }
+
+    pub fn options(&self) -> &VadOptions {
+        &self.options
+    }
+
+    /// Speech regions of 16kHz mono `audio`, in order & non-overlapping.
+    pub fn detect(&self, audio: &[f32]) -> Vec<SpeechRegion> {
+        let Ok(padded) = self.specgen.pad_audio(audio.to_vec()) else {
+            return vec![];
+        };
+        let log_mel = self.specgen.log_mel(&padded);
+        let active = self.active_frames(&log_mel);
+        self.regions(&active, audio.len())
+    }
+
+    /// Classifies each frame of a [n_mels, n_frames] log10 mel spectrogram.
+    fn active_frames(&self, log_mel: &Array2<f32>) -> Vec<bool> {
+        let n_frames = log_mel.ncols();
+        let energy = log_mel
+            .columns()
+            .into_iter()
+            .map(|frame| 10.0 * frame.mapv(|x| 10f32.powf(x)).sum().log10())
+            .collect::<Vec<_>>();
+
+        let mut sorted = energy.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let floor = sorted[((n_frames - 1) as f32 * NOISE_PERCENTILE) as usize];
+
+        //Positive flux, weighted by the share of the frame's power in each band
+        let columns = log_mel.columns().into_iter();
+        let flux = std::iter::once(0f32)
+            .chain(
+                columns
+                    .clone()
+                    .zip(columns.skip(1))
+                    .map(|(previous, current)| {
+                        let power = current.mapv(|x| 10f32.powf(x));
+                        let total = power.sum();
+                        current
+                            .iter()
+                            .zip(previous.iter())
+                            .zip(power.iter())
+                            .map(|((c, p), w)| (c - p).max(0.0) * w / total)
+                            .sum()
+                    }),
+            )
+            .collect::<Vec<f32>>();
+
+        (0..n_frames)
+            .map(|t| {
+                let window = t.saturating_sub(FLUX_CONTEXT)..(t + FLUX_CONTEXT + 1).min(n_frames);
+                let mean_flux = flux[window.clone()].iter().sum::<f32>() / window.len() as f32;
+                energy[t] >= floor + self.options.energy_threshold
+                    && mean_flux >= self.options.flux_threshold
+            })
+            .collect()
+    }
+
+    fn regions(&self, active: &[bool], n_samples: usize) -> Vec<SpeechRegion> {
+        let frames = |secs: f64| (secs * SAMPLE_RATE as f64 / HOP_LENGTH as f64).round() as usize;
+        let min_speech = frames(self.options.min_speech_duration);
+        let min_silence = frames(self.options.min_silence_duration);
+        let pad = frames(self.options.speech_pad);
+        let n_frames = active.len();
+
+        let mut runs: Vec<(usize, usize)> = vec![];
+        let mut t = 0;
+        while t < n_frames {
+            if !active[t] {
+                t += 1;
+                continue;
+            }
+            let start = t;
+            while t < n_frames && active[t] {
+                t += 1;
+            }
+            match runs.last_mut() {
+                Some(last) if start - last.1 < min_silence => last.1 = t,
+                _ => runs.push((start, t)),
+            }
+        }
+
+        let mut regions: Vec<SpeechRegion> = vec![];
+        for (start, end) in runs.into_iter().filter(|(s, e)| e - s >= min_speech) {
+            let start = start.saturating_sub(pad) * HOP_LENGTH;
+            let end = match end + pad >= n_frames {
+                true => n_samples,
+                false => ((end + pad) * HOP_LENGTH).min(n_samples),
+            };
+            match regions.last_mut() {
+                Some(last) if start <= last.end => last.end = end,
+                _ => regions.push(SpeechRegion { start, end }),
+            }
+        }
+        regions
+    }
+}
+
+/// Maps times in audio made by concatenating [SpeechRegion]s back onto the recording
+/// the regions were detected in.
+#[derive(Debug, Clone)]
+pub struct SpeechTimeline {
+    /// Offset of each region in the concatenated audio.
+    chunks: Vec<(usize, SpeechRegion)>,
+    /// Offset in the concatenated audio where each clip ends.
+    clip_ends: Vec<usize>,
+}
+
+impl SpeechTimeline {
+    /// Concatenates the `regions` of `audio`, returning the speech only audio & its timeline.
+    pub fn gather(audio: &[f32], regions: &[SpeechRegion]) -> (Vec<f32>, Self) {
+        Self::gather_clips(audio, &[regions.to_vec()])
+    }
+
+    /// Concatenates the regions of each clip of `audio`, remembering where each clip ends
+    /// so that windows of the concatenated audio never span two clips.
+    pub fn gather_clips(audio: &[f32], clips: &[Vec<SpeechRegion>]) -> (Vec<f32>, Self) {
+        let regions = clips.iter().flatten();
+        let mut speech = Vec::with_capacity(regions.clone().map(SpeechRegion::len).sum());
+        let mut chunks = Vec::with_capacity(regions.clone().count());
+        let mut clip_ends = Vec::with_capacity(clips.len());
+        for clip in clips {
+            for region in clip {
+                chunks.push((speech.len(), *region));
+                speech.extend_from_slice(&audio[region.start..region.end]);
+            }
+            clip_ends.push(speech.len());
+        }
+        (speech, Self { chunks, clip_ends })
+    }
+
+    /// The end of the clip containing `sample` of the concatenated audio.
+    pub fn clip_end(&self, sample: usize) -> usize {
+        let total = self.chunks.last().map_or(0, |(offset, r)| offset + r.len());
+        self.clip_ends
+            .iter()
+            .copied()
+            .find(|end| *end > sample)
+            .unwrap_or(total)
+    }
+
+    /// Converts a time in seconds within the concatenated audio to the original recording.
+    ///
+    /// A time on the boundary between two regions is ambiguous, `is_end` resolves it to the
+    /// end of the earlier region rather than the start of the later one.
+    pub fn to_original(&self, time: f64, is_end: bool) -> f64 {
+        let sample = (time * SAMPLE_RATE as f64).round().max(0.0) as usize;
+        let idx = self
+            .chunks
+            .iter()
+            .rposition(|(offset, _)| match is_end {
+                true => *offset < sample,
+                false => *offset <= sample,
+            })
+            .unwrap_or(0);
+        let Some((offset, region)) = self.chunks.get(idx) else {
+            return time;
+        };
+        let original = (region.start + sample.saturating_sub(*offset)).min(region.end);
+        original as f64 / SAMPLE_RATE as f64
+    }
+
+    /// Moves the segment & word timestamps back onto the original recording.
+    pub fn restore(&self, segments: &mut [Segment]) {
+        for segment in segments {
+            segment.start = self.to_original(segment.start, false);
+            segment.stop = self.to_original(segment.stop, true);
+            for word in segment.words.iter_mut() {
+                word.start = self.to_original(word.start, false);
+                word.stop = self.to_original(word.stop, true);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// Deterministic white noise in [-1, 1].
+    fn noise(n: usize, seed: &mut u32) -> Vec<f32> {
+        (0..n)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (*seed >> 8) as f32 / (1 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    /// Noise with a 4Hz amplitude envelope, crudely mimicking syllables.
+    fn babble(secs: f32, seed: &mut u32) -> Vec<f32> {
+        let n = (secs * SAMPLE_RATE as f32) as usize;
+        noise(n, seed)
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                0.5 * x * (0.5 - 0.5 * (2.0 * PI * 4.0 * t).cos())
+            })
+            .collect()
+    }
+
+    fn silence(secs: f32) -> Vec<f32> {
+        vec![0.0; (secs * SAMPLE_RATE as f32) as usize]
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= 0.3,
+            "expected {expected}s, got {actual}s"
+        );
+    }
+
+    #[test]
+    fn detects_bursts_between_silence() {
+        let mut seed = 42;
+        let audio = [
+            silence(1.0),
+            babble(2.0, &mut seed),
+            silence(3.0),
+            babble(1.0, &mut seed),
+            silence(1.0),
+        ]
+        .concat();
+
+        let regions = VoiceActivityDetector::default().detect(&audio);
+        assert_eq!(regions.len(), 2, "{regions:?}");
+        assert_close(regions[0].start_secs(), 1.0);
+        assert_close(regions[0].end_secs(), 3.0);
+        assert_close(regions[1].start_secs(), 6.0);
+        assert_close(regions[1].end_secs(), 7.0);
+    }
+
+    #[test]
+    fn rejects_stationary_tone() {
+        let mut seed = 7;
+        let tone = (0..3 * SAMPLE_RATE)
+            .map(|i| 0.5 * (2.0 * PI * 440.0 * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect::<Vec<_>>();
+        let audio = [tone, silence(1.0), babble(2.0, &mut seed), silence(1.0)].concat();
+
+        let regions = VoiceActivityDetector::default().detect(&audio);
+        assert_eq!(regions.len(), 1, "{regions:?}");
+        assert_close(regions[0].start_secs(), 4.0);
+        assert_close(regions[0].end_secs(), 6.0);
+    }
+
+    #[test]
+    fn silence_has_no_speech() {
+        assert!(VoiceActivityDetector::default()
+            .detect(&silence(5.0))
+            .is_empty());
+        assert!(VoiceActivityDetector::default().detect(&[]).is_empty());
+    }
+
+    #[test]
+    fn timeline_restores_timestamps() {
+        let audio = (0..10 * SAMPLE_RATE).map(|i| i as f32).collect::<Vec<_>>();
+        let regions = [
+            SpeechRegion {
+                start: SAMPLE_RATE,
+                end: 3 * SAMPLE_RATE,
+            },
+            SpeechRegion {
+                start: 6 * SAMPLE_RATE,
+                end: 7 * SAMPLE_RATE,
+            },
+        ];
+        let (speech, timeline) = SpeechTimeline::gather(&audio, &regions);
+        assert_eq!(speech.len(), 3 * SAMPLE_RATE);
+        assert_eq!(speech[2 * SAMPLE_RATE], (6 * SAMPLE_RATE) as f32);
+
+        assert_eq!(timeline.to_original(0.0, false), 1.0);
+        assert_eq!(timeline.to_original(1.5, false), 2.5);
+        assert_eq!(timeline.to_original(2.0, true), 3.0);
+        assert_eq!(timeline.to_original(2.0, false), 6.0);
+        assert_eq!(timeline.to_original(2.5, true), 6.5);
+
+        let mut segments = vec![Segment::new(1.5, 2.5, vec![], false)];
+        timeline.restore(&mut segments);
+        assert_eq!((segments[0].start, segments[0].stop), (2.5, 6.5));
+    }
+
+    #[test]
+    fn distant_clips_end_separately() {
+        let audio = (0..12 * SAMPLE_RATE).map(|i| i as f32).collect::<Vec<_>>();
+        let clips = SpeechRegion::from_clip_timestamps(&[0.0, 1.0, 10.0, 11.0], audio.len());
+        let clips = clips.into_iter().map(|c| vec![c]).collect::<Vec<_>>();
+        let (speech, timeline) = SpeechTimeline::gather_clips(&audio, &clips);
+        assert_eq!(speech.len(), 2 * SAMPLE_RATE);
+        assert_eq!(speech[SAMPLE_RATE], (10 * SAMPLE_RATE) as f32);
+
+        assert_eq!(timeline.clip_end(0), SAMPLE_RATE);
+        assert_eq!(timeline.clip_end(SAMPLE_RATE - 1), SAMPLE_RATE);
+        assert_eq!(timeline.clip_end(SAMPLE_RATE), 2 * SAMPLE_RATE);
+        assert_eq!(timeline.clip_end(2 * SAMPLE_RATE), 2 * SAMPLE_RATE);
+
+        assert_eq!(timeline.to_original(0.5, false), 0.5);
+        assert_eq!(timeline.to_original(1.0, true), 1.0);
+        assert_eq!(timeline.to_original(1.0, false), 10.0);
+        assert_eq!(timeline.to_original(1.5, false), 10.5);
+    }
+
+    #[test]
+    fn clips_are_sorted_merged_and_clamped() {
+        let n_samples = 60 * SAMPLE_RATE;
+        let secs = |regions: Vec<SpeechRegion>| {
+            regions
+                .iter()
+                .map(|r| (r.start_secs(), r.end_secs()))
+                .collect::<Vec<_>>()
+        };
+        let clips = SpeechRegion::from_clip_timestamps(&[40., 50., 5., 10., 8., 20.], n_samples);
+        assert_eq!(secs(clips), [(5., 20.), (40., 50.)]);
+
+        let clips = SpeechRegion::from_clip_timestamps(&[-1., 2., 30., 10., 55.], n_samples);
+        assert_eq!(secs(clips), [(0., 2.), (55., 60.)]);
+
+        assert!(SpeechRegion::from_clip_timestamps(&[70., 80.], n_samples).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{BufRead, Seek, SeekFrom};
+use std::io::{BufRead, Seek};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use ratchet::{shape, Device, Tensor};
@@ -6,8 +6,8 @@ use ratchet_loader::{GGMLCompatible, GGMLFormat, GGMLModel, LoadError};
 use ratchet_nn::Module;
 
 use crate::{
-    DecodingTask, Language, LogitMutator, SelectLanguage, SpectrogramGenerator, WhisperDecoder,
-    WhisperEncoder, WhisperTokenizer,
+    DecodingTask, Language, LanguageDetection, SelectLanguage, SpecialTokens, SpectrogramConfig,
+    SpectrogramGenerator, TokenizerSource, WhisperDecoder, WhisperEncoder,
 };
 
 #[derive(Debug)]
@@ -15,7 +15,8 @@ pub struct WhisperGGMLHeader {
     pub format: GGMLFormat,
     pub hparams: HyperParameters,
     pub filters: MelFilters,
-    pub n_tokens: i32,
+    /// Byte-level BPE token table, see [TokenizerSource::Vocabulary].
+    pub tokens: Vec<Vec<u8>>,
 }
 
 #[derive(Debug, Clone)]
@@ -113,6 +114,8 @@ pub struct Whisper {
     pub decoder: WhisperDecoder,
     pub hparams: HyperParameters,
     pub device: Device,
+    /// Defaults to the token table embedded in the model file, if present.
+    pub tokenizer: TokenizerSource,
 }
 
 impl Whisper {
@@ -121,10 +124,36 @@ impl Whisper {
         reader: &mut R,
         device: Device,
     ) -> anyhow::Result<Self> {
+        let hparams = &disk_model.header.hparams;
+        SpecialTokens::new(hparams.n_vocab as usize)?;
+        for (prefix, n_layers) in [
+            ("encoder", hparams.n_audio_layer),
+            ("decoder", hparams.n_text_layer),
+        ] {
+            let extra = format!("{}.blocks.{}.attn_ln.weight", prefix, n_layers);
+            anyhow::ensure!(
+                !disk_model.tensors.contains_key(&extra),
+                "Model contains more {} blocks than the {} in its hyperparameters",
+                prefix,
+                n_layers
+            );
+        }
         let encoder = WhisperEncoder::load(disk_model, reader, &device)?;
         let decoder = WhisperDecoder::load(disk_model, reader, &device)?;
-        //TODO: remove clones
-        let generator = crate::SpectrogramGenerator::new(disk_model.header.filters.mels.clone());
+        let filters = &disk_model.header.filters;
+        let generator = if filters.mels.is_empty() {
+            let config = SpectrogramConfig::whisper(hparams.n_mels as _);
+            SpectrogramGenerator::from_config(config)
+        } else {
+            anyhow::ensure!(
+                hparams.n_mels == filters.n_mel,
+                "Model expects {} mel bins, but the filterbank has {}",
+                hparams.n_mels,
+                filters.n_mel
+            );
+            //TODO: remove clones
+            SpectrogramGenerator::new(filters.mels.clone())
+        };
         log::info!("Sucessfully loaded Whisper model");
         Ok(Self {
             specgen: generator,
@@ -132,6 +161,7 @@ impl Whisper {
             decoder,
             hparams: disk_model.header.hparams.clone(),
             device,
+            tokenizer: TokenizerSource::from_header(&disk_model.header.tokens),
         })
     }
 
@@ -154,15 +184,19 @@ impl GGMLCompatible for Whisper {
         let hparams = HyperParameters::read(reader)?;
         let filters = MelFilters::read(reader)?;
         let n_tokens = reader.read_i32::<LittleEndian>()?;
-        for _ in 0..n_tokens {
-            let token_len = reader.read_u32::<LittleEndian>()?;
-            reader.seek(SeekFrom::Current(token_len as i64))?;
-        }
+        let tokens = (0..n_tokens)
+            .map(|_| {
+                let token_len = reader.read_u32::<LittleEndian>()?;
+                let mut token = vec![0; token_len as usize];
+                reader.read_exact(&mut token)?;
+                Ok(token)
+            })
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
         Ok(Self::ModelHeader {
             format,
             hparams,
             filters,
-            n_tokens,
+            tokens,
         })
     }
 
@@ -173,9 +207,10 @@ impl GGMLCompatible for Whisper {
         header.format.write(writer)?;
         header.hparams.write(writer)?;
         header.filters.write(writer)?;
-        writer.write_i32::<LittleEndian>(header.n_tokens)?;
-        for _ in 0..header.n_tokens {
-            writer.write_u32::<LittleEndian>(0)?;
+        writer.write_i32::<LittleEndian>(header.tokens.len() as i32)?;
+        for token in &header.tokens {
+            writer.write_u32::<LittleEndian>(token.len() as u32)?;
+            writer.write_all(token)?;
         }
         Ok(())
     }
@@ -183,50 +218,94 @@ impl GGMLCompatible for Whisper {
 
 impl Whisper {
     pub fn is_multilingual(&self) -> bool {
-        self.hparams.n_vocab == 51865
+        self.specials().is_multilingual()
+    }
+
+    pub fn n_vocab(&self) -> usize {
+        self.hparams.n_vocab as usize
+    }
+
+    pub fn n_mels(&self) -> usize {
+        self.hparams.n_mels as usize
+    }
+
+    pub fn specials(&self) -> SpecialTokens {
+        SpecialTokens::new(self.n_vocab()).expect("vocabulary is checked on load")
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     pub fn detect_language(&mut self, mel: Tensor) -> anyhow::Result<Language> {
+        let detection = self.detect_language_probs(mel, None)?;
+        Ok(Language::Token(detection.best().token))
+    }
+
+    /// Probabilities of each language supported by the model, optionally restricted
+    /// to the `allowed` language codes.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn detect_language_probs(
+        &mut self,
+        mel: Tensor,
+        allowed: Option<&[String]>,
+    ) -> anyhow::Result<LanguageDetection> {
+        let selector = self.language_selector(allowed)?;
         let audio_ctx = self.encoder.forward(&mel)?.resolve()?;
-        let sot = Tensor::from_data([WhisperTokenizer::SOT], shape![1, 1], self.device.clone());
+        let specials = self.specials();
+        let sot = Tensor::from_data([specials.sot], shape![1, 1], self.device.clone());
 
+        self.decoder.set_batch_size(1);
         let logits = self.decoder.forward(&[audio_ctx, sot])?.resolve()?;
         self.decoder.reset();
 
         let cpu_logits = logits.to(&Device::CPU)?;
-        let logits = DecodingTask::slice_logits(cpu_logits);
-
-        let selector = SelectLanguage {};
-        let lang_t = selector.apply(logits, None).unwrap();
-        Ok(Language::Token(lang_t.item()))
+        let logits = DecodingTask::slice_logits(cpu_logits, specials.n_vocab);
+        Ok(selector.detect(logits))
     }
 
     #[cfg(target_arch = "wasm32")]
     pub async fn detect_language(&mut self, mel: Tensor) -> anyhow::Result<Language> {
+        let detection = self.detect_language_probs(mel, None).await?;
+        Ok(Language::Token(detection.best().token))
+    }
+
+    /// Probabilities of each language supported by the model, optionally restricted
+    /// to the `allowed` language codes.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn detect_language_probs(
+        &mut self,
+        mel: Tensor,
+        allowed: Option<&[String]>,
+    ) -> anyhow::Result<LanguageDetection> {
+        let selector = self.language_selector(allowed)?;
         let audio_ctx = self.encoder.forward(&mel)?.resolve()?;
-        let sot = Tensor::from_data(&[WhisperTokenizer::SOT], shape![1, 1], self.device.clone());
+        let specials = self.specials();
+        let sot = Tensor::from_data(&[specials.sot], shape![1, 1], self.device.clone());
 
+        self.decoder.set_batch_size(1);
         let logits = self.decoder.forward(&[audio_ctx, sot])?.resolve()?;
         self.decoder.reset();
 
         let cpu_logits = logits.to(&Device::CPU).await?;
-        let logits = DecodingTask::slice_logits(cpu_logits);
+        let logits = DecodingTask::slice_logits(cpu_logits, specials.n_vocab);
+        Ok(selector.detect(logits))
+    }
 
-        let selector = SelectLanguage {};
-        let lang_t = selector.apply(logits, None).unwrap();
-        Ok(Language::Token(lang_t.item()))
+    fn language_selector(&self, allowed: Option<&[String]>) -> anyhow::Result<SelectLanguage> {
+        let selector = SelectLanguage::new(self.specials());
+        match allowed {
+            Some(allowed) => selector.with_allowed(allowed),
+            None => Ok(selector),
+        }
     }
 }
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    use std::{
-        collections::{HashMap, HashSet},
-        path::PathBuf,
-    };
+    use std::collections::{HashMap, HashSet};
 
-    use crate::{transcribe, DecodingOptionsBuilder, Whisper};
+    use crate::{
+        load_audio, transcribe, transcribe_batch, transcribe_with_hooks, CancellationToken,
+        DecodeError, DecodingOptionsBuilder, TranscribeHooks, Whisper, SAMPLE_RATE,
+    };
     use hf_hub::api::sync::Api;
     use ratchet::{Device, DeviceRequest, Quantization};
     use ratchet_loader::{Converter, GGMLCompatible};
@@ -235,14 +314,6 @@ mod tests {
         let _ = env_logger::builder().is_test(true).try_init();
     }
 
-    fn load_sample(path: PathBuf) -> Vec<f32> {
-        let mut reader = hound::WavReader::open(path).unwrap();
-        reader
-            .samples::<i16>()
-            .map(|x| x.unwrap() as f32 / 32768.0)
-            .collect::<Vec<_>>()
-    }
-
     #[test]
     pub fn whisper_end_to_end() {
         log_init();
@@ -252,7 +323,7 @@ mod tests {
 
         let dataset = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
         let audio_path = dataset.get("mm0.wav").unwrap();
-        let samples = load_sample(audio_path);
+        let samples = load_audio(audio_path).unwrap();
 
         let options = DecodingOptionsBuilder::new().build();
         let mut reader = std::io::BufReader::new(std::fs::File::open(model_path).unwrap());
@@ -262,9 +333,73 @@ mod tests {
 
         let mut whisper = Whisper::load(&gg_disk, &mut reader, device).unwrap();
 
-        let transcript = transcribe(&mut whisper, samples, options).unwrap();
+        let mut progress = vec![];
+        let mut streamed = 0;
+        let hooks = TranscribeHooks::default()
+            .on_progress(|p| progress.push(p))
+            .on_segment(|_| streamed += 1);
+        let transcript = transcribe_with_hooks(&mut whisper, samples, options, hooks).unwrap();
         println!("{}", transcript.formatted.unwrap());
         println!("Processing time: {:?}", transcript.processing_time);
+        assert_eq!(progress.last(), Some(&1.0));
+        assert_eq!(streamed, transcript.segments.len() + 1);
+    }
+
+    #[test]
+    pub fn whisper_cancellation() {
+        log_init();
+        let api = Api::new().unwrap();
+        let model = api.model("FL33TW00D-HF/ratchet-whisper".to_string());
+        let model_path = model.get("tiny_q8.bin").unwrap();
+        let dataset = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
+        let samples = load_audio(dataset.get("mm0.wav").unwrap()).unwrap();
+
+        let mut reader = std::io::BufReader::new(std::fs::File::open(model_path).unwrap());
+        let gg_disk = Whisper::load_ggml(&mut reader).unwrap();
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let mut whisper = Whisper::load(&gg_disk, &mut reader, device).unwrap();
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let hooks = TranscribeHooks::default().cancel(cancel);
+        let options = DecodingOptionsBuilder::new()
+            .language("en".to_string())
+            .build();
+        let err = transcribe_with_hooks(&mut whisper, samples, options, hooks).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::Cancelled)
+        ));
+    }
+
+    #[test]
+    pub fn whisper_batch_matches_single() {
+        log_init();
+        let api = Api::new().unwrap();
+        let model = api.model("FL33TW00D-HF/ratchet-whisper".to_string());
+        let model_path = model.get("tiny_q8.bin").unwrap();
+        let dataset = api.dataset("FL33TW00D-HF/ratchet-util".to_string());
+        //Two English recordings, so that both windows are decoded in one batch
+        let jfk = load_audio(dataset.get("jfk.wav").unwrap()).unwrap();
+        let audios = vec![jfk[..5 * SAMPLE_RATE].to_vec(), jfk];
+
+        let mut reader = std::io::BufReader::new(std::fs::File::open(model_path).unwrap());
+        let gg_disk = Whisper::load_ggml(&mut reader).unwrap();
+        let device = Device::request_device(DeviceRequest::GPU).unwrap();
+        let mut whisper = Whisper::load(&gg_disk, &mut reader, device).unwrap();
+
+        //Both recordings fit in a single window, so prompting makes no difference.
+        //Decoding stays greedy, as a sampled fallback would differ between the runs
+        let options = DecodingOptionsBuilder::new()
+            .language("en".to_string())
+            .temperature_increment_on_fallback(0.)
+            .build();
+        let batched = transcribe_batch(&mut whisper, audios.clone(), options.clone(), 2).unwrap();
+        for (audio, batched) in audios.into_iter().zip(batched) {
+            let single = transcribe(&mut whisper, audio, options.clone()).unwrap();
+            assert_eq!(single.language, batched.language);
+            assert_eq!(single.formatted, batched.formatted);
+        }
     }
 
     #[test]
@@ -0,0 +1,465 @@
+//Adapted from: https://github.com/openai/whisper/blob/main/whisper/utils.py
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{TranscriptionResult, WhisperTokenizer, WordTiming};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Txt,
+    Vtt,
+    Srt,
+    Tsv,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Txt => "txt",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::Srt => "srt",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    pub fn writer(&self, options: WriterOptions) -> Box<dyn ResultWriter> {
+        match self {
+            OutputFormat::Txt => Box::new(TxtWriter),
+            OutputFormat::Vtt => Box::new(VttWriter::new(options)),
+            OutputFormat::Srt => Box::new(SrtWriter::new(options)),
+            OutputFormat::Tsv => Box::new(TsvWriter),
+            OutputFormat::Json => Box::new(JsonWriter),
+        }
+    }
+}
+
+/// Options controlling how subtitle cues are laid out.
+///
+/// `max_line_count` only takes effect when `max_line_width` is also set, mirroring OpenAI.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriterOptions {
+    pub max_line_width: Option<usize>,
+    pub max_line_count: Option<usize>,
+    pub highlight_words: bool,
+}
+
+/// A segment with its text decoded, the unit all writers operate on.
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedSegment {
+    pub id: usize,
+    pub start: f64,
+    #[serde(rename = "end")]
+    pub stop: f64,
+    pub text: String,
+    pub tokens: Vec<u32>,
+    pub avg_logprob: f32,
+    pub compression_ratio: f32,
+    pub no_speech_prob: f32,
+    pub words: Vec<WordTiming>,
+}
+
+impl DecodedSegment {
+    pub fn from_result(result: &TranscriptionResult, tokenizer: &WhisperTokenizer) -> Vec<Self> {
+        result
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(id, segment)| DecodedSegment {
+                id,
+                start: segment.start,
+                stop: segment.stop,
+                text: segment.text(tokenizer),
+                tokens: segment.tokens.clone(),
+                avg_logprob: segment.avg_logprob,
+                compression_ratio: segment.compression_ratio,
+                no_speech_prob: segment.no_speech_prob,
+                words: segment.words.clone(),
+            })
+            .collect()
+    }
+}
+
+pub trait ResultWriter {
+    fn write_segments(
+        &self,
+        segments: &[DecodedSegment],
+        out: &mut dyn Write,
+    ) -> std::io::Result<()>;
+
+    fn write_result(
+        &self,
+        result: &TranscriptionResult,
+        tokenizer: &WhisperTokenizer,
+        out: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        self.write_segments(&DecodedSegment::from_result(result, tokenizer), out)
+    }
+
+    fn to_string(&self, result: &TranscriptionResult, tokenizer: &WhisperTokenizer) -> String {
+        let mut out = Vec::new();
+        self.write_result(result, tokenizer, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+}
+
+pub struct TxtWriter;
+
+impl ResultWriter for TxtWriter {
+    fn write_segments(
+        &self,
+        segments: &[DecodedSegment],
+        out: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        for segment in segments {
+            writeln!(out, "{}", segment.text.trim())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct TsvWriter;
+
+impl ResultWriter for TsvWriter {
+    /// Start and end times are written as integer milliseconds, as this format is intended
+    /// for consumption by spreadsheets & scripts rather than humans.
+    fn write_segments(
+        &self,
+        segments: &[DecodedSegment],
+        out: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        writeln!(out, "start\tend\ttext")?;
+        for segment in segments {
+            writeln!(
+                out,
+                "{}\t{}\t{}",
+                (segment.start * 1000.0).round() as i64,
+                (segment.stop * 1000.0).round() as i64,
+                segment.text.trim().replace('\t', " ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct JsonWriter;
+
+#[derive(Serialize)]
+struct JsonTranscript<'a> {
+    text: String,
+    segments: &'a [DecodedSegment],
+}
+
+impl ResultWriter for JsonWriter {
+    fn write_segments(
+        &self,
+        segments: &[DecodedSegment],
+        out: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        let transcript = JsonTranscript {
+            text: segments.iter().map(|s| s.text.as_str()).collect(),
+            segments,
+        };
+        serde_json::to_writer(&mut *out, &transcript)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, derive_new::new)]
+pub struct VttWriter {
+    options: WriterOptions,
+}
+
+impl ResultWriter for VttWriter {
+    fn write_segments(
+        &self,
+        segments: &[DecodedSegment],
+        out: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        writeln!(out, "WEBVTT\n")?;
+        for cue in iterate_cues(segments, &self.options) {
+            writeln!(
+                out,
+                "{} --> {}\n{}\n",
+                TranscriptionResult::format_timestamp(cue.start, false, "."),
+                TranscriptionResult::format_timestamp(cue.stop, false, "."),
+                cue.text
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, derive_new::new)]
+pub struct SrtWriter {
+    options: WriterOptions,
+}
+
+impl ResultWriter for SrtWriter {
+    fn write_segments(
+        &self,
+        segments: &[DecodedSegment],
+        out: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        for (i, cue) in iterate_cues(segments, &self.options).iter().enumerate() {
+            writeln!(
+                out,
+                "{}\n{} --> {}\n{}\n",
+                i + 1,
+                TranscriptionResult::format_timestamp(cue.start, true, ","),
+                TranscriptionResult::format_timestamp(cue.stop, true, ","),
+                cue.text
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub stop: f64,
+    pub text: String,
+}
+
+/// Words for a segment, falling back to whitespace delimited words with interpolated timings
+/// when no word level timestamps are available.
+fn segment_words(segment: &DecodedSegment) -> Vec<WordTiming> {
+    if !segment.words.is_empty() {
+        return segment.words.clone();
+    }
+    // keep a leading space on each word, like Whisper's own tokens
+    let pieces = segment
+        .text
+        .split_whitespace()
+        .map(|word| format!(" {}", word))
+        .collect::<Vec<_>>();
+
+    let total_chars = pieces
+        .iter()
+        .map(|p| p.chars().count())
+        .sum::<usize>()
+        .max(1);
+    let duration = segment.stop - segment.start;
+    let mut start = segment.start;
+    pieces
+        .into_iter()
+        .map(|piece| {
+            let stop = start + duration * piece.chars().count() as f64 / total_chars as f64;
+            let word = WordTiming::new(piece, start, stop, 1.0);
+            start = stop;
+            word
+        })
+        .collect()
+}
+
+/// Groups words into subtitles that respect the line width & line count limits.
+fn iterate_subtitles(segments: &[DecodedSegment], options: &WriterOptions) -> Vec<Vec<WordTiming>> {
+    let preserve_segments = options.max_line_count.is_none() || options.max_line_width.is_none();
+    let max_line_width = options.max_line_width.unwrap_or(1000);
+
+    let mut subtitles = vec![];
+    let mut subtitle: Vec<WordTiming> = vec![];
+    let mut line_len = 0;
+    let mut line_count = 1;
+    let mut last = segments.first().map_or(0.0, |s| s.start);
+
+    for segment in segments {
+        for (i, original) in segment_words(segment).into_iter().enumerate() {
+            let mut timing = original;
+            let word_len = timing.word.chars().count();
+            let long_pause = !preserve_segments && timing.start - last > 3.0;
+            let has_room = line_len + word_len <= max_line_width;
+            let seg_break = i == 0 && !subtitle.is_empty() && preserve_segments;
+
+            if line_len > 0 && has_room && !long_pause && !seg_break {
+                line_len += word_len;
+            } else {
+                timing.word = timing.word.trim().to_string();
+                let line_limit_hit = options
+                    .max_line_count
+                    .is_some_and(|max_line_count| long_pause || line_count >= max_line_count);
+                if (!subtitle.is_empty() && line_limit_hit) || seg_break {
+                    subtitles.push(std::mem::take(&mut subtitle));
+                    line_count = 1;
+                } else if line_len > 0 {
+                    line_count += 1;
+                    timing.word = format!("\n{}", timing.word);
+                }
+                line_len = timing.word.trim().chars().count();
+            }
+            last = timing.start;
+            subtitle.push(timing);
+        }
+    }
+    if !subtitle.is_empty() {
+        subtitles.push(subtitle);
+    }
+    subtitles
+}
+
+/// Lays segments out as subtitle cues.
+///
+/// Segments are emitted verbatim unless line limits or word highlighting are requested,
+/// in which case the (possibly interpolated) word timings drive the layout.
+pub fn iterate_cues(segments: &[DecodedSegment], options: &WriterOptions) -> Vec<Cue> {
+    let has_words = segments.iter().any(|s| !s.words.is_empty());
+    let highlight_words = options.highlight_words && has_words;
+    if options.max_line_width.is_none() && !highlight_words {
+        return segments
+            .iter()
+            .map(|s| Cue {
+                start: s.start,
+                stop: s.stop,
+                text: s.text.trim().replace("-->", "->"),
+            })
+            .collect();
+    }
+
+    let mut cues = vec![];
+    for subtitle in iterate_subtitles(segments, options) {
+        let subtitle_start = subtitle[0].start;
+        let subtitle_stop = subtitle[subtitle.len() - 1].stop;
+        let subtitle_text = subtitle.iter().map(|w| w.word.as_str()).collect::<String>();
+        if !highlight_words {
+            cues.push(Cue {
+                start: subtitle_start,
+                stop: subtitle_stop,
+                text: subtitle_text.trim().replace("-->", "->"),
+            });
+            continue;
+        }
+
+        let mut last = subtitle_start;
+        for (i, this_word) in subtitle.iter().enumerate() {
+            if this_word.start > last {
+                cues.push(Cue {
+                    start: last,
+                    stop: this_word.start,
+                    text: subtitle_text.trim().replace("-->", "->"),
+                });
+            }
+            let highlighted = subtitle
+                .iter()
+                .enumerate()
+                .map(|(j, word)| {
+                    if i == j {
+                        let leading = &word.word[..word.word.len() - word.word.trim_start().len()];
+                        format!("{}<u>{}</u>", leading, word.word.trim_start())
+                    } else {
+                        word.word.clone()
+                    }
+                })
+                .collect::<String>();
+            cues.push(Cue {
+                start: this_word.start,
+                stop: this_word.stop,
+                text: highlighted.trim().replace("-->", "->"),
+            });
+            last = this_word.stop;
+        }
+    }
+    cues
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, stop: f64, text: &str) -> DecodedSegment {
+        DecodedSegment {
+            id: 0,
+            start,
+            stop,
+            text: text.to_string(),
+            tokens: vec![],
+            avg_logprob: 0.0,
+            compression_ratio: 0.0,
+            no_speech_prob: 0.0,
+            words: vec![],
+        }
+    }
+
+    fn render(writer: &dyn ResultWriter, segments: &[DecodedSegment]) -> String {
+        let mut out = Vec::new();
+        writer.write_segments(segments, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn srt_and_vtt_layout() {
+        let segments = vec![
+            segment(0.0, 2.5, " And so my fellow Americans"),
+            segment(2.5, 3661.25, " ask not --> what"),
+        ];
+        let srt = render(&SrtWriter::new(WriterOptions::default()), &segments);
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:02,500\nAnd so my fellow Americans\n\n\
+             2\n00:00:02,500 --> 01:01:01,250\nask not -> what\n\n"
+        );
+
+        let vtt = render(&VttWriter::new(WriterOptions::default()), &segments);
+        assert!(vtt.starts_with("WEBVTT\n\n00:00.000 --> 00:02.500\n"));
+        assert!(vtt.contains("00:02.500 --> 01:01:01.250\nask not -> what\n"));
+    }
+
+    #[test]
+    fn line_limits_split_cues() {
+        let segments = vec![segment(
+            0.0,
+            6.0,
+            " one two three four five six seven eight nine ten eleven twelve",
+        )];
+        let options = WriterOptions {
+            max_line_width: Some(15),
+            max_line_count: Some(2),
+            highlight_words: false,
+        };
+        let cues = iterate_cues(&segments, &options);
+        assert!(cues.len() > 1);
+        for cue in &cues {
+            let lines = cue.text.lines().collect::<Vec<_>>();
+            assert!(lines.len() <= 2, "too many lines: {:?}", lines);
+            assert!(lines.iter().all(|l| l.chars().count() <= 15));
+        }
+        let rejoined = cues
+            .iter()
+            .map(|c| c.text.replace('\n', " "))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(rejoined, segments[0].text.trim());
+        assert_eq!(cues[0].start, 0.0);
+        assert_eq!(cues[cues.len() - 1].stop, 6.0);
+    }
+
+    #[test]
+    fn highlight_words() {
+        let mut seg = segment(0.0, 1.0, " hello world");
+        seg.words = vec![
+            WordTiming::new(" hello".to_string(), 0.0, 0.4, 0.9),
+            WordTiming::new(" world".to_string(), 0.6, 1.0, 0.9),
+        ];
+        let options = WriterOptions {
+            highlight_words: true,
+            ..Default::default()
+        };
+        let texts = iterate_cues(&[seg], &options)
+            .into_iter()
+            .map(|c| c.text)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec!["<u>hello</u> world", "hello world", "hello <u>world</u>"]
+        );
+    }
+
+    #[test]
+    fn tsv_uses_milliseconds() {
+        let segments = vec![segment(1.5, 2.0, " tab\tseparated")];
+        let tsv = render(&TsvWriter, &segments);
+        assert_eq!(tsv, "start\tend\ttext\n1500\t2000\ttab separated\n");
+    }
+}
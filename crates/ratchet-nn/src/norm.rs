@@ -1,4 +1,4 @@
-use ratchet::Tensor;
+use ratchet::{shape, Tensor};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LayerNormConfig {
@@ -48,3 +48,39 @@ impl crate::Module for LayerNorm {
         input.layer_norm(&self.weight, self.bias.as_ref(), self.eps)
     }
 }
+
+/// Root mean square normalization, as used by Llama.
+///
+/// Computed from existing ops, the mean of the squares is a matmul with a `1 / D` vector.
+#[derive(Clone, Debug)]
+pub struct RMSNorm {
+    weight: Tensor,
+    eps: Tensor,
+    mean: Tensor,
+}
+
+impl RMSNorm {
+    pub fn new(weight: Tensor, eps: f32) -> Self {
+        let dim = weight.shape()[0];
+        let device = weight.device().clone();
+        let eps = Tensor::from_data([eps], shape![1], device.clone());
+        let mean = Tensor::from_data(vec![1. / dim as f32; dim], shape![1, dim], device);
+        Self { weight, eps, mean }
+    }
+
+    pub fn weight(&self) -> &Tensor {
+        &self.weight
+    }
+}
+
+impl crate::Module for RMSNorm {
+    type Input = Tensor;
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let rms = input
+            .mul(input)?
+            .matmul(&self.mean, true)?
+            .add(&self.eps)?
+            .sqrt()?;
+        input.div(&rms)?.mul(&self.weight)
+    }
+}
@@ -0,0 +1,163 @@
+use ratchet::{shape, Device, Tensor};
+
+/// Rotary position embedding (RoPE).
+///
+/// Pairs of features are rotated by an angle proportional to their position. With
+/// `interleaved` the pairs are adjacent features `(2i, 2i + 1)`, as in the original Llama &
+/// GGUF checkpoints. Otherwise they are the two halves of each head `(i, i + head_dim / 2)`,
+/// as in HuggingFace checkpoints.
+///
+/// Computed as `x * cos + partner(x) * sin`, where `partner` gathers the other feature of
+/// each pair & the sin table is negated for the first feature of each pair.
+#[derive(Clone, Debug)]
+pub struct RotaryEmbedding {
+    cos: Tensor,
+    sin: Tensor,
+    partners: Tensor,
+    head_dim: usize,
+    max_positions: usize,
+}
+
+/// Input to [RotaryEmbedding], `x` is `[batch, seq_len, n_heads, head_dim]` & `offset` the
+/// position of its first element.
+#[derive(Debug, derive_new::new)]
+pub struct RotaryInput {
+    pub x: Tensor,
+    pub offset: usize,
+}
+
+impl RotaryEmbedding {
+    pub fn new(
+        head_dim: usize,
+        max_positions: usize,
+        theta: f32,
+        interleaved: bool,
+        device: &Device,
+    ) -> Self {
+        let (cos, sin) = rotary_tables(head_dim, max_positions, theta, interleaved);
+        let partners = pair_partners(head_dim, interleaved);
+        let table_shape = shape![max_positions, head_dim];
+        Self {
+            cos: Tensor::from_data(cos, table_shape.clone(), device.clone()),
+            sin: Tensor::from_data(sin, table_shape, device.clone()),
+            partners: Tensor::from_data(partners, shape![head_dim], device.clone()),
+            head_dim,
+            max_positions,
+        }
+    }
+
+    pub fn max_positions(&self) -> usize {
+        self.max_positions
+    }
+}
+
+impl crate::Module for RotaryEmbedding {
+    type Input = RotaryInput;
+
+    fn forward(&self, input: &Self::Input) -> anyhow::Result<Tensor> {
+        let RotaryInput { x, offset } = input;
+        let seq_len = x.shape()[1];
+        anyhow::ensure!(
+            offset + seq_len <= self.max_positions,
+            "Position {} exceeds the {} supported by the rotary embedding",
+            offset + seq_len,
+            self.max_positions
+        );
+        let positions = *offset..offset + seq_len;
+        let table_shape = shape![seq_len, 1, self.head_dim];
+        let cos = self
+            .cos
+            .slice(&[positions.clone(), 0..self.head_dim])?
+            .view(table_shape.clone())?;
+        let sin = self
+            .sin
+            .slice(&[positions, 0..self.head_dim])?
+            .view(table_shape)?;
+        let rows = x.shape().numel() / self.head_dim;
+        let partners = x
+            .view(shape![rows, self.head_dim])?
+            .index_select(&self.partners, 1)?
+            .view(x.shape().clone())?;
+        x.mul(&cos)?.add(&partners.mul(&sin)?)
+    }
+}
+
+/// `[max_positions, head_dim]` cos & sin of the angle each feature is rotated by, the sin
+/// negated for the first feature of each pair.
+fn rotary_tables(
+    head_dim: usize,
+    max_positions: usize,
+    theta: f32,
+    interleaved: bool,
+) -> (Vec<f32>, Vec<f32>) {
+    let inv_freq = (0..head_dim / 2)
+        .map(|i| 1. / theta.powf(2. * i as f32 / head_dim as f32))
+        .collect::<Vec<_>>();
+    let mut cos = Vec::with_capacity(max_positions * head_dim);
+    let mut sin = Vec::with_capacity(max_positions * head_dim);
+    for pos in 0..max_positions {
+        for j in 0..head_dim {
+            let (pair, first) = match interleaved {
+                true => (j / 2, j % 2 == 0),
+                false => (j % (head_dim / 2), j < head_dim / 2),
+            };
+            let angle = pos as f32 * inv_freq[pair];
+            cos.push(angle.cos());
+            sin.push(if first { -angle.sin() } else { angle.sin() });
+        }
+    }
+    (cos, sin)
+}
+
+/// The index of the other feature in each feature's pair.
+fn pair_partners(head_dim: usize, interleaved: bool) -> Vec<i32> {
+    (0..head_dim)
+        .map(|j| match interleaved {
+            true => j ^ 1,
+            false => (j + head_dim / 2) % head_dim,
+        })
+        .map(|j| j as i32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the tables to `x` on the CPU, as the module does on device.
+    fn apply(x: &[f32], pos: usize, theta: f32, interleaved: bool) -> Vec<f32> {
+        let hd = x.len();
+        let (cos, sin) = rotary_tables(hd, pos + 1, theta, interleaved);
+        let partners = pair_partners(hd, interleaved);
+        (0..hd)
+            .map(|j| x[j] * cos[pos * hd + j] + x[partners[j] as usize] * sin[pos * hd + j])
+            .collect()
+    }
+
+    #[test]
+    fn rotates_feature_pairs() {
+        let x = [1., 2., 3., 4., 5., 6., 7., 8.];
+        let (pos, theta) = (3, 10000f32);
+        let angle = |i: usize| pos as f32 / theta.powf(2. * i as f32 / 8.);
+        let rotate = |a: f32, b: f32, i: usize| {
+            let (s, c) = angle(i).sin_cos();
+            (a * c - b * s, a * s + b * c)
+        };
+
+        let interleaved = apply(&x, pos, theta, true);
+        for i in 0..4 {
+            let (a, b) = rotate(x[2 * i], x[2 * i + 1], i);
+            assert!((interleaved[2 * i] - a).abs() < 1e-5);
+            assert!((interleaved[2 * i + 1] - b).abs() < 1e-5);
+        }
+
+        let halves = apply(&x, pos, theta, false);
+        for i in 0..4 {
+            let (a, b) = rotate(x[i], x[i + 4], i);
+            assert!((halves[i] - a).abs() < 1e-5);
+            assert!((halves[i + 4] - b).abs() < 1e-5);
+        }
+
+        assert_eq!(apply(&x, 0, theta, false), x);
+    }
+}
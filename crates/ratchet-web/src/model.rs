@@ -115,7 +115,7 @@ impl Model {
 mod tests {
     use super::*;
     use ratchet_hub::{ApiBuilder, RepoType};
-    use ratchet_models::DecodingOptionsBuilder;
+    use ratchet_models::{decode_audio, DecodingOptionsBuilder};
     use wasm_bindgen_test::*;
 
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
@@ -142,14 +142,6 @@ mod tests {
         }
     }
 
-    fn load_sample(bytes: &[u8]) -> Vec<f32> {
-        let mut reader = hound::WavReader::new(std::io::Cursor::new(bytes)).unwrap();
-        reader
-            .samples::<i16>()
-            .map(|x| x.unwrap() as f32 / 32768.0)
-            .collect::<Vec<_>>()
-    }
-
     #[wasm_bindgen_test]
     async fn browser_end_to_end() -> Result<(), JsValue> {
         log_init();
@@ -164,7 +156,7 @@ mod tests {
 
         let data_repo = ApiBuilder::from_hf("FL33TW00D-HF/ratchet-util", RepoType::Dataset).build();
         let audio_bytes = data_repo.get("jfk.wav").await?;
-        let sample = load_sample(&audio_bytes.to_vec());
+        let sample = decode_audio(audio_bytes.to_vec(), Some("wav")).unwrap();
 
         let decode_options = DecodingOptionsBuilder::default().build();
 
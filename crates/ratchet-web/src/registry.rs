@@ -10,6 +10,7 @@ pub enum AvailableModels {
     WHISPER_MEDIUM,
     WHISPER_LARGE_V2,
     WHISPER_LARGE_V3,
+    DISTIL_LARGE_V3,
 }
 
 impl AvailableModels {
@@ -27,6 +28,7 @@ impl AvailableModels {
             AvailableModels::WHISPER_MEDIUM => "medium",
             AvailableModels::WHISPER_LARGE_V2 => "large-v2",
             AvailableModels::WHISPER_LARGE_V3 => "large-v3",
+            AvailableModels::DISTIL_LARGE_V3 => "distil-large-v3",
         };
         match quantization {
             Quantization::Q8 => format!("{}_q8.bin", model_stem),